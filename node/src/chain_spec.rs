@@ -1,11 +1,19 @@
 use cumulus_primitives_core::ParaId;
-use parachain_runtime::{AccountId, Signature};
+use parachain_runtime::{AccountId, Balance, Moment, Signature};
 use sc_chain_spec::{ChainSpecExtension, ChainSpecGroup};
 use sc_service::ChainType;
 use serde::{Deserialize, Serialize};
 use sp_core::{sr25519, Pair, Public};
 use sp_runtime::traits::{IdentifyAccount, Verify};
 
+/// Parachain Id of the service chain in the local test network
+#[cfg(feature = "client")]
+const SERVICE_PARA_ID: u32 = 200;
+/// Collateral of the pre-registered devices
+const DEVICE_PENALTY: Balance = 1_000_000_000;
+/// Work circle duration of the pre-registered devices, ms
+const DEVICE_WCD: Moment = 3_600_000;
+
 /// Specialized `ChainSpec` for the normal parachain runtime.
 pub type ChainSpec = sc_service::GenericChainSpec<parachain_runtime::GenesisConfig, Extensions>;
 
@@ -59,6 +67,7 @@ pub fn development_config(id: ParaId) -> ChainSpec {
                     get_account_id_from_seed::<sr25519::Public>("Alice//stash"),
                     get_account_id_from_seed::<sr25519::Public>("Bob//stash"),
                 ],
+                vec![get_account_id_from_seed::<sr25519::Public>("Dave")],
                 id,
             )
         },
@@ -97,6 +106,10 @@ pub fn local_testnet_config(id: ParaId) -> ChainSpec {
                     get_account_id_from_seed::<sr25519::Public>("Eve//stash"),
                     get_account_id_from_seed::<sr25519::Public>("Ferdie//stash"),
                 ],
+                vec![
+                    get_account_id_from_seed::<sr25519::Public>("Dave"),
+                    get_account_id_from_seed::<sr25519::Public>("Eve"),
+                ],
                 id,
             )
         },
@@ -114,6 +127,7 @@ pub fn local_testnet_config(id: ParaId) -> ChainSpec {
 fn testnet_genesis(
     root_key: AccountId,
     endowed_accounts: Vec<AccountId>,
+    devices: Vec<AccountId>,
     id: ParaId,
 ) -> parachain_runtime::GenesisConfig {
     parachain_runtime::GenesisConfig {
@@ -126,11 +140,26 @@ fn testnet_genesis(
         pallet_balances: parachain_runtime::BalancesConfig {
             balances: endowed_accounts
                 .iter()
+                .chain(devices.iter().filter(|d| !endowed_accounts.contains(d)))
                 .cloned()
                 .map(|k| (k, 1_000_000_000_000_000_000))
                 .collect(),
         },
         pallet_sudo: parachain_runtime::SudoConfig { key: root_key },
         parachain_info: parachain_runtime::ParachainInfoConfig { parachain_id: id },
+        #[cfg(feature = "service")]
+        pallet_service: parachain_runtime::ServiceModuleConfig {
+            devices: devices
+                .into_iter()
+                .map(|d| (d, DEVICE_PENALTY, DEVICE_WCD, true))
+                .collect(),
+        },
+        #[cfg(feature = "client")]
+        pallet_client: parachain_runtime::ClientModuleConfig {
+            devices: devices
+                .into_iter()
+                .map(|d| (d, SERVICE_PARA_ID.into(), DEVICE_PENALTY, DEVICE_WCD, true))
+                .collect(),
+        },
     }
 }
@@ -10,6 +10,7 @@ mod chain_spec;
 mod service;
 mod cli;
 mod command;
+mod rpc;
 
 fn main() -> sc_cli::Result<()> {
     command::run()
@@ -0,0 +1,365 @@
+//! Robobank JSON-RPC endpoints backed by `RobobankApi`
+
+use std::{marker::PhantomData, sync::Arc};
+
+use codec::Decode;
+use frame_system::EventRecord;
+use futures::{compat::Compat, future, stream, StreamExt};
+use jsonrpc_core::{
+    futures::{Future as _, Sink as _},
+    Error as RpcError, ErrorCode, IoHandler, Result,
+};
+use jsonrpc_derive::rpc;
+use jsonrpc_pubsub::{manager::SubscriptionManager, typed::Subscriber, SubscriptionId};
+use parachain_runtime::{AccountId, Balance, Event, Moment, OrderPayload};
+use robobank_runtime_api::{
+    DeviceInfo, OrderId, OrderInfo, OrderQuote, OrderRequest, RobobankApi as RobobankRuntimeApi,
+};
+use sc_client_api::{Backend, BlockchainEvents, StorageProvider};
+use sc_rpc::{Metadata, SubscriptionTaskExecutor};
+use serde::Serialize;
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::storage::StorageKey;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+
+/// Storage key of `System::Events`
+const EVENTS_KEY: [u8; 32] =
+    hex_literal::hex!("26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7");
+
+/// Runtime API call failed
+const RUNTIME_ERROR: i64 = 1;
+
+/// Order lifecycle stage
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase", tag = "event")]
+pub enum OrderEventKind {
+    NewOrder,
+    Accept,
+    Reject,
+    Done,
+    Progress {
+        progress: u8,
+    },
+    /// Withdrawal of the order sent to the service parachain
+    CancelRequested,
+    /// Withdrawal refused, the order goes on
+    CancelRejected,
+    /// Order withdrawn or cancelled by the client
+    Cancelled,
+    /// Order refused by the paused service parachain
+    Failed,
+    /// Order amendment acknowledged by the device
+    Amended,
+}
+
+/// Order lifecycle event streamed to subscribers
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderEvent<Hash> {
+    /// Block the event was emitted in
+    pub block: Hash,
+    pub id: OrderId,
+    pub client: AccountId,
+    pub device: AccountId,
+    #[serde(flatten)]
+    pub kind: OrderEventKind,
+}
+
+#[rpc]
+pub trait RobobankApi<BlockHash> {
+    type Metadata;
+
+    /// Profile of the device
+    #[rpc(name = "robobank_getDevice")]
+    fn device(
+        &self,
+        device: AccountId,
+        at: Option<BlockHash>,
+    ) -> Result<Option<DeviceInfo<Balance, Moment>>>;
+
+    /// Order currently assigned to the device
+    #[rpc(name = "robobank_getOrder")]
+    fn order(
+        &self,
+        device: AccountId,
+        at: Option<BlockHash>,
+    ) -> Result<Option<OrderInfo<OrderPayload, Balance, Moment, AccountId>>>;
+
+    /// Devices ready to accept an order
+    #[rpc(name = "robobank_listReadyDevices")]
+    fn ready_devices(&self, at: Option<BlockHash>) -> Result<Vec<AccountId>>;
+
+    /// Reservations and fees of a prospective order
+    #[rpc(name = "robobank_quoteOrder")]
+    fn quote_order(
+        &self,
+        order: OrderRequest<OrderPayload, Balance, Moment, AccountId>,
+        at: Option<BlockHash>,
+    ) -> Result<Option<OrderQuote<Balance, Moment>>>;
+
+    /// Streams lifecycle events of orders the account takes part in, as device or as client
+    #[pubsub(
+        subscription = "robobank_orderEvents",
+        subscribe,
+        name = "robobank_subscribeOrderEvents"
+    )]
+    fn subscribe_order_events(
+        &self,
+        metadata: Self::Metadata,
+        subscriber: Subscriber<OrderEvent<BlockHash>>,
+        account: AccountId,
+    );
+
+    #[pubsub(
+        subscription = "robobank_orderEvents",
+        unsubscribe,
+        name = "robobank_unsubscribeOrderEvents"
+    )]
+    fn unsubscribe_order_events(
+        &self,
+        metadata: Option<Self::Metadata>,
+        id: SubscriptionId,
+    ) -> Result<bool>;
+}
+
+/// Implements `RobobankApi` on top of the runtime API
+pub struct Robobank<C, Block, BE> {
+    client: Arc<C>,
+    subscriptions: SubscriptionManager,
+    _marker: PhantomData<(Block, BE)>,
+}
+
+impl<C, Block, BE> Robobank<C, Block, BE> {
+    pub fn new(client: Arc<C>, executor: SubscriptionTaskExecutor) -> Self {
+        Self {
+            client,
+            subscriptions: SubscriptionManager::new(Arc::new(executor)),
+            _marker: Default::default(),
+        }
+    }
+}
+
+fn runtime_error(e: impl std::fmt::Debug) -> RpcError {
+    RpcError {
+        code: ErrorCode::ServerError(RUNTIME_ERROR),
+        message: "Runtime API call failed".into(),
+        data: Some(format!("{:?}", e).into()),
+    }
+}
+
+/// Order lifecycle stage of the runtime event
+#[cfg(feature = "service")]
+fn order_event(event: Event) -> Option<(OrderId, AccountId, AccountId, OrderEventKind)> {
+    use parachain_runtime::pallet_service::Event as E;
+
+    let event = match event {
+        Event::pallet_service(event) => event,
+        _ => return None,
+    };
+    match event {
+        E::NewOrder(id, client, device, ..) => Some((id, client, device, OrderEventKind::NewOrder)),
+        E::Accept(id, client, device, ..) => Some((id, client, device, OrderEventKind::Accept)),
+        E::Reject(id, client, device, ..) => Some((id, client, device, OrderEventKind::Reject)),
+        E::Done(id, client, device, ..) => Some((id, client, device, OrderEventKind::Done)),
+        E::Progress(id, client, device, _, _, progress) => {
+            Some((id, client, device, OrderEventKind::Progress { progress }))
+        }
+        E::CancelRejected(id, client, device, ..) => {
+            Some((id, client, device, OrderEventKind::CancelRejected))
+        }
+        E::Cancelled(id, client, device, ..) => {
+            Some((id, client, device, OrderEventKind::Cancelled))
+        }
+        E::OrderFailed(id, client, device, ..) => {
+            Some((id, client, device, OrderEventKind::Failed))
+        }
+        E::Amended(id, client, device, ..) => Some((id, client, device, OrderEventKind::Amended)),
+        _ => None,
+    }
+}
+
+/// Order lifecycle stage of the runtime event
+#[cfg(feature = "client")]
+fn order_event(event: Event) -> Option<(OrderId, AccountId, AccountId, OrderEventKind)> {
+    use parachain_runtime::pallet_client::Event as E;
+
+    let event = match event {
+        Event::pallet_client(event) => event,
+        _ => return None,
+    };
+    match event {
+        E::NewOrder(id, client, device, ..) => Some((id, client, device, OrderEventKind::NewOrder)),
+        E::Accept(id, client, device, ..) => Some((id, client, device, OrderEventKind::Accept)),
+        E::Reject(id, client, device, ..) => Some((id, client, device, OrderEventKind::Reject)),
+        E::Done(id, client, device, ..) => Some((id, client, device, OrderEventKind::Done)),
+        E::Progress(id, client, device, _, _, progress) => {
+            Some((id, client, device, OrderEventKind::Progress { progress }))
+        }
+        E::CancelRequested(id, client, device, ..) => {
+            Some((id, client, device, OrderEventKind::CancelRequested))
+        }
+        E::CancelRejected(id, client, device, ..) => {
+            Some((id, client, device, OrderEventKind::CancelRejected))
+        }
+        E::Cancelled(id, client, device, ..) => {
+            Some((id, client, device, OrderEventKind::Cancelled))
+        }
+        E::Failed(id, client, device, ..) => Some((id, client, device, OrderEventKind::Failed)),
+        E::Amended(id, client, device, ..) => Some((id, client, device, OrderEventKind::Amended)),
+        _ => None,
+    }
+}
+
+impl<C, Block, BE> Robobank<C, Block, BE>
+where
+    Block: BlockT,
+    BE: Backend<Block> + 'static,
+    C: StorageProvider<Block, BE> + Send + Sync + 'static,
+{
+    /// Order lifecycle events of the block involving the account
+    fn order_events(
+        client: &C,
+        hash: Block::Hash,
+        account: &AccountId,
+    ) -> Vec<OrderEvent<Block::Hash>> {
+        let records = client
+            .storage(&BlockId::Hash(hash), &StorageKey(EVENTS_KEY.to_vec()))
+            .ok()
+            .flatten()
+            .and_then(|data| {
+                Vec::<EventRecord<Event, Block::Hash>>::decode(&mut data.0.as_slice()).ok()
+            })
+            .unwrap_or_default();
+
+        records
+            .into_iter()
+            .filter_map(|record| order_event(record.event))
+            .filter(|(_, client, device, _)| device == account || client == account)
+            .map(|(id, client, device, kind)| OrderEvent {
+                block: hash,
+                id,
+                client,
+                device,
+                kind,
+            })
+            .collect()
+    }
+}
+
+impl<C, Block, BE> RobobankApi<Block::Hash> for Robobank<C, Block, BE>
+where
+    Block: BlockT,
+    BE: Backend<Block> + 'static,
+    C: ProvideRuntimeApi<Block>
+        + HeaderBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>
+        + Send
+        + Sync
+        + 'static,
+    C::Api: RobobankRuntimeApi<Block, AccountId, Balance, Moment, OrderPayload>,
+{
+    type Metadata = Metadata;
+
+    fn device(
+        &self,
+        device: AccountId,
+        at: Option<Block::Hash>,
+    ) -> Result<Option<DeviceInfo<Balance, Moment>>> {
+        let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+        self.client
+            .runtime_api()
+            .device(&at, device)
+            .map_err(runtime_error)
+    }
+
+    fn order(
+        &self,
+        device: AccountId,
+        at: Option<Block::Hash>,
+    ) -> Result<Option<OrderInfo<OrderPayload, Balance, Moment, AccountId>>> {
+        let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+        self.client
+            .runtime_api()
+            .order(&at, device)
+            .map_err(runtime_error)
+    }
+
+    fn ready_devices(&self, at: Option<Block::Hash>) -> Result<Vec<AccountId>> {
+        let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+        self.client
+            .runtime_api()
+            .ready_devices(&at)
+            .map_err(runtime_error)
+    }
+
+    fn quote_order(
+        &self,
+        order: OrderRequest<OrderPayload, Balance, Moment, AccountId>,
+        at: Option<Block::Hash>,
+    ) -> Result<Option<OrderQuote<Balance, Moment>>> {
+        let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+        self.client
+            .runtime_api()
+            .quote_order(&at, order)
+            .map_err(runtime_error)
+    }
+
+    fn subscribe_order_events(
+        &self,
+        _metadata: Self::Metadata,
+        subscriber: Subscriber<OrderEvent<Block::Hash>>,
+        account: AccountId,
+    ) {
+        let client = self.client.clone();
+        let events = self
+            .client
+            .import_notification_stream()
+            .filter(|notification| future::ready(notification.is_new_best))
+            .flat_map(move |notification| {
+                stream::iter(Self::order_events(&*client, notification.hash, &account))
+            })
+            .map(|event| Ok::<_, ()>(Ok(event)))
+            .boxed();
+
+        self.subscriptions.add(subscriber, |sink| {
+            sink.sink_map_err(|e| log::debug!("Order events sink failed: {:?}", e))
+                .send_all(Compat::new(events))
+                .map(|_| ())
+        });
+    }
+
+    fn unsubscribe_order_events(
+        &self,
+        _metadata: Option<Self::Metadata>,
+        id: SubscriptionId,
+    ) -> Result<bool> {
+        Ok(self.subscriptions.cancel(id))
+    }
+}
+
+/// Instantiates all RPC extensions of the collator
+pub fn create_full<C, Block, BE>(
+    client: Arc<C>,
+    subscription_executor: SubscriptionTaskExecutor,
+) -> IoHandler<Metadata>
+where
+    Block: BlockT,
+    BE: Backend<Block> + 'static,
+    C: ProvideRuntimeApi<Block>
+        + HeaderBackend<Block>
+        + BlockchainEvents<Block>
+        + StorageProvider<Block, BE>
+        + Send
+        + Sync
+        + 'static,
+    C::Api: RobobankRuntimeApi<Block, AccountId, Balance, Moment, OrderPayload>,
+{
+    let mut io = IoHandler::default();
+    io.extend_with(RobobankApi::to_delegate(Robobank::new(
+        client,
+        subscription_executor,
+    )));
+    io
+}
@@ -132,6 +132,7 @@ async fn start_node_impl<RB>(
 where
     RB: Fn(
             Arc<TFullClient<Block, RuntimeApi, Executor>>,
+            sc_rpc::SubscriptionTaskExecutor,
         ) -> jsonrpc_core::IoHandler<sc_rpc::Metadata>
         + Send
         + 'static,
@@ -184,7 +185,9 @@ where
         })?;
 
     let rpc_client = client.clone();
-    let rpc_extensions_builder = Box::new(move |_, _| rpc_ext_builder(rpc_client.clone()));
+    let rpc_extensions_builder = Box::new(move |_, subscription_executor| {
+        rpc_ext_builder(rpc_client.clone(), subscription_executor)
+    });
 
     sc_service::spawn_tasks(sc_service::SpawnTasksParams {
         on_demand: None,
@@ -271,7 +274,7 @@ pub async fn start_node(
         polkadot_config,
         id,
         validator,
-        |_| Default::default(),
+        crate::rpc::create_full,
     )
     .await
 }
@@ -0,0 +1,439 @@
+//! Benchmarks of the client pallet
+use super::*;
+
+use frame_benchmarking::{account, benchmarks, impl_benchmark_test_suite};
+use frame_support::sp_runtime::traits::Bounded;
+use frame_system::RawOrigin;
+
+const SEED: u32 = 0;
+const WCD: u32 = 1_000;
+const UNTIL: u32 = 1_000_000;
+const SERVICE: u32 = 200;
+const MAX_PAYLOAD: u32 = 1_024;
+
+fn funded<T: Config>(name: &'static str) -> T::AccountId {
+    let who: T::AccountId = account(name, 0, SEED);
+    T::Currency::make_free_balance_be(&who, BalanceOf::<T>::max_value() / 4u32.into());
+    who
+}
+
+fn device<T: Config>(state: DeviceState) -> T::AccountId {
+    let device = funded::<T>("device");
+    Device::<T>::insert(
+        &device,
+        DeviceProfile {
+            state,
+            penalty: 1_000u32.into(),
+            wcd: WCD.into(),
+            paraid: SERVICE.into(),
+        },
+    );
+    DeviceParas::<T>::insert(&device, ParaId::from(SERVICE));
+    ProtocolVersions::<T>::insert(ParaId::from(SERVICE), PROTOCOL_VERSION);
+    device
+}
+
+/// Payload of `s` bytes, fixed size payloads don't depend on it
+fn payload<T: Config>(s: u32) -> T::OrderPayload {
+    T::PayloadOfSize::convert(s)
+}
+
+/// Places an order straight into storage, funds are reserved
+fn place_order<T: Config>(device: &T::AccountId, client: &T::AccountId, state: DeviceState) {
+    let fee: BalanceOf<T> = 1_000u32.into();
+
+    T::Currency::reserve(device, 1_000u32.into()).unwrap();
+    T::Currency::reserve(client, fee).unwrap();
+    Orders::<T>::insert(
+        device,
+        OrderOf::<T> {
+            id: 0,
+            until: UNTIL.into(),
+            until_relay: None,
+            data: Default::default(),
+            fee,
+            client: client.clone(),
+            paraid: SERVICE.into(),
+        },
+    );
+    Device::<T>::mutate(device, |dev| {
+        if let Some(dev) = dev {
+            dev.state = state
+        }
+    });
+}
+
+/// Access policy listing the most clients
+fn policy<T: Config>() -> AccessPolicy<u32, T::AccountId> {
+    let entries = (0..T::MaxPolicyEntries::get())
+        .map(|i| (T::SelfParaId::get().into(), account("client", i, SEED)))
+        .collect();
+    AccessPolicy::Allow(entries)
+}
+
+fn handle<T: Config>(msg: XCMPMessageOf<T>) {
+    <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SERVICE.into(), 0, msg.encode());
+}
+
+benchmarks! {
+    test {
+        let caller = funded::<T>("caller");
+    }: _(RawOrigin::Signed(caller))
+
+    order {
+        let s in 0 .. MAX_PAYLOAD;
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        let order = OrderBaseOf::<T> {
+            until: UNTIL.into(),
+            until_relay: None,
+            data: payload::<T>(s),
+            fee: 1_000u32.into(),
+            device: device.clone(),
+        };
+    }: _(RawOrigin::Signed(client), order)
+    verify {
+        assert!(Orders::<T>::contains_key(&device));
+    }
+
+    cancel {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        Timestamp::<T>::set_timestamp((UNTIL + 1).into());
+    }: _(RawOrigin::Signed(client), device.clone())
+    verify {
+        assert!(Cancelled::<T>::contains_key(&device));
+    }
+
+    amend_order {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        let amendment = AmendmentOf::<T> {
+            until: (UNTIL * 2).into(),
+            until_relay: None,
+            fee: 2_000u32.into(),
+        };
+    }: _(RawOrigin::Signed(client), device.clone(), amendment)
+    verify {
+        assert!(Amendments::<T>::contains_key(&device));
+    }
+
+    on_device_sync {
+        let device = device::<T>(DeviceState::Ready);
+        Subscriptions::<T>::insert(ParaId::from(SERVICE), Subscription { next: 0, syncing: false });
+        let msg = XCMPMessageOf::<T>::DeviceUpdate(0, device.clone(), 2_000u32.into(), WCD.into());
+    }: { handle::<T>(msg) }
+    verify {
+        assert_eq!(Device::<T>::get(&device).unwrap().penalty, 2_000u32.into());
+    }
+
+    on_device_policy {
+        let n in 0 .. T::MaxPolicyEntries::get();
+        let device = device::<T>(DeviceState::Ready);
+        Subscriptions::<T>::insert(ParaId::from(SERVICE), Subscription { next: 0, syncing: false });
+        let entries = (0..n)
+            .map(|i| (T::SelfParaId::get().into(), account("client", i, SEED)))
+            .collect();
+        let msg = XCMPMessageOf::<T>::DevicePolicy(0, device.clone(), AccessPolicy::Allow(entries));
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(Policies::<T>::contains_key(&device));
+    }
+
+    on_device_snapshot {
+        let n in 0 .. 100;
+        ProtocolVersions::<T>::insert(ParaId::from(SERVICE), PROTOCOL_VERSION);
+        Subscriptions::<T>::insert(ParaId::from(SERVICE), Subscription { next: 0, syncing: true });
+        let devices = (0..n)
+            .map(|i| {
+                let device: T::AccountId = account("device", i, SEED);
+                DeviceParas::<T>::insert(&device, ParaId::from(SERVICE));
+                (device, 1_000u32.into(), WCD.into(), DeviceState::Ready, policy::<T>())
+            })
+            .collect();
+        let msg = XCMPMessageOf::<T>::DeviceSnapshot(n.into(), devices);
+    }: { handle::<T>(msg) }
+    verify {
+        assert_eq!(
+            Subscriptions::<T>::get(ParaId::from(SERVICE)),
+            Some(Subscription { next: n.into(), syncing: false })
+        );
+    }
+
+    subscribe {
+        ProtocolVersions::<T>::insert(ParaId::from(SERVICE), PROTOCOL_VERSION);
+    }: _(RawOrigin::Root, SERVICE.into())
+    verify {
+        assert!(Subscriptions::<T>::contains_key(ParaId::from(SERVICE)));
+    }
+
+    unsubscribe {
+        ProtocolVersions::<T>::insert(ParaId::from(SERVICE), PROTOCOL_VERSION);
+        Subscriptions::<T>::insert(ParaId::from(SERVICE), Subscription { next: 1, syncing: false });
+    }: _(RawOrigin::Root, SERVICE.into())
+    verify {
+        assert!(!Subscriptions::<T>::contains_key(ParaId::from(SERVICE)));
+    }
+
+    bind_device {
+        let device = device::<T>(DeviceState::Ready);
+        Policies::<T>::insert(&device, AccessPolicyOf::<T>::Allow(Vec::new()));
+        let paraid = ParaId::from(SERVICE + 1);
+    }: _(RawOrigin::Root, device.clone(), paraid)
+    verify {
+        assert_eq!(DeviceParas::<T>::get(&device), Some(paraid));
+        assert!(!Device::<T>::contains_key(&device));
+    }
+
+    on_accept {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        let msg = XCMPMessageOf::<T>::OrderAccept(0, client, device);
+    }: { handle::<T>(msg) }
+
+    // Overdue rejection slashes the penalty, the heaviest path
+    on_reject {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        Timestamp::<T>::set_timestamp((UNTIL + 1).into());
+        let msg = XCMPMessageOf::<T>::OrderReject(0, client, device.clone(), true);
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(!Orders::<T>::contains_key(&device));
+    }
+
+    // Overdue completion slashes the penalty, the heaviest path
+    on_done {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        Timestamp::<T>::set_timestamp((UNTIL + 1).into());
+        let msg = XCMPMessageOf::<T>::OrderDone(0, client, device.clone(), true);
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(!Orders::<T>::contains_key(&device));
+    }
+
+    on_progress {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        let telemetry = Telemetry {
+            progress: MAX_PROGRESS,
+            position: Some((1, 2, 3)),
+            digest: [0xff; 32],
+        };
+        let msg = XCMPMessageOf::<T>::OrderProgress(0, client, device.clone(), telemetry);
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(Progress::<T>::contains_key(&device));
+    }
+
+    on_amend_accept {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        let amendment = AmendmentOf::<T> {
+            until: (UNTIL * 2).into(),
+            until_relay: None,
+            fee: 2_000u32.into(),
+        };
+        T::Currency::reserve(&client, 1_000u32.into()).unwrap();
+        Amendments::<T>::insert(&device, amendment);
+        let msg = XCMPMessageOf::<T>::OrderAmendAccept(0, client, device.clone());
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(!Amendments::<T>::contains_key(&device));
+    }
+
+    set_pause {
+        let origin = T::PauseOrigin::successful_origin();
+        let target = PauseTarget::Para(SERVICE.into());
+    }: {
+        Pallet::<T>::set_pause(origin, target, true)?;
+    }
+    verify {
+        assert!(Pauses::<T>::get(target));
+    }
+
+    on_queue_message {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        Pauses::<T>::insert(PauseTarget::Kind(MessageKind::OrderAccept), true);
+        let msg = XCMPMessageOf::<T>::OrderAccept(0, client, device);
+    }: { handle::<T>(msg) }
+    verify {
+        assert_eq!(PausedMessagesBounds::<T>::get(), (0, 1));
+    }
+
+    on_failed {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        let msg = XCMPMessageOf::<T>::OrderFailed(0, client, device.clone());
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(!Orders::<T>::contains_key(&device));
+    }
+
+    on_cancel_rejected {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        Cancelled::<T>::insert(&device, (0, Timestamp::<T>::get()));
+        let msg = XCMPMessageOf::<T>::OrderCancelRejected(0, client, device.clone());
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(!Cancelled::<T>::contains_key(&device));
+    }
+
+    on_cancelled {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        Cancelled::<T>::insert(&device, (0, Timestamp::<T>::get()));
+        let msg = XCMPMessageOf::<T>::OrderCancelled(0, client, device.clone());
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(!Orders::<T>::contains_key(&device));
+    }
+
+    on_amend_reject {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        let amendment = AmendmentOf::<T> {
+            until: (UNTIL * 2).into(),
+            until_relay: None,
+            fee: 2_000u32.into(),
+        };
+        T::Currency::reserve(&client, 1_000u32.into()).unwrap();
+        Amendments::<T>::insert(&device, amendment);
+        let msg = XCMPMessageOf::<T>::OrderAmendReject(0, client, device.clone());
+    }: { handle::<T>(msg) }
+    verify {
+        assert!(!Amendments::<T>::contains_key(&device));
+    }
+
+    handshake {
+    }: _(RawOrigin::Root, SERVICE.into())
+
+    on_handshake {
+        let blob = XCMPMessageOf::<T>::Handshake(PROTOCOL_VERSION).to_wire(PROTOCOL_VERSION, None);
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SERVICE.into(), 0, blob);
+    }
+    verify {
+        assert_eq!(ProtocolVersions::<T>::get(ParaId::from(SERVICE)), Some(PROTOCOL_VERSION));
+    }
+
+    retry_message {
+        let dest = ParaId::from(SERVICE);
+        let blob = XCMPMessageOf::<T>::Handshake(PROTOCOL_VERSION).to_wire(PROTOCOL_VERSION, None);
+        let now = frame_system::Pallet::<T>::block_number();
+        let pending = OutboundMessage {
+            blob,
+            kind: MessageKind::Handshake,
+            attempts: 1,
+            next_attempt: now,
+        };
+        Outbox::<T>::insert(dest, 0, pending);
+        OutboxBounds::<T>::insert(dest, (0, 1));
+        OutboxDestinations::<T>::put(vec![dest]);
+    }: {
+        Pallet::<T>::retry_outbox(now, Weight::max_value());
+    }
+    verify {
+        assert!(Outbox::<T>::get(dest, 0).map_or(true, |m| m.attempts == 2));
+    }
+
+    on_ack {
+        let dest = ParaId::from(SERVICE);
+        let pending = PendingAck {
+            blob: vec![0; 64],
+            kind: MessageKind::OrderDone,
+            sent_at: frame_system::Pallet::<T>::block_number(),
+            resends: 0,
+        };
+        Pallet::<T>::track_unacked(dest, 0, pending);
+        let blob = XCMPMessageOf::<T>::Ack(0).to_wire(PROTOCOL_VERSION, None);
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SERVICE.into(), 0, blob);
+    }
+    verify {
+        assert!(Unacked::<T>::get(dest, 0).is_none());
+    }
+
+    resend_message {
+        let dest = ParaId::from(SERVICE);
+        let blob = XCMPMessageOf::<T>::OrderCancel(0, funded::<T>("client"), funded::<T>("device"))
+            .to_wire(PROTOCOL_VERSION, Some(0));
+        let pending = PendingAck {
+            blob,
+            kind: MessageKind::OrderCancel,
+            sent_at: frame_system::Pallet::<T>::block_number(),
+            resends: 0,
+        };
+        Pallet::<T>::track_unacked(dest, 0, pending);
+        let now = frame_system::Pallet::<T>::block_number() + T::AckTimeout::get();
+    }: {
+        Pallet::<T>::resend_unacked(now, Weight::max_value());
+    }
+    verify {
+        assert_eq!(Unacked::<T>::get(dest, 0).map(|m| m.resends), Some(1));
+    }
+
+    on_duplicate {
+        let dest = ParaId::from(SERVICE);
+        let window = SeqWindow {
+            watermark: 1,
+            seen: 0,
+        };
+        InboundSequence::<T>::insert(dest, window);
+        let blob = XCMPMessageOf::<T>::OrderCancel(0, funded::<T>("client"), funded::<T>("device"))
+            .to_wire(PROTOCOL_VERSION, Some(0));
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SERVICE.into(), 0, blob);
+    }
+    verify {
+        assert_eq!(InboundSequence::<T>::get(dest).watermark, 1);
+    }
+
+    set_settlement {
+        ProtocolVersions::<T>::insert(ParaId::from(SERVICE), PROTOCOL_VERSION);
+    }: _(RawOrigin::Root, SERVICE.into(), SettlementMode::Transfer)
+    verify {
+        assert_eq!(SettlementModes::<T>::get(ParaId::from(SERVICE)), SettlementMode::Transfer);
+    }
+
+    settle_epoch {
+        let n in 1 .. 100;
+        let fee: BalanceOf<T> = 1_000u32.into();
+        T::Currency::make_free_balance_be(&Pallet::<T>::pot(), fee * (n + 1).into());
+        ProtocolVersions::<T>::insert(ParaId::from(SERVICE), PROTOCOL_VERSION);
+        for i in 0 .. n {
+            let device: T::AccountId = account("device", i, SEED);
+            let entry = LedgerEntryOf::<T> { fees: fee, penalties: Zero::zero() };
+            Ledgers::<T>::insert(ParaId::from(SERVICE), device, entry);
+        }
+        PendingSettlements::<T>::put(vec![ParaId::from(SERVICE)]);
+    }: {
+        Pallet::<T>::settle_pending(1u32.into(), Weight::max_value());
+    }
+    verify {
+        assert!(Settlements::<T>::contains_key(ParaId::from(SERVICE), 0));
+    }
+
+    on_bad_message {
+        let blob = vec![0xff; 64];
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SERVICE.into(), 0, blob);
+    }
+}
+
+impl_benchmark_test_suite!(Pallet, crate::mock::new_test_ext(), crate::mock::Test);
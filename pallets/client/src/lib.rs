@@ -4,14 +4,20 @@
 
 use codec::{Decode, Encode};
 use frame_support::{
-    dispatch::DispatchResult,
-    sp_runtime::traits::Hash,
-    sp_runtime::RuntimeDebug,
-    traits::{BalanceStatus::Free, Currency, Get, ReservableCurrency},
+    dispatch::{DispatchError, DispatchResult},
+    sp_runtime::traits::{AccountIdConversion, Convert, Hash, SaturatedConversion, Zero},
+    sp_runtime::{ModuleId, RuntimeDebug},
+    traits::{BalanceStatus::Free, Currency, ExistenceRequirement, Get, ReservableCurrency},
+    transactional,
+    weights::{DispatchClass, Weight},
 };
 pub use pallet::*;
 use sp_std::prelude::*;
-use xcm::v0::{Junction, OriginKind, SendXcm, Xcm};
+use xcm::v0::{
+    Error as XcmError, Junction, MultiAsset, MultiLocation, Order as XcmOrder, OriginKind, SendXcm,
+    Xcm,
+};
+use xcm_executor::traits::LocationConversion;
 
 use sp_std::convert::{TryFrom, TryInto};
 
@@ -23,6 +29,7 @@ use cumulus_primitives_core::{
     ServiceQuality, UpwardMessage, UpwardMessageSender, XcmpMessageHandler, XcmpMessageSender,
 };
 use frame_support::traits::OnKilledAccount;
+pub use pallet_common::FixedXcmWeight;
 use pallet_common::*;
 use xcm::VersionedXcm;
 
@@ -33,11 +40,17 @@ type XCMPMessageOf<T> = XCMPMessage<
     <T as pallet_timestamp::Config>::Moment,
 >;
 
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking;
+pub mod migrations;
 #[cfg(test)]
 mod mock;
 
 #[cfg(test)]
 mod tests;
+pub mod weights;
+
+pub use weights::WeightInfo;
 
 #[cfg_attr(feature = "std", derive(PartialEq, Debug))]
 #[derive(Encode, Decode, Default)]
@@ -51,14 +64,25 @@ pub struct DeviceProfile<T: Config> {
     /// Parachain Id
     paraid: ParaId,
 }
-pub(crate) type OrderBaseOf<T> = OrderBase<
+
+/// Device registry subscription to a service parachain
+#[cfg_attr(feature = "std", derive(PartialEq, Debug))]
+#[derive(Encode, Decode, Default, Clone)]
+pub struct Subscription {
+    /// Sequence number of the next expected registry change
+    pub next: u64,
+    /// Snapshot requested, changes are skipped until it arrives
+    pub syncing: bool,
+}
+
+pub type OrderBaseOf<T> = OrderBase<
     <T as Config>::OrderPayload,
     BalanceOf<T>,
     MomentOf<T>,
     <T as frame_system::Config>::AccountId,
 >;
 
-pub(crate) type OrderOf<T> = Order<
+pub type OrderOf<T> = Order<
     <T as Config>::OrderPayload,
     BalanceOf<T>,
     MomentOf<T>,
@@ -69,33 +93,94 @@ pub(crate) type OrderOf<T> = Order<
 pub type BalanceOf<T> =
     <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
 pub type MomentOf<T> = <T as pallet_timestamp::Config>::Moment;
+pub type SettlementOf<T> = Settlement<BalanceOf<T>>;
+pub type AmendmentOf<T> = Amendment<BalanceOf<T>, MomentOf<T>>;
+pub type AccessPolicyOf<T> = AccessPolicy<ParaId, <T as frame_system::Config>::AccountId>;
+pub type LedgerEntryOf<T> = LedgerEntry<BalanceOf<T>>;
+pub type SettlementRecordOf<T> =
+    SettlementRecord<BalanceOf<T>, <T as frame_system::Config>::BlockNumber>;
 type Timestamp<T> = pallet_timestamp::Pallet<T>;
 
 #[frame_support::pallet]
 pub mod pallet {
     #![allow(clippy::unused_unit)]
     use super::{
-        BalanceOf, DeviceProfile, DeviceState, Junction, MomentOf, OrderBaseOf, OrderOf,
-        OriginKind, ParaId, ReservableCurrency, SendXcm, ServiceQuality, Timestamp, Xcm,
-        XcmpMessageSender,
+        AccessPolicyOf, AmendmentOf, BalanceOf, DeviceProfile, DeviceState, EpochIndex, Junction,
+        LedgerEntryOf, MessageKind, Messaging, ModuleId, MomentOf, MultiLocation, Nonce,
+        OrderBaseOf, OrderId, OrderOf, OriginKind, OutboundMessage, ParaId, PauseTarget,
+        PendingAck, ProtocolVersion, RelayBlockNumber, Releases, ReservableCurrency, SendXcm,
+        SeqWindow, ServiceQuality, SettlementMode, SettlementOf, SettlementRecordOf, Subscription,
+        Telemetry, Timestamp, WeighXcm, WeightInfo, Xcm, XcmError, XcmpMessageSender,
+        PROTOCOL_VERSION,
     };
     use crate::XCMPMessageOf;
     use frame_support::dispatch::{Dispatchable, PostDispatchInfo};
-    use frame_support::{dispatch::DispatchResultWithPostInfo, pallet_prelude::*};
+    use frame_support::sp_runtime::traits::{Convert, UniqueSaturatedFrom, Zero};
+    use frame_support::traits::EnsureOrigin;
+    use frame_support::{dispatch::DispatchResultWithPostInfo, pallet_prelude::*, transactional};
     use frame_system::pallet_prelude::*;
     use sp_std::prelude::*;
-    use xcm_executor::traits::ConvertOrigin;
+    use xcm::v0::ExecuteXcm;
+    use xcm_executor::traits::{ConvertOrigin, LocationConversion};
 
     /// Configure the pallet by specifying the parameters and types on which it depends.
     #[pallet::config]
     pub trait Config: frame_system::Config + pallet_timestamp::Config {
         /// Because this pallet emits events, it depends on the runtime's definition of an event.
         type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
-        //type XcmSender: SendXcm;
+        /// Sender of fee transfers
+        type XcmSender: SendXcm;
         /// Use cumulus Xcm API
         type XcmpMessageSender: XcmpMessageSender;
+        /// Executor of inbound XCM
+        type XcmExecutor: ExecuteXcm;
+        /// Weighs inbound XCM before it is executed
+        type XcmWeigher: WeighXcm;
+        /// Weight available to a single inbound XCM
+        type MaxXcmWeight: Get<Weight>;
+        /// Converts accounts and their locations, resolves sovereign accounts of parachains
+        type AccountIdConverter: LocationConversion<Self::AccountId>;
+        /// Location of the fee currency as seen by service parachains, this parachain is its
+        /// reserve
+        type FeeAssetLocation: Get<MultiLocation>;
+        /// Blocks between net settlements, zero disables them
+        type SettlementEpoch: Get<Self::BlockNumber>;
+        /// Ledger entries settled per transfer, a closed epoch is settled over as many idle
+        /// blocks as it takes
+        type SettlementBatch: Get<u32>;
+        /// Id of the account holding fees until they are settled
+        type PotId: Get<ModuleId>;
         type Currency: ReservableCurrency<Self::AccountId>;
         type OrderPayload: Encode + Decode + Clone + Default + Parameter;
+        /// Builds order payloads of the given encoded size for benchmarks
+        #[cfg(feature = "runtime-benchmarks")]
+        type PayloadOfSize: Convert<u32, Self::OrderPayload>;
+        /// Id of this parachain, clients are listed under it in device access policies
+        type SelfParaId: Get<ParaId>;
+        /// Expected relay chain block duration
+        type RelayBlockDuration: Get<MomentOf<Self>>;
+        /// Maximum number of clients listed in a device access policy
+        type MaxPolicyEntries: Get<u32>;
+        /// Delivery attempts of a deferred message, or resends of an unacknowledged one,
+        /// before it is dropped
+        type MaxSendAttempts: Get<u32>;
+        /// Deferred messages kept per destination, sends are refused while it is full
+        type MaxOutboxMessages: Get<u32>;
+        /// Delay before the first retry of a deferred message, doubled with every attempt
+        type RetryBackoff: Get<Self::BlockNumber>;
+        /// Blocks to wait for the acknowledgement before the message is sent again
+        type AckTimeout: Get<Self::BlockNumber>;
+        /// Messages awaiting acknowledgement per destination, sends are refused beyond it
+        type MaxUnacked: Get<u32>;
+        /// Origin allowed to pause cross-chain message processing
+        type PauseOrigin: EnsureOrigin<Self::Origin>;
+        /// Maximum number of messages held while their processing is paused
+        type MaxPausedMessages: Get<u32>;
+        /// Time to wait for the service parachain to answer a withdrawal, the client may
+        /// settle it on this parachain afterwards
+        type CancelTimeout: Get<MomentOf<Self>>;
+        /// Weight information for extrinsics and message handlers in this pallet
+        type WeightInfo: WeightInfo;
     }
 
     #[pallet::pallet]
@@ -113,15 +198,333 @@ pub mod pallet {
     #[pallet::getter(fn orders)]
     pub type Orders<T: Config> = StorageMap<_, Twox64Concat, T::AccountId, OrderOf<T>, OptionQuery>;
 
+    /// Id of the next placed order
+    #[pallet::storage]
+    pub type NextOrderId<T: Config> = StorageValue<_, OrderId, ValueQuery>;
+
+    /// Storage layout version
+    #[pallet::storage]
+    pub(super) type StorageVersion<T: Config> = StorageValue<_, Releases, ValueQuery>;
+
+    /// Relay chain block number the current block is built on
+    #[pallet::storage]
+    #[pallet::getter(fn relay_block_number)]
+    pub type RelayParentNumber<T: Config> = StorageValue<_, RelayBlockNumber, ValueQuery>;
+
+    /// Latest telemetry snapshot relayed by the device
+    #[pallet::storage]
+    #[pallet::getter(fn progress)]
+    pub type Progress<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, (MomentOf<T>, Telemetry), OptionQuery>;
+
+    /// Clients permitted to order the device
+    #[pallet::storage]
+    #[pallet::getter(fn policies)]
+    pub type Policies<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        <T as frame_system::Config>::AccountId,
+        AccessPolicyOf<T>,
+        ValueQuery,
+    >;
+
+    /// Protocol versions negotiated with sibling parachains
+    #[pallet::storage]
+    #[pallet::getter(fn protocol_version)]
+    pub type ProtocolVersions<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, ProtocolVersion, OptionQuery>;
+
+    /// Nonce of the next acknowledged message to the parachain
+    #[pallet::storage]
+    pub type NextNonce<T: Config> = StorageMap<_, Twox64Concat, ParaId, Nonce, ValueQuery>;
+
+    /// Sent messages waiting for the receiver acknowledgement
+    #[pallet::storage]
+    #[pallet::getter(fn unacked)]
+    pub type Unacked<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        ParaId,
+        Twox64Concat,
+        Nonce,
+        PendingAck<T::BlockNumber>,
+        OptionQuery,
+    >;
+
+    /// Number of the destination's `Unacked` messages
+    #[pallet::storage]
+    pub type UnackedCount<T: Config> = StorageMap<_, Twox64Concat, ParaId, u32, ValueQuery>;
+
+    /// Destinations with unacknowledged messages, in the order they are checked
+    #[pallet::storage]
+    pub type UnackedDestinations<T: Config> = StorageValue<_, Vec<ParaId>, ValueQuery>;
+
+    /// Index in `UnackedDestinations` the next resend round starts from
+    #[pallet::storage]
+    pub type UnackedCursor<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+    /// Inbound nonces processed from the parachain
+    #[pallet::storage]
+    #[pallet::getter(fn inbound_sequence)]
+    pub type InboundSequence<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, SeqWindow, ValueQuery>;
+
+    /// Inbound nonces refused, kept to answer their duplicates alike
+    #[pallet::storage]
+    pub type InboundRefused<T: Config> =
+        StorageDoubleMap<_, Twox64Concat, ParaId, Twox64Concat, Nonce, (), OptionQuery>;
+
+    /// Messages deferred after a failed send, by destination
+    #[pallet::storage]
+    pub type Outbox<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        ParaId,
+        Twox64Concat,
+        u64,
+        OutboundMessage<T::BlockNumber>,
+        OptionQuery,
+    >;
+
+    /// Head and tail of the destination's `Outbox`, present while it has messages
+    #[pallet::storage]
+    pub type OutboxBounds<T: Config> = StorageMap<_, Twox64Concat, ParaId, (u64, u64), ValueQuery>;
+
+    /// Destinations with deferred messages, in the order they are retried
+    #[pallet::storage]
+    pub type OutboxDestinations<T: Config> = StorageValue<_, Vec<ParaId>, ValueQuery>;
+
+    /// Index in `OutboxDestinations` the next retry round starts from
+    #[pallet::storage]
+    pub type OutboxCursor<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+    /// Fee settlement of orders to devices on the parachain
+    #[pallet::storage]
+    #[pallet::getter(fn settlement_mode)]
+    pub type SettlementModes<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, SettlementMode, ValueQuery>;
+
+    /// Fees and penalties accumulated in the current epoch, by service parachain and device
+    #[pallet::storage]
+    #[pallet::getter(fn ledger)]
+    pub type Ledgers<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        ParaId,
+        Twox64Concat,
+        T::AccountId,
+        LedgerEntryOf<T>,
+        ValueQuery,
+    >;
+
+    /// Settlement mode of the open order to the device, fixed when the order was placed
+    #[pallet::storage]
+    pub(super) type OrderSettlements<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, SettlementMode, ValueQuery>;
+
+    /// Service parachains with entries in `Ledgers`
+    #[pallet::storage]
+    pub(super) type NettingDestinations<T: Config> = StorageValue<_, Vec<ParaId>, ValueQuery>;
+
+    /// Index of the current settlement epoch
+    #[pallet::storage]
+    #[pallet::getter(fn epoch)]
+    pub type Epoch<T: Config> = StorageValue<_, EpochIndex, ValueQuery>;
+
+    /// Service parachains the closed epoch is still to be settled with, the first is settled
+    /// next
+    #[pallet::storage]
+    pub(super) type PendingSettlements<T: Config> = StorageValue<_, Vec<ParaId>, ValueQuery>;
+
+    /// Index of the closed epoch being settled
+    #[pallet::storage]
+    pub(super) type SettlingEpoch<T: Config> = StorageValue<_, EpochIndex, ValueQuery>;
+
+    /// Net settlements of past epochs
+    #[pallet::storage]
+    #[pallet::getter(fn settlements)]
+    pub type Settlements<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        ParaId,
+        Twox64Concat,
+        EpochIndex,
+        SettlementRecordOf<T>,
+        OptionQuery,
+    >;
+
+    /// Active pauses of cross-chain message processing
+    #[pallet::storage]
+    #[pallet::getter(fn paused)]
+    pub type Pauses<T: Config> = StorageMap<_, Twox64Concat, PauseTarget<ParaId>, bool, ValueQuery>;
+
+    /// Messages received while paused, processed in order once resumed
+    #[pallet::storage]
+    pub type PausedMessages<T: Config> =
+        StorageMap<_, Twox64Concat, u64, (ParaId, XCMPMessageOf<T>), OptionQuery>;
+
+    /// Head and tail of `PausedMessages`
+    #[pallet::storage]
+    pub type PausedMessagesBounds<T: Config> = StorageValue<_, (u64, u64), ValueQuery>;
+
+    /// Order amendments awaiting device acknowledgement
+    #[pallet::storage]
+    #[pallet::getter(fn amendments)]
+    pub type Amendments<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, AmendmentOf<T>, OptionQuery>;
+
+    /// Device registries of service parachains mirrored to `Device`
+    #[pallet::storage]
+    #[pallet::getter(fn subscription)]
+    pub type Subscriptions<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, Subscription, OptionQuery>;
+
+    /// Service parachain the device is registered on, set by governance, only it can mirror
+    /// the device
+    #[pallet::storage]
+    #[pallet::getter(fn device_para)]
+    pub type DeviceParas<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, ParaId, OptionQuery>;
+
+    /// Withdrawal of the open order to the device (id, requested at), the order stays open
+    /// until the service parachain answers or `CancelTimeout` passes
+    #[pallet::storage]
+    #[pallet::getter(fn cancelled)]
+    pub type Cancelled<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, (OrderId, MomentOf<T>), OptionQuery>;
+
     #[pallet::event]
-    #[pallet::metadata(T::AccountId = "AccountId")]
+    #[pallet::metadata(
+        T::AccountId = "AccountId",
+        BalanceOf<T> = "Balance",
+        SettlementOf<T> = "Settlement"
+    )]
     #[pallet::generate_deposit(pub(super) fn deposit_event)]
     pub enum Event<T: Config> {
         NewDevice(T::AccountId),
-        NewOrder(T::AccountId, T::AccountId),
-        Accept(T::AccountId, T::AccountId),
-        Reject(T::AccountId, T::AccountId),
-        Done(T::AccountId, T::AccountId),
+        /// Device profile updated by the service parachain \[device\]
+        DeviceUpdated(T::AccountId),
+        /// Device registry of the parachain mirrored or not \[paraid, subscribed\]
+        SubscriptionChanged(ParaId, bool),
+        /// Device registry snapshot received \[paraid, devices\]
+        RegistrySynced(ParaId, u32),
+        /// Device registry change missed, snapshot requested again \[paraid, expected, received\]
+        RegistryGap(ParaId, u64, u64),
+        /// Device assigned to the service parachain that mirrors it \[device, paraid\]
+        DeviceBound(T::AccountId, ParaId),
+        /// Device access policy changed by the service parachain \[device\]
+        PolicyChanged(T::AccountId),
+        /// Order sent to the device parachain \[id, client, device, paraid, fee\]
+        NewOrder(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Order accepted by the device \[id, client, device, paraid, fee\]
+        Accept(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Order rejected by the device \[id, client, device, paraid, fee, settlement\]
+        Reject(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            SettlementOf<T>,
+        ),
+        /// Order completed \[id, client, device, paraid, fee, settlement\]
+        Done(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            SettlementOf<T>,
+        ),
+        /// Order progress relayed from the device \[id, client, device, paraid, fee, progress\]
+        Progress(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            u8,
+        ),
+        /// Order amendment sent to the device parachain \[id, client, device, paraid, fee\],
+        /// fee is the amended one
+        AmendRequested(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Order amendment acknowledged by the device \[id, client, device, paraid, fee\]
+        Amended(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Order amendment refused by the service parachain, the extra fee is released
+        /// \[id, client, device, paraid, fee\], fee is the refused one
+        AmendRejected(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Protocol version agreed with the parachain \[paraid, version\]
+        HandshakeCompleted(ParaId, ProtocolVersion),
+        /// Message could not be sent, deferred to the outbox \[dest, kind\]
+        MessageDeferred(ParaId, MessageKind),
+        /// Deferred message sent \[dest, kind\]
+        MessageDelivered(ParaId, MessageKind),
+        /// Deferred message dropped after too many attempts \[dest, kind, attempts\]
+        MessageAbandoned(ParaId, MessageKind, u32),
+        /// Message not sent, the outbox of the destination is full \[dest, kind\]
+        MessageRefused(ParaId, MessageKind),
+        /// Message acknowledged by the receiver \[dest, nonce\]
+        MessageAcked(ParaId, Nonce),
+        /// Message refused by the receiver \[dest, nonce, kind\]
+        MessageNacked(ParaId, Nonce, MessageKind),
+        /// Message not acknowledged in time, sent again \[dest, nonce, kind, resends\]
+        MessageUnacknowledged(ParaId, Nonce, MessageKind, u32),
+        /// Message still not acknowledged after the last resend, given up \[dest, nonce, kind\]
+        UnackedAbandoned(ParaId, Nonce, MessageKind),
+        /// Message received ahead of the expected one \[sender, expected, received\]
+        SequenceGap(ParaId, Nonce, Nonce),
+        /// Already processed message dropped \[sender, nonce\]
+        DuplicateDropped(ParaId, Nonce),
+        /// Fee settlement changed \[paraid, mode\]
+        SettlementChanged(ParaId, SettlementMode),
+        /// Fee transferred to the device on the service parachain \[id, device, paraid, fee\]
+        FeeTransferred(OrderId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Fee transfer failed, paid on this parachain instead \[id, device, paraid\]
+        FeeTransferFailed(OrderId, T::AccountId, ParaId),
+        /// Batch of the epoch ledger settled with the parachain \[paraid, epoch, total\]
+        EpochSettled(ParaId, EpochIndex, BalanceOf<T>),
+        /// Epoch ledger not settled, the rest of it is carried over to the next epoch
+        /// \[paraid, epoch\]
+        EpochSettlementFailed(ParaId, EpochIndex),
+        /// Cross-chain message processing paused or resumed \[target, paused\]
+        PauseChanged(PauseTarget<ParaId>, bool),
+        /// Message received while paused, queued for processing \[sender, kind\]
+        MessageQueued(ParaId, MessageKind),
+        /// Message received while paused and dropped, the queue is full \[sender, kind\]
+        MessageDropped(ParaId, MessageKind),
+        /// Order closed or accepted on the service parachain before the cancel arrived, the
+        /// order stays as it is \[id, client, device, paraid\]
+        CancelRejected(OrderId, T::AccountId, T::AccountId, ParaId),
+        /// Withdrawal of the order sent, it is settled once the service parachain confirms it
+        /// \[id, client, device, paraid\]
+        CancelRequested(OrderId, T::AccountId, T::AccountId, ParaId),
+        /// Order withdrawal confirmed by the service parachain
+        /// \[id, client, device, paraid, fee, settlement\]
+        Cancelled(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            SettlementOf<T>,
+        ),
+        /// Order refused by the paused service parachain
+        /// \[id, client, device, paraid, fee, settlement\]
+        Failed(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            SettlementOf<T>,
+        ),
+        /// Inbound XCM executed \[hash, weight\]
+        XcmSuccess(<T as frame_system::Config>::Hash, Weight),
+        /// Inbound XCM failed \[hash, error\]
+        XcmFail(<T as frame_system::Config>::Hash, XcmError),
+        /// Inbound XCM dropped unexecuted, it exceeds the weight limit or can't be weighed
+        /// \[hash\]
+        XcmOverweight(<T as frame_system::Config>::Hash),
         BadVersion(<T as frame_system::Config>::Hash),
     }
 
@@ -140,14 +543,96 @@ pub mod pallet {
         NoOrder,
         Prohibited,
         CannotReachDestination,
+        NoAmendment,
+        NotAllowed,
+        TooManyEntries,
+        Paused,
+        BadProtocolVersion,
+        NoHandshake,
+        UnexpectedMessage,
+        BadLocation,
+        NotSubscribed,
+        BadTelemetry,
+        OutboxFull,
+        TooManyUnacked,
+        CancelPending,
+    }
+
+    #[pallet::genesis_config]
+    pub struct GenesisConfig<T: Config> {
+        /// Pre-registered remote devices (device, paraid, penalty, wcd, onoff)
+        ///
+        /// The work circle duration is given in timestamp units, `Moment` has no serde support.
+        pub devices: Vec<(T::AccountId, ParaId, BalanceOf<T>, u64, bool)>,
+    }
+
+    #[cfg(feature = "std")]
+    impl<T: Config> Default for GenesisConfig<T> {
+        fn default() -> Self {
+            Self {
+                devices: Vec::new(),
+            }
+        }
+    }
+
+    #[pallet::genesis_build]
+    impl<T: Config> GenesisBuild<T> for GenesisConfig<T> {
+        fn build(&self) {
+            for (id, paraid, penalty, wcd, onoff) in &self.devices {
+                DeviceParas::<T>::insert(id, paraid);
+                Device::<T>::insert(
+                    id,
+                    DeviceProfile {
+                        wcd: MomentOf::<T>::unique_saturated_from(*wcd),
+                        penalty: *penalty,
+                        state: if *onoff {
+                            DeviceState::Ready
+                        } else {
+                            DeviceState::Off
+                        },
+                        paraid: *paraid,
+                    },
+                );
+            }
+            StorageVersion::<T>::put(Releases::V2);
+        }
     }
 
     #[pallet::hooks]
-    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {}
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        fn on_runtime_upgrade() -> Weight {
+            crate::migrations::migrate::<T>()
+        }
+
+        fn on_initialize(n: BlockNumberFor<T>) -> Weight {
+            let epoch = T::SettlementEpoch::get();
+            if epoch.is_zero() || !(n % epoch).is_zero() {
+                return 0;
+            }
+            Self::close_epoch()
+        }
+
+        fn on_idle(n: BlockNumberFor<T>, remaining_weight: Weight) -> Weight {
+            let mut used = Self::retry_outbox(n, remaining_weight);
+            used += Self::resend_unacked(n, remaining_weight.saturating_sub(used));
+            used += Self::settle_pending(n, remaining_weight.saturating_sub(used));
+            used + Self::replay_paused(remaining_weight.saturating_sub(used))
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            crate::migrations::pre_migrate::<T>()
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            crate::migrations::post_migrate::<T>()
+        }
+    }
 
     #[pallet::call]
     impl<T: Config> Pallet<T> {
-        #[pallet::weight(10_000)]
+        #[pallet::weight(T::WeightInfo::test())]
         pub fn test(origin: OriginFor<T>) -> DispatchResult {
             T::XcmpMessageSender::send_blob_message(
                 (200).into(),
@@ -158,13 +643,18 @@ pub mod pallet {
             .map(|_| ())
         }
 
-        #[pallet::weight(10_000)]
+        #[pallet::weight(T::WeightInfo::order(order.data.encoded_size() as u32))]
+        #[transactional]
         pub fn order(origin: OriginFor<T>, order: OrderBaseOf<T>) -> DispatchResult {
             let who = ensure_signed(origin)?;
 
+            if Pauses::<T>::get(PauseTarget::All) {
+                return Err(Error::<T>::Paused.into());
+            }
+
             let now = Timestamp::<T>::get();
 
-            if now >= order.until {
+            if order.is_overdue(&now, RelayParentNumber::<T>::get()) {
                 return Err(Error::<T>::Overdue.into());
             }
 
@@ -177,7 +667,16 @@ pub mod pallet {
                 return Err(Error::<T>::IllegalState.into());
             }
 
-            if order.until < (now + dev.wcd) {
+            if !ProtocolVersions::<T>::contains_key(dev.paraid) {
+                return Err(Error::<T>::NoHandshake.into());
+            }
+
+            // Checked here as well so that the service parachain never sees the order
+            if !Policies::<T>::get(&order.device).permits(&T::SelfParaId::get(), &who) {
+                return Err(Error::<T>::NotAllowed.into());
+            }
+
+            if !Self::leaves_wcd(order.until, order.until_relay, now, dev.wcd) {
                 return Err(Error::<T>::BadOrderDetails.into());
             };
 
@@ -189,33 +688,33 @@ pub mod pallet {
             T::Currency::reserve(&who, order.fee)?;
 
             let device = order.device.clone();
+            let fee = order.fee;
+            let id = Self::next_order_id();
             // store order
             let order: OrderBaseOf<T> = {
-                let order: OrderOf<T> = order.convert(who.clone());
+                let order: OrderOf<T> = order.convert(id, who.clone());
                 Orders::<T>::insert(&device, &order);
+                OrderSettlements::<T>::insert(&device, SettlementModes::<T>::get(dev.paraid));
                 order.convert(device.clone())
             };
 
-            let msg: XCMPMessageOf<T> = XCMPMessageOf::<T>::NewOrder(who.clone(), order);
+            let msg: XCMPMessageOf<T> = XCMPMessageOf::<T>::NewOrder(id, who.clone(), order);
 
             log::info!("send XCM order message");
 
-            T::XcmpMessageSender::send_blob_message(
-                dev.paraid,
-                msg.encode(),
-                ServiceQuality::Ordered,
-            )
-            .map_err(|_| Error::<T>::CannotReachDestination)?;
+            Self::send_message(dev.paraid, msg)?;
             log::info!("XCM order message has sent");
             dev.state = DeviceState::Busy;
             Device::<T>::insert(&device, &dev);
 
-            Self::deposit_event(Event::NewOrder(who, device.clone()));
+            Self::deposit_event(Event::NewOrder(id, who, device, dev.paraid, fee));
 
             Ok(())
         }
 
-        #[pallet::weight(10_000)]
+        /// Asks the service parachain to withdraw the order, an unanswered withdrawal is settled
+        /// on this parachain once `CancelTimeout` passes
+        #[pallet::weight(T::WeightInfo::cancel().max(T::WeightInfo::on_cancelled()))]
         pub fn cancel(origin: OriginFor<T>, device: T::AccountId) -> DispatchResult {
             let who = ensure_signed(origin)?;
 
@@ -223,63 +722,493 @@ pub mod pallet {
 
             let now = Timestamp::<T>::get();
 
-            if now < order.until || order.client != who {
+            if order.client != who {
                 return Err(Error::<T>::Prohibited.into());
             }
 
-            let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
-            // Note. we don't change device state
-            Self::order_reject(who, &order, now, device, &mut dev)
+            if let Some(requested) = Self::pending_cancel(&device, order.id) {
+                if now < requested.saturating_add(T::CancelTimeout::get()) {
+                    return Err(Error::<T>::CancelPending.into());
+                }
+                // The service parachain never answered, settled here as of the request
+                return Self::settle_cancel(who, &order, requested, device);
+            }
+
+            let dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+            // Withdrawal before acceptance, collateral is released without penalty
+            if !Self::is_overdue(&order, &now) && dev.state != DeviceState::Busy {
+                return Err(Error::<T>::Prohibited.into());
+            }
+            let msg: XCMPMessageOf<T> =
+                XCMPMessageOf::<T>::OrderCancel(order.id, who.clone(), device.clone());
+            Self::send_message(dev.paraid, msg)?;
+            Cancelled::<T>::insert(&device, (order.id, now));
+            Self::deposit_event(Event::CancelRequested(order.id, who, device, dev.paraid));
+            Ok(())
         }
 
-        #[pallet::weight(10_000)]
-        pub fn register(
+        /// Extends the deadline and/or raises the fee of the order, the amendment binds once
+        /// the device acknowledges it
+        #[pallet::weight(T::WeightInfo::amend_order())]
+        #[transactional]
+        pub fn amend_order(
+            origin: OriginFor<T>,
+            device: T::AccountId,
+            amendment: AmendmentOf<T>,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+
+            let order = Orders::<T>::get(&device).ok_or(Error::<T>::NoOrder)?;
+            if order.client != who {
+                return Err(Error::<T>::Prohibited.into());
+            }
+
+            let now = Timestamp::<T>::get();
+            if Self::is_overdue(&order, &now) {
+                return Err(Error::<T>::Overdue.into());
+            }
+
+            if Amendments::<T>::contains_key(&device) {
+                return Err(Error::<T>::IllegalState.into());
+            }
+
+            if Self::pending_cancel(&device, order.id).is_some() {
+                return Err(Error::<T>::CancelPending.into());
+            }
+
+            if !amendment.extends(&order) {
+                return Err(Error::<T>::BadOrderDetails.into());
+            }
+
+            let dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+            T::Currency::reserve(&who, amendment.fee - order.fee)?;
+
+            let msg: XCMPMessageOf<T> = XCMPMessageOf::<T>::OrderAmend(
+                order.id,
+                who.clone(),
+                device.clone(),
+                amendment.clone(),
+            );
+            Self::send_message(dev.paraid, msg)?;
+
+            let fee = amendment.fee;
+            Amendments::<T>::insert(&device, amendment);
+            Self::deposit_event(Event::AmendRequested(
+                order.id, who, device, dev.paraid, fee,
+            ));
+            Ok(())
+        }
+
+        /// Starts protocol version negotiation with the parachain
+        #[pallet::weight(T::WeightInfo::handshake())]
+        pub fn handshake(origin: OriginFor<T>, paraid: ParaId) -> DispatchResult {
+            ensure_root(origin)?;
+
+            Self::send_message(paraid, XCMPMessageOf::<T>::Handshake(PROTOCOL_VERSION))
+        }
+
+        /// Pauses or resumes processing of cross-chain messages
+        #[pallet::weight(T::WeightInfo::set_pause())]
+        pub fn set_pause(
+            origin: OriginFor<T>,
+            target: PauseTarget<ParaId>,
+            paused: bool,
+        ) -> DispatchResult {
+            T::PauseOrigin::ensure_origin(origin)?;
+
+            if paused {
+                Pauses::<T>::insert(target, true);
+            } else {
+                Pauses::<T>::remove(target);
+            }
+            Self::deposit_event(Event::PauseChanged(target, paused));
+            Ok(())
+        }
+
+        /// Chooses how fees of orders to devices on the parachain are paid
+        #[pallet::weight(T::WeightInfo::set_settlement())]
+        pub fn set_settlement(
             origin: OriginFor<T>,
             paraid: ParaId,
-            penalty: BalanceOf<T>,
-            wcd: MomentOf<T>,
-            onoff: bool,
+            mode: SettlementMode,
         ) -> DispatchResult {
-            let id = ensure_signed(origin)?;
-
-            if Orders::<T>::contains_key(&id) {
-                return Err(Error::<T>::DeviceExists.into());
-            }
-            // Despite the order doesn't exist, device can be in Busy,Busy2 state.
-            //
-            Device::<T>::insert(
-                &id,
-                DeviceProfile {
-                    wcd,
-                    penalty,
-                    state: if onoff {
-                        DeviceState::Ready
-                    } else {
-                        DeviceState::Off
-                    },
-                    paraid,
+            ensure_root(origin)?;
+
+            if SettlementModes::<T>::get(paraid) != mode {
+                // Both parachains settle the orders placed from now on the new way
+                Self::send_message(paraid, XCMPMessageOf::<T>::SettlementChanged(mode))?;
+            }
+            SettlementModes::<T>::insert(paraid, mode);
+            Self::deposit_event(Event::SettlementChanged(paraid, mode));
+            Ok(())
+        }
+
+        /// Mirrors devices of the service parachain, starting with the snapshot of its registry
+        #[pallet::weight(T::WeightInfo::subscribe())]
+        pub fn subscribe(origin: OriginFor<T>, paraid: ParaId) -> DispatchResult {
+            ensure_root(origin)?;
+
+            Self::send_message(paraid, XCMPMessageOf::<T>::Subscribe)?;
+            Subscriptions::<T>::insert(
+                paraid,
+                Subscription {
+                    next: 0,
+                    syncing: true,
                 },
             );
+            Self::deposit_event(Event::SubscriptionChanged(paraid, true));
+            Ok(())
+        }
+
+        /// Assigns the device to the service parachain it is registered on, the mirrored
+        /// profile of another parachain is dropped
+        #[pallet::weight(T::WeightInfo::bind_device())]
+        pub fn bind_device(
+            origin: OriginFor<T>,
+            device: T::AccountId,
+            paraid: ParaId,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            if Orders::<T>::contains_key(&device) {
+                return Err(Error::<T>::OrderExists.into());
+            }
+            if Device::<T>::get(&device).map_or(false, |dev| dev.paraid != paraid) {
+                Device::<T>::remove(&device);
+                Policies::<T>::remove(&device);
+            }
+            DeviceParas::<T>::insert(&device, paraid);
+            Self::deposit_event(Event::DeviceBound(device, paraid));
+            Ok(())
+        }
 
-            Self::deposit_event(Event::NewDevice(id));
+        /// Stops mirroring devices of the service parachain, mirrored profiles are kept
+        #[pallet::weight(T::WeightInfo::unsubscribe())]
+        pub fn unsubscribe(origin: OriginFor<T>, paraid: ParaId) -> DispatchResult {
+            ensure_root(origin)?;
+
+            if !Subscriptions::<T>::contains_key(paraid) {
+                return Err(Error::<T>::NotSubscribed.into());
+            }
+            Self::send_message(paraid, XCMPMessageOf::<T>::Unsubscribe)?;
+            Subscriptions::<T>::remove(paraid);
+            Self::deposit_event(Event::SubscriptionChanged(paraid, false));
             Ok(())
         }
     }
 }
 
 impl<T: Config> Pallet<T> {
-    fn on_accept(who: T::AccountId, device: T::AccountId) -> DispatchResult {
-        //let order = Orders::<T>::get(&device).ok_or(Error::<T>::NoOrder)?;
-        Self::deposit_event(Event::Accept(who, device));
+    /// Open order of the device the service parachain replies to
+    fn current_order(
+        id: OrderId,
+        who: &T::AccountId,
+        device: &T::AccountId,
+    ) -> Result<OrderOf<T>, DispatchError> {
+        let order = Orders::<T>::get(device).ok_or(Error::<T>::NoOrder)?;
+        if order.id != id || order.client != *who {
+            return Err(Error::<T>::Prohibited.into());
+        }
+        Ok(order)
+    }
+
+    /// Profile of the device, only the parachain hosting it may report on its orders
+    fn device_of(sender: ParaId, device: &T::AccountId) -> Result<DeviceProfile<T>, DispatchError> {
+        let dev = Device::<T>::get(device).ok_or(Error::<T>::NoDevice)?;
+        if dev.paraid != sender {
+            return Err(Error::<T>::Prohibited.into());
+        }
+        Ok(dev)
+    }
+
+    fn on_accept(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        let order = Self::current_order(id, &who, &device)?;
+        let mut dev = Self::device_of(sender, &device)?;
+
+        // Accepted orders can't be withdrawn before the deadline
+        dev.state = DeviceState::Busy2;
+        Device::<T>::insert(&device, &dev);
+
+        Self::deposit_event(Event::Accept(order.id, who, device, dev.paraid, order.fee));
+        Ok(())
+    }
+
+    fn on_amend_accept(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        let mut order = Self::current_order(id, &who, &device)?;
+        let dev = Self::device_of(sender, &device)?;
+
+        let amendment = Amendments::<T>::take(&device).ok_or(Error::<T>::NoAmendment)?;
+
+        order.amend(amendment);
+        Orders::<T>::insert(&device, &order);
+        Self::deposit_event(Event::Amended(order.id, who, device, dev.paraid, order.fee));
+        Ok(())
+    }
+
+    /// Releases the extra fee of the amendment the service parachain refused
+    fn on_amend_reject(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        let order = Self::current_order(id, &who, &device)?;
+        let dev = Self::device_of(sender, &device)?;
+        let amendment = Amendments::<T>::take(&device).ok_or(Error::<T>::NoAmendment)?;
+
+        T::Currency::unreserve(&who, amendment.fee - order.fee);
+        Self::deposit_event(Event::AmendRejected(
+            order.id,
+            who,
+            device,
+            dev.paraid,
+            amendment.fee,
+        ));
         Ok(())
     }
 
-    fn on_reject(who: T::AccountId, device: T::AccountId, onoff: bool) -> DispatchResult {
-        let order = Orders::<T>::get(&device).ok_or(Error::<T>::NoOrder)?;
+    fn on_device_announce(
+        sender: ParaId,
+        seq: u64,
+        device: T::AccountId,
+        penalty: BalanceOf<T>,
+        wcd: MomentOf<T>,
+        state: DeviceState,
+    ) -> DispatchResult {
+        if !Self::check_delta(sender, seq)? {
+            return Ok(());
+        }
+        Self::announce_device(sender, device, penalty, wcd, state)
+    }
+
+    /// Mirrors the device registered on the service parachain it is bound to
+    fn announce_device(
+        sender: ParaId,
+        device: T::AccountId,
+        penalty: BalanceOf<T>,
+        wcd: MomentOf<T>,
+        state: DeviceState,
+    ) -> DispatchResult {
+        if DeviceParas::<T>::get(&device) != Some(sender) {
+            return Err(Error::<T>::Prohibited.into());
+        }
+        Device::<T>::insert(
+            &device,
+            DeviceProfile {
+                state,
+                penalty,
+                wcd,
+                paraid: sender,
+            },
+        );
+        Self::deposit_event(Event::NewDevice(device));
+        Ok(())
+    }
+
+    fn on_device_update(
+        sender: ParaId,
+        seq: u64,
+        device: T::AccountId,
+        penalty: BalanceOf<T>,
+        wcd: MomentOf<T>,
+    ) -> DispatchResult {
+        if !Self::check_delta(sender, seq)? {
+            return Ok(());
+        }
+        Self::mirror_device(sender, &device, |dev| {
+            dev.penalty = penalty;
+            dev.wcd = wcd;
+        })?;
+        Self::deposit_event(Event::DeviceUpdated(device));
+        Ok(())
+    }
+
+    fn on_device_state(
+        sender: ParaId,
+        seq: u64,
+        device: T::AccountId,
+        state: DeviceState,
+    ) -> DispatchResult {
+        if !Self::check_delta(sender, seq)? {
+            return Ok(());
+        }
+        Self::mirror_device(sender, &device, |dev| dev.state = state)
+    }
+
+    fn on_device_policy(
+        sender: ParaId,
+        seq: u64,
+        device: T::AccountId,
+        policy: AccessPolicy<u32, T::AccountId>,
+    ) -> DispatchResult {
+        if !Self::check_delta(sender, seq)? {
+            return Ok(());
+        }
+        Self::mirror_policy(sender, device, policy)
+    }
+
+    /// Mirrors the access policy of the device, only the parachain of the device can set it
+    fn mirror_policy(
+        sender: ParaId,
+        device: T::AccountId,
+        policy: AccessPolicy<u32, T::AccountId>,
+    ) -> DispatchResult {
+        let dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+        if dev.paraid != sender {
+            return Err(Error::<T>::Prohibited.into());
+        }
+
+        if policy.entries() > T::MaxPolicyEntries::get() as usize {
+            return Err(Error::<T>::TooManyEntries.into());
+        }
+
+        let policy: AccessPolicyOf<T> = policy.convert();
+        if policy == AccessPolicyOf::<T>::Open {
+            Policies::<T>::remove(&device);
+        } else {
+            Policies::<T>::insert(&device, policy);
+        }
+        Self::deposit_event(Event::PolicyChanged(device));
+        Ok(())
+    }
+
+    /// Replaces mirrored profiles with the registry snapshot, the changes follow from `next`
+    fn on_device_snapshot(
+        sender: ParaId,
+        next: u64,
+        devices: Vec<(
+            T::AccountId,
+            BalanceOf<T>,
+            MomentOf<T>,
+            DeviceState,
+            AccessPolicy<u32, T::AccountId>,
+        )>,
+    ) -> DispatchResult {
+        if !Subscriptions::<T>::contains_key(sender) {
+            return Err(Error::<T>::NotSubscribed.into());
+        }
+
+        let count = devices.len() as u32;
+        for (device, penalty, wcd, state, policy) in devices {
+            let mirrored = Self::announce_device(sender, device.clone(), penalty, wcd, state)
+                .and_then(|_| Self::mirror_policy(sender, device, policy));
+            if let Err(e) = mirrored {
+                log::warn!("snapshot device from {:?} skipped: {:?}", sender, e);
+            }
+        }
+        Subscriptions::<T>::insert(
+            sender,
+            Subscription {
+                next,
+                syncing: false,
+            },
+        );
+        Self::deposit_event(Event::RegistrySynced(sender, count));
+        Ok(())
+    }
+
+    /// Checks the registry change follows the last one applied, false if it must be skipped.
+    /// A missed change makes the registry subscribed again for a fresh snapshot
+    fn check_delta(sender: ParaId, seq: u64) -> Result<bool, DispatchError> {
+        let mut sub = Subscriptions::<T>::get(sender).ok_or(Error::<T>::NotSubscribed)?;
+        // Changes up to the snapshot are part of it
+        if sub.syncing || seq < sub.next {
+            return Ok(false);
+        }
+        if seq > sub.next {
+            Self::deposit_event(Event::RegistryGap(sender, sub.next, seq));
+            // Left out of sync while the outbox is full, the next change finds the gap again
+            match Self::send_message(sender, XCMPMessageOf::<T>::Subscribe) {
+                Ok(()) => {
+                    sub.syncing = true;
+                    Subscriptions::<T>::insert(sender, sub);
+                }
+                Err(e) => log::warn!("resync with {:?} postponed: {:?}", sender, e),
+            }
+            return Ok(false);
+        }
+        sub.next += 1;
+        Subscriptions::<T>::insert(sender, sub);
+        Ok(true)
+    }
+
+    /// Changes the mirrored profile, only the parachain of the device can do so
+    fn mirror_device(
+        sender: ParaId,
+        device: &T::AccountId,
+        f: impl FnOnce(&mut DeviceProfile<T>),
+    ) -> DispatchResult {
+        Device::<T>::try_mutate(device, |d| match d {
+            Some(dev) if dev.paraid == sender => {
+                f(dev);
+                Ok(())
+            }
+            Some(_) => Err(Error::<T>::Prohibited.into()),
+            None => Err(Error::<T>::NoDevice.into()),
+        })
+    }
+
+    /// Releases the order the service parachain refused to receive, nobody is penalized
+    fn on_failed(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        let order = Self::current_order(id, &who, &device)?;
+
+        let mut dev = Self::device_of(sender, &device)?;
+        if dev.state != DeviceState::Busy {
+            return Err(Error::<T>::IllegalState.into());
+        }
+        dev.state = DeviceState::Ready;
+
+        Self::drop_amendment(&order, &device);
+        let unrefunded = T::Currency::unreserve(&who, order.fee);
+        let unreleased = T::Currency::unreserve(&device, dev.penalty);
+        let settlement = SettlementOf::<T> {
+            paid: Zero::zero(),
+            refunded: order.fee - unrefunded,
+            penalty: dev.penalty - unreleased,
+            slashed: false,
+        };
+
+        Self::close_order(&device);
+        Device::<T>::insert(&device, &dev);
+
+        Self::deposit_event(Event::Failed(
+            order.id, who, device, dev.paraid, order.fee, settlement,
+        ));
+        Ok(())
+    }
+
+    fn on_reject(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+        onoff: bool,
+    ) -> DispatchResult {
+        let order = match Orders::<T>::get(&device) {
+            Some(order) if order.id == id => order,
+            _ => return Err(Error::<T>::NoOrder.into()),
+        };
+        if order.client != who {
+            return Err(Error::<T>::Prohibited.into());
+        }
 
-        //TODO order.client== who
         let now = Timestamp::<T>::get();
-        let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+        let mut dev = Self::device_of(sender, &device)?;
 
         dev.state = if !onoff {
             DeviceState::Off
@@ -287,23 +1216,60 @@ impl<T: Config> Pallet<T> {
             DeviceState::Ready
         };
 
-        Self::order_reject(who, &order, now, device, &mut dev)
+        Self::order_reject(who, &order, now, device, &mut dev, false)
     }
 
-    fn on_done(who: T::AccountId, device: T::AccountId, onoff: bool) -> DispatchResult {
-        let order = Orders::<T>::get(&device).ok_or(Error::<T>::NoOrder)?;
-        //TODO order.client== who
-        let now = Timestamp::<T>::get();
-        let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+    fn on_progress(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+        telemetry: Telemetry,
+    ) -> DispatchResult {
+        if !telemetry.is_valid() {
+            return Err(Error::<T>::BadTelemetry.into());
+        }
 
-        T::Currency::repatriate_reserved(&who, &device, order.fee, Free)?;
+        let order = Self::current_order(id, &who, &device)?;
+        let dev = Self::device_of(sender, &device)?;
 
-        if now < order.until {
-            T::Currency::unreserve(&device, dev.penalty);
-        } else {
-            T::Currency::repatriate_reserved(&device, &who, dev.penalty, Free)?;
+        let progress = telemetry.progress;
+        Progress::<T>::insert(&device, (Timestamp::<T>::get(), telemetry));
+        Self::deposit_event(Event::Progress(
+            order.id, who, device, dev.paraid, order.fee, progress,
+        ));
+        Ok(())
+    }
+
+    fn on_done(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+        onoff: bool,
+    ) -> DispatchResult {
+        let order = match Orders::<T>::get(&device) {
+            Some(order) if order.id == id => order,
+            _ => return Err(Error::<T>::NoOrder.into()),
+        };
+        if order.client != who {
+            return Err(Error::<T>::Prohibited.into());
         }
-        Orders::<T>::remove(&device);
+
+        let now = Timestamp::<T>::get();
+        let mut dev = Self::device_of(sender, &device)?;
+
+        Self::drop_amendment(&order, &device);
+        let paid = Self::pay_fee(&order, &who, &device, dev.paraid)?;
+        let (penalty, slashed) = Self::settle_penalty(&order, &now, &who, &device, &dev)?;
+        let settlement = SettlementOf::<T> {
+            paid,
+            refunded: Zero::zero(),
+            penalty,
+            slashed,
+        };
+
+        Self::close_order(&device);
 
         dev.state = if !onoff {
             DeviceState::Off
@@ -312,30 +1278,654 @@ impl<T: Config> Pallet<T> {
         };
 
         Device::<T>::insert(&device, &dev);
-        Self::deposit_event(Event::Done(who, device));
+        Self::deposit_event(Event::Done(
+            order.id, who, device, dev.paraid, order.fee, settlement,
+        ));
+        Ok(())
+    }
+
+    /// Settles the withdrawn order once the service parachain confirms it
+    fn on_cancelled(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        let order = Self::current_order(id, &who, &device)?;
+        Self::device_of(sender, &device)?;
+        let requested = Self::pending_cancel(&device, id).ok_or(Error::<T>::NoOrder)?;
+        Self::settle_cancel(who, &order, requested, device)
+    }
+
+    /// Drops the withdrawal, the order goes on or was closed by a reply that came first
+    fn on_cancel_rejected(
+        sender: ParaId,
+        id: OrderId,
+        who: T::AccountId,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        let dev = Self::device_of(sender, &device)?;
+        Self::pending_cancel(&device, id).ok_or(Error::<T>::NoOrder)?;
+        Cancelled::<T>::remove(&device);
+        Self::deposit_event(Event::CancelRejected(id, who, device, dev.paraid));
+        Ok(())
+    }
+
+    /// Time the withdrawal of the order was requested at, if it awaits an answer
+    fn pending_cancel(device: &T::AccountId, id: OrderId) -> Option<MomentOf<T>> {
+        Cancelled::<T>::get(device)
+            .filter(|(cancelled, _)| *cancelled == id)
+            .map(|(_, requested)| requested)
+    }
+
+    /// Settles the withdrawn order as of the time the withdrawal was requested
+    fn settle_cancel(
+        who: T::AccountId,
+        order: &OrderOf<T>,
+        requested: MomentOf<T>,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+        if !Self::is_overdue(order, &requested) {
+            dev.state = DeviceState::Ready;
+        }
+        // Note. we don't change device state of overdue orders
+        Self::order_reject(who, order, requested, device, &mut dev, true)
+    }
+
+    /// Removes the closed order of the device with its withdrawal, if any
+    fn close_order(device: &T::AccountId) {
+        Orders::<T>::remove(device);
+        OrderSettlements::<T>::remove(device);
+        Progress::<T>::remove(device);
+        Cancelled::<T>::remove(device);
+    }
+
+    /// Pays the fee to the device in the settlement mode of its parachain
+    fn pay_fee(
+        order: &OrderOf<T>,
+        client: &T::AccountId,
+        device: &T::AccountId,
+        paraid: ParaId,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        match OrderSettlements::<T>::get(device) {
+            SettlementMode::Local => {}
+            SettlementMode::Transfer => {
+                let transferred = Self::transfer_fee(client, device, paraid, order.fee);
+                match transferred {
+                    Ok(paid) => {
+                        Self::deposit_event(Event::FeeTransferred(
+                            order.id,
+                            device.clone(),
+                            paraid,
+                            paid,
+                        ));
+                        return Ok(paid);
+                    }
+                    Err(e) => {
+                        log::error!("fee transfer return {:?}", e);
+                        Self::deposit_event(Event::FeeTransferFailed(
+                            order.id,
+                            device.clone(),
+                            paraid,
+                        ));
+                    }
+                }
+            }
+            SettlementMode::Netting => {
+                let unpaid =
+                    T::Currency::repatriate_reserved(client, &Self::pot(), order.fee, Free)?;
+                let paid = order.fee - unpaid;
+                Self::book(paraid, device, |entry| entry.fees += paid);
+                return Ok(paid);
+            }
+        }
+        let unpaid = T::Currency::repatriate_reserved(client, device, order.fee, Free)?;
+        Ok(order.fee - unpaid)
+    }
+
+    /// Moves the fee into the reserve held by the sovereign account of the service parachain
+    /// and deposits it to the device account there
+    #[transactional]
+    fn transfer_fee(
+        client: &T::AccountId,
+        device: &T::AccountId,
+        paraid: ParaId,
+        fee: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, DispatchError> {
+        let dest = MultiLocation::X2(Junction::Parent, Junction::Parachain { id: paraid.into() });
+        let reserve = T::AccountIdConverter::from_location(&dest).ok_or(Error::<T>::BadLocation)?;
+        let beneficiary = T::AccountIdConverter::try_into_location(device.clone())
+            .map_err(|_| Error::<T>::BadLocation)?;
+
+        let paid = fee - T::Currency::repatriate_reserved(client, &reserve, fee, Free)?;
+        let xcm = Xcm::ReserveAssetDeposit {
+            assets: vec![MultiAsset::ConcreteFungible {
+                id: T::FeeAssetLocation::get(),
+                amount: paid.saturated_into(),
+            }],
+            effects: vec![XcmOrder::DepositAsset {
+                assets: vec![MultiAsset::All],
+                dest: beneficiary,
+            }],
+        };
+        T::XcmSender::send_xcm(dest, xcm).map_err(|_| Error::<T>::CannotReachDestination)?;
+        Ok(paid)
+    }
+
+    /// Releases the device collateral, or slashes it in favour of the client if overdue.
+    /// Netted orders pay the penalty out of the device fees held in the pot first, the rest
+    /// of it is slashed
+    fn settle_penalty(
+        order: &OrderOf<T>,
+        now: &MomentOf<T>,
+        client: &T::AccountId,
+        device: &T::AccountId,
+        dev: &DeviceProfile<T>,
+    ) -> Result<(BalanceOf<T>, bool), DispatchError> {
+        if !Self::is_overdue(order, now) {
+            let remaining = T::Currency::unreserve(device, dev.penalty);
+            return Ok((dev.penalty - remaining, false));
+        }
+
+        let mut netted = Zero::zero();
+        if OrderSettlements::<T>::get(device) == SettlementMode::Netting {
+            let entry = Ledgers::<T>::get(dev.paraid, device);
+            netted = dev.penalty.min(entry.fees.saturating_sub(entry.penalties));
+        }
+        if !netted.is_zero() {
+            T::Currency::transfer(
+                &Self::pot(),
+                client,
+                netted,
+                ExistenceRequirement::AllowDeath,
+            )?;
+            Self::book(dev.paraid, device, |entry| entry.penalties += netted);
+            T::Currency::unreserve(device, netted);
+        }
+        let slashed = dev.penalty - netted;
+        let remaining = T::Currency::repatriate_reserved(device, client, slashed, Free)?;
+        Ok((dev.penalty - remaining, true))
+    }
+
+    /// Adds to the ledger with the service parachain, settled at the end of the epoch
+    fn book(paraid: ParaId, device: &T::AccountId, f: impl FnOnce(&mut LedgerEntryOf<T>)) {
+        Ledgers::<T>::mutate(paraid, device, f);
+        NettingDestinations::<T>::mutate(|dests| {
+            if !dests.contains(&paraid) {
+                dests.push(paraid);
+            }
+        });
+    }
+
+    /// Account holding netted fees until the end of the epoch
+    pub fn pot() -> T::AccountId {
+        T::PotId::get().into_account()
+    }
+
+    /// Closes the epoch, its ledgers are settled in batches while blocks have weight to spare
+    pub(crate) fn close_epoch() -> Weight {
+        if !PendingSettlements::<T>::get().is_empty() {
+            // The epoch runs on until the previous one is settled
+            return T::DbWeight::get().reads(1);
+        }
+        let epoch = Epoch::<T>::get();
+        PendingSettlements::<T>::put(NettingDestinations::<T>::take());
+        SettlingEpoch::<T>::put(epoch);
+        Epoch::<T>::put(epoch.wrapping_add(1));
+        T::DbWeight::get().reads_writes(3, 4)
+    }
+
+    /// Settles the ledgers of the closed epoch, one transfer per batch of entries. A
+    /// service parachain the settlement fails with is carried over to the next epoch
+    pub(crate) fn settle_pending(now: T::BlockNumber, remaining_weight: Weight) -> Weight {
+        let mut used = T::DbWeight::get().reads(1);
+        let mut pending = PendingSettlements::<T>::get();
+        if pending.is_empty() {
+            return used;
+        }
+
+        let batch = T::SettlementBatch::get();
+        let batch_weight = T::WeightInfo::settle_epoch(batch);
+        let epoch = SettlingEpoch::<T>::get();
+        while let Some(&paraid) = pending.first() {
+            if used.saturating_add(batch_weight) > remaining_weight {
+                break;
+            }
+            used += batch_weight;
+
+            let ledger: Vec<_> = Ledgers::<T>::iter_prefix(paraid)
+                .take(batch as usize)
+                .collect();
+            if (ledger.len() as u32) < batch {
+                pending.remove(0);
+            }
+            if ledger.is_empty() {
+                continue;
+            }
+            for (device, _) in &ledger {
+                Ledgers::<T>::remove(paraid, device);
+            }
+
+            // Checked first, the transfer is not made unless the reconciliation can follow
+            let settled = Self::ensure_can_send(paraid, MessageKind::Reconcile)
+                .and_then(|_| Self::settle_para(paraid, &ledger, now));
+            match settled {
+                Ok(record) => {
+                    Self::deposit_event(Event::EpochSettled(paraid, epoch, record.total));
+                    Settlements::<T>::mutate(paraid, epoch, |settled| {
+                        let settled = settled.get_or_insert_with(Default::default);
+                        settled.total += record.total;
+                        settled.penalties += record.penalties;
+                        settled.devices += record.devices;
+                        settled.settled_at = record.settled_at;
+                    });
+                    let reconcile = ledger
+                        .into_iter()
+                        .map(|(device, entry)| (device, entry.fees, entry.penalties))
+                        .collect();
+                    Self::try_send(paraid, XCMPMessageOf::<T>::Reconcile(epoch, reconcile));
+                }
+                Err(e) => {
+                    log::error!("settlement with {:?} return {:?}", paraid, e);
+                    for (device, entry) in ledger {
+                        Self::book(paraid, &device, |booked| {
+                            booked.fees += entry.fees;
+                            booked.penalties += entry.penalties;
+                        });
+                    }
+                    if pending.first() == Some(&paraid) {
+                        pending.remove(0);
+                    }
+                    Self::deposit_event(Event::EpochSettlementFailed(paraid, epoch));
+                }
+            }
+        }
+        PendingSettlements::<T>::put(pending);
+        used
+    }
+
+    /// Moves the fees of the ledger into the reserve held by the sovereign account of the
+    /// service parachain and deposits them to the devices there with a single message
+    #[transactional]
+    fn settle_para(
+        paraid: ParaId,
+        ledger: &[(T::AccountId, LedgerEntryOf<T>)],
+        now: T::BlockNumber,
+    ) -> Result<SettlementRecordOf<T>, DispatchError> {
+        let dest = MultiLocation::X2(Junction::Parent, Junction::Parachain { id: paraid.into() });
+        let reserve = T::AccountIdConverter::from_location(&dest).ok_or(Error::<T>::BadLocation)?;
+
+        let mut record = SettlementRecordOf::<T> {
+            devices: ledger.len() as u32,
+            settled_at: now,
+            ..Default::default()
+        };
+        let mut effects = Vec::new();
+        for (device, entry) in ledger {
+            // Penalties were paid to clients out of the pot already
+            let net = entry.fees.saturating_sub(entry.penalties);
+            record.total += net;
+            record.penalties += entry.penalties;
+            if net.is_zero() {
+                continue;
+            }
+            let beneficiary = T::AccountIdConverter::try_into_location(device.clone())
+                .map_err(|_| Error::<T>::BadLocation)?;
+            effects.push(XcmOrder::DepositAsset {
+                assets: vec![MultiAsset::ConcreteFungible {
+                    id: T::FeeAssetLocation::get(),
+                    amount: net.saturated_into(),
+                }],
+                dest: beneficiary,
+            });
+        }
+        if record.total.is_zero() {
+            return Ok(record);
+        }
+
+        T::Currency::transfer(
+            &Self::pot(),
+            &reserve,
+            record.total,
+            ExistenceRequirement::AllowDeath,
+        )?;
+        let xcm = Xcm::ReserveAssetDeposit {
+            assets: vec![MultiAsset::ConcreteFungible {
+                id: T::FeeAssetLocation::get(),
+                amount: record.total.saturated_into(),
+            }],
+            effects,
+        };
+        T::XcmSender::send_xcm(dest, xcm).map_err(|_| Error::<T>::CannotReachDestination)?;
+        Ok(record)
+    }
+
+    /// Agrees on the highest protocol version both parachains support
+    fn on_handshake(sender: ParaId, version: ProtocolVersion) -> DispatchResult {
+        let agreed = version.min(PROTOCOL_VERSION);
+        Self::send_message(sender, XCMPMessageOf::<T>::HandshakeAck(agreed))?;
+
+        ProtocolVersions::<T>::insert(sender, agreed);
+        Self::deposit_event(Event::HandshakeCompleted(sender, agreed));
+        Ok(())
+    }
+
+    fn on_handshake_ack(sender: ParaId, version: ProtocolVersion) -> DispatchResult {
+        if version > PROTOCOL_VERSION {
+            return Err(Error::<T>::BadProtocolVersion.into());
+        }
+
+        ProtocolVersions::<T>::insert(sender, version);
+        Self::deposit_event(Event::HandshakeCompleted(sender, version));
+        Ok(())
+    }
+
+    fn on_ack(sender: ParaId, nonce: Nonce) -> DispatchResult {
+        // Duplicated acknowledgements of resent messages are ignored
+        if Self::take_unacked(sender, nonce).is_some() {
+            Self::deposit_event(Event::MessageAcked(sender, nonce));
+        }
+        Ok(())
+    }
+
+    fn on_nack(sender: ParaId, nonce: Nonce) -> DispatchResult {
+        if let Some(pending) = Self::take_unacked(sender, nonce) {
+            Self::deposit_event(Event::MessageNacked(sender, nonce, pending.kind));
+        }
         Ok(())
     }
 
+    /// Discards the unacknowledged amendment of a closed order, releasing its extra fee
+    fn drop_amendment(order: &OrderOf<T>, device: &T::AccountId) {
+        if let Some(amendment) = Amendments::<T>::take(device) {
+            T::Currency::unreserve(&order.client, amendment.fee - order.fee);
+        }
+    }
+
+    fn next_order_id() -> OrderId {
+        NextOrderId::<T>::mutate(|id| {
+            let next = *id;
+            *id = id.wrapping_add(1);
+            next
+        })
+    }
+
+    fn is_overdue(order: &OrderOf<T>, now: &MomentOf<T>) -> bool {
+        order.is_overdue(now, RelayParentNumber::<T>::get())
+    }
+
+    /// Checks the order deadline leaves the device at least one work circle
+    fn leaves_wcd(
+        until: MomentOf<T>,
+        until_relay: Option<RelayBlockNumber>,
+        now: MomentOf<T>,
+        wcd: MomentOf<T>,
+    ) -> bool {
+        pallet_common::leaves_wcd(
+            until,
+            until_relay,
+            now,
+            wcd,
+            RelayParentNumber::<T>::get(),
+            T::RelayBlockDuration::get(),
+        )
+    }
+
+    /// Device profile, used by the runtime API
+    pub fn device_info(device: &T::AccountId) -> Option<DeviceInfo<BalanceOf<T>, MomentOf<T>>> {
+        Device::<T>::get(device).map(|dev| DeviceInfo {
+            state: dev.state,
+            penalty: dev.penalty,
+            wcd: dev.wcd,
+            paraid: Some(dev.paraid.into()),
+        })
+    }
+
+    /// Current order of the device, used by the runtime API
+    pub fn order_info(
+        device: &T::AccountId,
+    ) -> Option<Order<T::OrderPayload, BalanceOf<T>, MomentOf<T>, T::AccountId, u32>> {
+        Orders::<T>::get(device).map(|order| Order {
+            id: order.id,
+            until: order.until,
+            until_relay: order.until_relay,
+            data: order.data,
+            fee: order.fee,
+            client: order.client,
+            paraid: order.paraid.into(),
+        })
+    }
+
+    /// Devices ready to accept an order
+    pub fn ready_devices() -> Vec<T::AccountId> {
+        Device::<T>::iter()
+            .filter(|(_, dev)| dev.state == DeviceState::Ready)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Quotes a prospective order, `dispatch_fee` is the fee of the `order` extrinsic
+    pub fn quote_order(
+        order: &OrderBaseOf<T>,
+        dispatch_fee: BalanceOf<T>,
+    ) -> Option<OrderQuote<BalanceOf<T>, MomentOf<T>>> {
+        let dev = Device::<T>::get(&order.device)?;
+        let now = Timestamp::<T>::get();
+
+        let available = dev.state == DeviceState::Ready
+            && !Orders::<T>::contains_key(&order.device)
+            && !order.is_overdue(&now, RelayParentNumber::<T>::get())
+            && Self::leaves_wcd(order.until, order.until_relay, now, dev.wcd);
+
+        Some(OrderQuote {
+            fee: order.fee,
+            penalty: dev.penalty,
+            dispatch_fee,
+            min_until: now.saturating_add(dev.wcd),
+            available,
+        })
+    }
+
     fn order_reject(
         who: T::AccountId,
         order: &OrderOf<T>,
         now: T::Moment,
         device: T::AccountId,
         dev: &mut DeviceProfile<T>,
+        cancelled: bool,
     ) -> DispatchResult {
-        T::Currency::unreserve(&who, order.fee);
+        Self::drop_amendment(order, &device);
+        let unrefunded = T::Currency::unreserve(&who, order.fee);
+        let (penalty, slashed) = Self::settle_penalty(order, &now, &order.client, &device, dev)?;
+        let settlement = SettlementOf::<T> {
+            paid: Zero::zero(),
+            refunded: order.fee - unrefunded,
+            penalty,
+            slashed,
+        };
 
-        if now < order.until {
-            T::Currency::unreserve(&device, dev.penalty);
+        Self::close_order(&device);
+        Device::<T>::insert(&device, &*dev);
+
+        Self::deposit_event(if cancelled {
+            Event::Cancelled(order.id, who, device, dev.paraid, order.fee, settlement)
         } else {
-            T::Currency::repatriate_reserved(&device, &order.client, dev.penalty, Free)?;
+            Event::Reject(order.id, who, device, dev.paraid, order.fee, settlement)
+        });
+        Ok(())
+    }
+}
+
+impl<T: Config> From<MessagingEvent<ParaId>> for Event<T> {
+    fn from(event: MessagingEvent<ParaId>) -> Self {
+        match event {
+            MessagingEvent::MessageDeferred(dest, kind) => Event::MessageDeferred(dest, kind),
+            MessagingEvent::MessageDelivered(dest, kind) => Event::MessageDelivered(dest, kind),
+            MessagingEvent::MessageAbandoned(dest, kind, attempts) => {
+                Event::MessageAbandoned(dest, kind, attempts)
+            }
+            MessagingEvent::MessageRefused(dest, kind) => Event::MessageRefused(dest, kind),
+            MessagingEvent::MessageUnacknowledged(dest, nonce, kind, resends) => {
+                Event::MessageUnacknowledged(dest, nonce, kind, resends)
+            }
+            MessagingEvent::UnackedAbandoned(dest, nonce, kind) => {
+                Event::UnackedAbandoned(dest, nonce, kind)
+            }
+            MessagingEvent::SequenceGap(sender, expected, received) => {
+                Event::SequenceGap(sender, expected, received)
+            }
+            MessagingEvent::DuplicateDropped(sender, nonce) => {
+                Event::DuplicateDropped(sender, nonce)
+            }
+            MessagingEvent::MessageQueued(sender, kind) => Event::MessageQueued(sender, kind),
+            MessagingEvent::MessageDropped(sender, kind) => Event::MessageDropped(sender, kind),
         }
+    }
+}
 
-        Orders::<T>::remove(&device);
-        Device::<T>::insert(&device, &*dev);
+impl<T: Config> From<MessagingError> for Error<T> {
+    fn from(error: MessagingError) -> Self {
+        match error {
+            MessagingError::OutboxFull => Error::OutboxFull,
+            MessagingError::TooManyUnacked => Error::TooManyUnacked,
+        }
+    }
+}
 
-        Self::deposit_event(Event::Reject(who, device));
-        Ok(())
+impl<T: Config> Messaging for Pallet<T> {
+    type Runtime = T;
+    type ParaId = ParaId;
+    type Message = XCMPMessageOf<T>;
+    type SendError = MessageSendError;
+    type Error = Error<T>;
+    type AckTimeout = T::AckTimeout;
+    type MaxUnacked = T::MaxUnacked;
+    type MaxSendAttempts = T::MaxSendAttempts;
+    type MaxOutboxMessages = T::MaxOutboxMessages;
+    type RetryBackoff = T::RetryBackoff;
+    type MaxPausedMessages = T::MaxPausedMessages;
+    type ProtocolVersions = ProtocolVersions<T>;
+    type NextNonce = NextNonce<T>;
+    type Unacked = Unacked<T>;
+    type UnackedCount = UnackedCount<T>;
+    type UnackedDestinations = UnackedDestinations<T>;
+    type UnackedCursor = UnackedCursor<T>;
+    type InboundSequence = InboundSequence<T>;
+    type InboundRefused = InboundRefused<T>;
+    type Outbox = Outbox<T>;
+    type OutboxBounds = OutboxBounds<T>;
+    type OutboxDestinations = OutboxDestinations<T>;
+    type OutboxCursor = OutboxCursor<T>;
+    type Pauses = Pauses<T>;
+    type PausedMessages = PausedMessages<T>;
+    type PausedMessagesBounds = PausedMessagesBounds<T>;
+
+    fn deposit_messaging_event(event: MessagingEvent<ParaId>) {
+        Self::deposit_event(event.into());
+    }
+
+    fn send_blob_message(dest: ParaId, blob: Vec<u8>) -> Result<(), MessageSendError> {
+        T::XcmpMessageSender::send_blob_message(dest, blob, ServiceQuality::Ordered)
+    }
+
+    /// Worst case weight of the message handler
+    fn message_weight(msg: &XCMPMessageOf<T>) -> Weight {
+        match msg {
+            XCMPMessageOf::<T>::OrderAccept(..) => T::WeightInfo::on_accept(),
+            XCMPMessageOf::<T>::OrderReject(..) => T::WeightInfo::on_reject(),
+            XCMPMessageOf::<T>::OrderDone(..) => T::WeightInfo::on_done(),
+            XCMPMessageOf::<T>::OrderProgress(..) => T::WeightInfo::on_progress(),
+            XCMPMessageOf::<T>::OrderAmendAccept(..) => T::WeightInfo::on_amend_accept(),
+            XCMPMessageOf::<T>::OrderFailed(..) => T::WeightInfo::on_failed(),
+            XCMPMessageOf::<T>::OrderCancelRejected(..) => T::WeightInfo::on_cancel_rejected(),
+            XCMPMessageOf::<T>::OrderCancelled(..) => T::WeightInfo::on_cancelled(),
+            XCMPMessageOf::<T>::OrderAmendReject(..) => T::WeightInfo::on_amend_reject(),
+            XCMPMessageOf::<T>::DeviceAnnounce(..)
+            | XCMPMessageOf::<T>::DeviceUpdate(..)
+            | XCMPMessageOf::<T>::DeviceStateChanged(..) => T::WeightInfo::on_device_sync(),
+            XCMPMessageOf::<T>::DeviceSnapshot(_, devices) => {
+                T::WeightInfo::on_device_snapshot(devices.len() as u32)
+            }
+            XCMPMessageOf::<T>::DevicePolicy(_, _, policy) => {
+                T::WeightInfo::on_device_policy(policy.entries() as u32)
+            }
+            XCMPMessageOf::<T>::Handshake(..) | XCMPMessageOf::<T>::HandshakeAck(..) => {
+                T::WeightInfo::on_handshake()
+            }
+            XCMPMessageOf::<T>::Ack(..) | XCMPMessageOf::<T>::Nack(..) => T::WeightInfo::on_ack(),
+            _ => T::WeightInfo::on_bad_message(),
+        }
+    }
+
+    fn process_message(sender: ParaId, msg: XCMPMessageOf<T>) -> DispatchResult {
+        let kind = msg.kind();
+        let result = match msg {
+            XCMPMessageOf::<T>::OrderAccept(id, client, devid) => {
+                Self::on_accept(sender, id, client, devid)
+            }
+            XCMPMessageOf::<T>::OrderReject(id, client, devid, onoff) => {
+                Self::on_reject(sender, id, client, devid, onoff)
+            }
+            XCMPMessageOf::<T>::OrderDone(id, client, devid, onoff) => {
+                Self::on_done(sender, id, client, devid, onoff)
+            }
+            XCMPMessageOf::<T>::OrderProgress(id, client, devid, telemetry) => {
+                Self::on_progress(sender, id, client, devid, telemetry)
+            }
+            XCMPMessageOf::<T>::OrderAmendAccept(id, client, devid) => {
+                Self::on_amend_accept(sender, id, client, devid)
+            }
+            XCMPMessageOf::<T>::OrderFailed(id, client, devid) => {
+                Self::on_failed(sender, id, client, devid)
+            }
+            XCMPMessageOf::<T>::OrderCancelRejected(id, client, devid) => {
+                Self::on_cancel_rejected(sender, id, client, devid)
+            }
+            XCMPMessageOf::<T>::OrderCancelled(id, client, devid) => {
+                Self::on_cancelled(sender, id, client, devid)
+            }
+            XCMPMessageOf::<T>::OrderAmendReject(id, client, devid) => {
+                Self::on_amend_reject(sender, id, client, devid)
+            }
+            XCMPMessageOf::<T>::DeviceAnnounce(seq, devid, penalty, wcd, state) => {
+                Self::on_device_announce(sender, seq, devid, penalty, wcd, state)
+            }
+            XCMPMessageOf::<T>::DeviceUpdate(seq, devid, penalty, wcd) => {
+                Self::on_device_update(sender, seq, devid, penalty, wcd)
+            }
+            XCMPMessageOf::<T>::DeviceStateChanged(seq, devid, state) => {
+                Self::on_device_state(sender, seq, devid, state)
+            }
+            XCMPMessageOf::<T>::DevicePolicy(seq, devid, policy) => {
+                Self::on_device_policy(sender, seq, devid, policy)
+            }
+            XCMPMessageOf::<T>::DeviceSnapshot(next, devices) => {
+                Self::on_device_snapshot(sender, next, devices)
+            }
+            XCMPMessageOf::<T>::Handshake(version) => Self::on_handshake(sender, version),
+            XCMPMessageOf::<T>::HandshakeAck(version) => Self::on_handshake_ack(sender, version),
+            XCMPMessageOf::<T>::Ack(nonce) => Self::on_ack(sender, nonce),
+            XCMPMessageOf::<T>::Nack(nonce) => Self::on_nack(sender, nonce),
+            _ => Err(Error::<T>::UnexpectedMessage.into()),
+        };
+        match result {
+            Ok(_) => log::info!("{:?} processed", kind),
+            Err(e) => log::error!("{:?} processing return {:?}", kind, e),
+        }
+        result
+    }
+
+    fn resend_message_weight() -> Weight {
+        T::WeightInfo::resend_message()
+    }
+
+    fn retry_message_weight() -> Weight {
+        T::WeightInfo::retry_message()
+    }
+
+    fn queue_message_weight() -> Weight {
+        T::WeightInfo::on_queue_message()
     }
 }
 
@@ -354,46 +1944,64 @@ impl<T: Config> OnKilledAccount<T::AccountId> for Pallet<T> {
     }
 }
 
+impl<T: Config> OnValidationData for Pallet<T> {
+    fn on_validation_data(data: &PersistedValidationData) {
+        RelayParentNumber::<T>::put(data.relay_parent_number);
+    }
+}
+
 impl<T: Config> XcmpMessageHandler for Pallet<T> {
     fn handle_xcm_message(sender: ParaId, _sent_at: relay_chain::BlockNumber, xcm: VersionedXcm) {
         let hash = xcm.using_encoded(T::Hashing::hash);
         log::debug!("Processing HRMP XCM: {:?}", &hash);
-        match Xcm::try_from(xcm) {
-            Ok(xcm) => {
-                // let location = (
-                //     Junction::Parent,
-                //     Junction::Parachain { id: sender.into() },
-                // );
-                // match T::XcmExecutor::execute_xcm(location.into(), xcm) {
-                //     Ok(..) => RawEvent::Success(hash),
-                //     Err(e) => RawEvent::Fail(hash, e),
-                // }
-            }
-            Err(..) => Self::deposit_event(Event::BadVersion(hash)),
+        let (event, weight) = match Xcm::try_from(xcm) {
+            Ok(xcm) => match T::XcmWeigher::weigh(&xcm) {
+                Some(weight) if weight <= T::MaxXcmWeight::get() => {
+                    let location = MultiLocation::X2(
+                        Junction::Parent,
+                        Junction::Parachain { id: sender.into() },
+                    );
+                    match T::XcmExecutor::execute_xcm(location, xcm) {
+                        Ok(()) => (Event::XcmSuccess(hash, weight), weight),
+                        Err(e) => (Event::XcmFail(hash, e), weight),
+                    }
+                }
+                _ => (Event::XcmOverweight(hash), 0),
+            },
+            Err(..) => (Event::BadVersion(hash), 0),
         };
+        Self::deposit_event(event);
+        // Inbound messages are processed outside of any extrinsic
+        frame_system::Pallet::<T>::register_extra_weight_unchecked(
+            weight,
+            DispatchClass::Mandatory,
+        );
     }
-    fn handle_blob_message(_sender: ParaId, _sent_at: relay_chain::BlockNumber, blob: Vec<u8>) {
+    fn handle_blob_message(sender: ParaId, _sent_at: relay_chain::BlockNumber, blob: Vec<u8>) {
         log::warn!("Processing Blob XCM: {:?}", blob);
-        match XCMPMessageOf::<T>::decode(&mut blob.as_slice()) {
+        let weight = match XCMPMessageOf::<T>::from_wire(&blob) {
             Err(e) => {
                 log::error!("{:?}", e);
-                return;
-            }
-            Ok(XCMPMessageOf::<T>::OrderAccept(client, devid)) => {
-                Self::on_accept(client, devid);
-                log::info!("OrderAccept");
+                T::WeightInfo::on_bad_message()
             }
-            Ok(XCMPMessageOf::<T>::OrderReject(client, devid, onoff)) => {
-                Self::on_reject(client, devid, onoff);
-                log::info!("OrderReject");
+            Ok((_, Some(nonce), _)) if !Self::check_sequence(sender, nonce) => {
+                T::WeightInfo::on_duplicate()
             }
-            Ok(XCMPMessageOf::<T>::OrderDone(cliend, devid, onoff)) => {
-                Self::on_done(cliend, devid, onoff);
-                log::info!("OrderDone");
+            Ok((_, nonce, msg)) if Self::is_paused(sender, msg.kind()) => {
+                Self::acknowledge(sender, nonce, Ok(()));
+                Self::queue_paused(sender, msg)
             }
-            Ok(_) => {
-                log::warn!("unknown XCM message received");
+            Ok((_, nonce, msg)) => {
+                let weight = Self::message_weight(&msg);
+                let result = Self::process_message(sender, msg);
+                Self::acknowledge(sender, nonce, result);
+                weight
             }
         };
+        // Inbound messages are processed outside of any extrinsic
+        frame_system::Pallet::<T>::register_extra_weight_unchecked(
+            weight,
+            DispatchClass::Mandatory,
+        );
     }
 }
@@ -0,0 +1,141 @@
+//! Storage migrations of the client pallet
+use super::*;
+use frame_support::{traits::PalletInfo, weights::Weight};
+use pallet_common::migrations as common;
+
+/// Runs all pending migrations
+pub fn migrate<T: Config>() -> Weight {
+    let mut weight = 0;
+
+    if StorageVersion::<T>::get() == Releases::V0 {
+        weight += v1::migrate::<T>();
+    }
+    if StorageVersion::<T>::get() == Releases::V1 {
+        weight += v2::migrate::<T>();
+    }
+    weight
+}
+
+#[cfg(any(feature = "try-runtime", test))]
+pub fn pre_migrate<T: Config>() -> Result<(), &'static str> {
+    match StorageVersion::<T>::get() {
+        Releases::V0 => v1::pre_migrate::<T>(),
+        Releases::V1 => v2::pre_migrate::<T>(),
+        _ => (),
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "try-runtime", test))]
+pub fn post_migrate<T: Config>() -> Result<(), &'static str> {
+    if common::is_counted(pallet_name::<T>(), 1) {
+        v1::post_migrate::<T>()?;
+    }
+    if common::is_counted(pallet_name::<T>(), 2) {
+        v2::post_migrate::<T>()?;
+    }
+    Ok(())
+}
+
+fn pallet_name<T: Config>() -> &'static [u8] {
+    <T as frame_system::Config>::PalletInfo::name::<Pallet<T>>()
+        .expect("pallet is part of the runtime; qed")
+        .as_bytes()
+}
+
+/// Moves unversioned storage to `Releases::V1`
+pub mod v1 {
+    use super::*;
+
+    pub type OldOrderOf<T> = common::OrderV0<
+        <T as Config>::OrderPayload,
+        BalanceOf<T>,
+        MomentOf<T>,
+        <T as frame_system::Config>::AccountId,
+        ParaId,
+    >;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let translated = common::translate_orders::<T::AccountId, OldOrderOf<T>, _>(
+            pallet_name::<T>(),
+            |_, old| old.upgrade(),
+        );
+        // DeviceProfile layout is unchanged
+        StorageVersion::<T>::put(Releases::V1);
+        log::info!("client storage migrated to V1, {} orders", translated);
+
+        T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+    }
+
+    #[cfg(any(feature = "try-runtime", test))]
+    pub fn pre_migrate<T: Config>() {
+        common::count_orders::<T::AccountId, OldOrderOf<T>>(pallet_name::<T>(), 1);
+    }
+
+    #[cfg(any(feature = "try-runtime", test))]
+    pub fn post_migrate<T: Config>() -> Result<(), &'static str> {
+        if StorageVersion::<T>::get() < Releases::V1 {
+            return Err("storage version is not updated");
+        }
+        common::verify_orders::<T::AccountId, OrderOf<T>>(pallet_name::<T>(), 1)?;
+        if Orders::<T>::iter().any(|(device, _)| !Device::<T>::contains_key(&device)) {
+            return Err("order of unknown device");
+        }
+        Ok(())
+    }
+}
+
+/// Assigns order ids counting from zero per service parachain, moves to `Releases::V2`
+pub mod v2 {
+    use super::*;
+
+    pub type OldOrderOf<T> = common::OrderV1<
+        <T as Config>::OrderPayload,
+        BalanceOf<T>,
+        MomentOf<T>,
+        <T as frame_system::Config>::AccountId,
+        ParaId,
+    >;
+
+    /// Service parachain hosting the device
+    fn service_of<T: Config>(device: &T::AccountId) -> ParaId {
+        Device::<T>::get(device)
+            .map(|dev| dev.paraid)
+            .unwrap_or_default()
+    }
+
+    pub fn migrate<T: Config>() -> Weight {
+        // The service parachain assigns the same ids to the orders in its migration
+        let mut ids = common::ParaOrderIds::default();
+
+        let translated = common::translate_orders::<T::AccountId, OldOrderOf<T>, _>(
+            pallet_name::<T>(),
+            |device, old| old.upgrade(ids.next(service_of::<T>(device))),
+        );
+        NextOrderId::<T>::mutate(|next_id| *next_id = (*next_id).max(ids.end()));
+        StorageVersion::<T>::put(Releases::V2);
+        log::info!("client storage migrated to V2, {} orders", translated);
+
+        T::DbWeight::get().reads_writes(2 * translated + 1, translated + 2)
+    }
+
+    #[cfg(any(feature = "try-runtime", test))]
+    pub fn pre_migrate<T: Config>() {
+        common::count_orders::<T::AccountId, OldOrderOf<T>>(pallet_name::<T>(), 2);
+    }
+
+    #[cfg(any(feature = "try-runtime", test))]
+    pub fn post_migrate<T: Config>() -> Result<(), &'static str> {
+        if StorageVersion::<T>::get() != Releases::V2 {
+            return Err("storage version is not updated");
+        }
+        common::verify_orders::<T::AccountId, OrderOf<T>>(pallet_name::<T>(), 2)?;
+        let next_id = NextOrderId::<T>::get();
+        if Orders::<T>::iter().any(|(_, order)| order.id >= next_id) {
+            return Err("order ids are not unique");
+        }
+        common::verify_para_order_ids(
+            Orders::<T>::iter().map(|(device, order)| (service_of::<T>(&device), order.id)),
+        )
+    }
+}
@@ -1,7 +1,7 @@
 #![allow(clippy::from_over_into)]
 
 use crate as pallet_client;
-use frame_support::parameter_types;
+use frame_support::{parameter_types, weights::Weight};
 use frame_system as system;
 use frame_system::EnsureRoot;
 use sp_core::H256;
@@ -16,13 +16,20 @@ use xcm_builder::{
     SovereignSignedViaLocation,
 };
 
-use xcm::v0::{Junction, MultiLocation, NetworkId};
+use xcm::v0::{Error as XcmError, Junction, MultiLocation, NetworkId, SendXcm, Xcm};
 use xcm_executor::traits::{IsConcrete, NativeAsset};
 use xcm_executor::{Config, XcmExecutor};
 
 use polkadot_parachain::primitives::Id as ParaId;
 use polkadot_parachain::primitives::Sibling;
 
+use cumulus_primitives_core::{MessageSendError, ServiceQuality, XcmpMessageSender};
+use sp_runtime::ModuleId;
+use std::cell::RefCell;
+
+pub const MILLISECS_PER_BLOCK: u64 = 12000;
+pub const SLOT_DURATION: u64 = MILLISECS_PER_BLOCK;
+
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
 type Block = frame_system::mocking::MockBlock<Test>;
 pub type Signature = MultiSignature;
@@ -39,6 +46,10 @@ parameter_types! {
     }.into();
 
     pub SelfParaId: ParaId = ParaId::from(999);
+    pub FeeAssetLocation: MultiLocation = MultiLocation::X2(
+        Junction::Parent,
+        Junction::Parachain { id: 999 },
+    );
 }
 
 type LocationConverter = (
@@ -105,7 +116,8 @@ frame_support::construct_runtime!(
         XcmHandler: cumulus_pallet_xcm_handler::{Event<T>, Origin},
         ParachainSystem: cumulus_pallet_parachain_system::{Module, Call, Storage, Inherent, Event},
         //XcmHandler: cumulus_pallet_xcm_handler::{Module, Event<T>, Origin},
-        TemplateModule: pallet_template::{Module, Call, Storage, Event<T>},
+        Timestamp: pallet_timestamp::{Module, Call, Storage, Inherent},
+        ClientModule: pallet_client::{Module, Call, Storage, Event<T>},
     }
 );
 
@@ -156,15 +168,149 @@ impl system::Config for Test {
     type SS58Prefix = SS58Prefix;
 }
 
+parameter_types! {
+    pub const MinimumPeriod: u64 = SLOT_DURATION / 2;
+}
+
+impl pallet_timestamp::Config for Test {
+    /// A timestamp: milliseconds since the unix epoch.
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const SettlementEpoch: u64 = 10;
+    pub const SettlementBatch: u32 = 2;
+    pub const PotId: ModuleId = ModuleId(*b"rbx/pot_");
+    pub const RelayBlockDuration: u64 = 6000;
+    pub const MaxPolicyEntries: u32 = 2;
+    pub const MaxPausedMessages: u32 = 2;
+    pub const MaxSendAttempts: u32 = 3;
+    pub const MaxOutboxMessages: u32 = 2;
+    pub const RetryBackoff: u64 = 2;
+    pub const AckTimeout: u64 = 5;
+    pub const MaxUnacked: u32 = 4;
+    pub const XcmInstructionWeight: Weight = 1_000;
+    pub const MaxXcmWeight: Weight = 1_000_000;
+    pub const CancelTimeout: u64 = 5000;
+}
+
+thread_local! {
+    /// Blobs handed over to the channel
+    pub static SENT: RefCell<Vec<(ParaId, Vec<u8>)>> = RefCell::new(vec![]);
+    /// Fee transfers handed over to the XCM router
+    pub static SENT_XCM: RefCell<Vec<(MultiLocation, Xcm)>> = RefCell::new(vec![]);
+    /// Refuse all sends while set
+    pub static CHANNEL_CLOSED: RefCell<bool> = RefCell::new(false);
+}
+
+pub struct XcmpSender;
+impl XcmpMessageSender for XcmpSender {
+    fn send_blob_message<T: Into<ParaId>>(
+        dest: T,
+        msg: Vec<u8>,
+        _qos: ServiceQuality,
+    ) -> Result<u32, MessageSendError> {
+        if CHANNEL_CLOSED.with(|closed| *closed.borrow()) {
+            return Err(MessageSendError::NoChannel);
+        }
+        SENT.with(|sent| sent.borrow_mut().push((dest.into(), msg)));
+        Ok(0)
+    }
+}
+
+pub struct XcmSender;
+impl SendXcm for XcmSender {
+    fn send_xcm(dest: MultiLocation, msg: Xcm) -> Result<(), XcmError> {
+        if CHANNEL_CLOSED.with(|closed| *closed.borrow()) {
+            return Err(XcmError::CannotReachDestination);
+        }
+        SENT_XCM.with(|sent| sent.borrow_mut().push((dest, msg)));
+        Ok(())
+    }
+}
+
+pub fn close_channel(closed: bool) {
+    CHANNEL_CLOSED.with(|c| *c.borrow_mut() = closed);
+}
+
+pub fn sent_messages() -> Vec<(ParaId, Vec<u8>)> {
+    SENT.with(|sent| sent.borrow().clone())
+}
+
+pub fn sent_xcm() -> Vec<(MultiLocation, Xcm)> {
+    SENT_XCM.with(|sent| sent.borrow().clone())
+}
+
+/// Order payloads are byte vectors of the requested length
+#[cfg(feature = "runtime-benchmarks")]
+pub struct PayloadOfSize;
+#[cfg(feature = "runtime-benchmarks")]
+impl sp_runtime::traits::Convert<u32, Vec<u8>> for PayloadOfSize {
+    fn convert(s: u32) -> Vec<u8> {
+        vec![0xff; s as usize]
+    }
+}
+
 impl pallet_client::Config for Test {
     type Event = Event;
-    type XcmSender = ();
+    type XcmSender = XcmSender;
+    type XcmpMessageSender = XcmpSender;
+    type XcmExecutor = XcmExecutor<XcmConfig>;
+    type XcmWeigher = pallet_common::FixedXcmWeight<XcmInstructionWeight, Call>;
+    type MaxXcmWeight = MaxXcmWeight;
+    type AccountIdConverter = LocationConverter;
+    type FeeAssetLocation = FeeAssetLocation;
+    type SettlementEpoch = SettlementEpoch;
+    type SettlementBatch = SettlementBatch;
+    type PotId = PotId;
+    type OrderPayload = Vec<u8>;
+    #[cfg(feature = "runtime-benchmarks")]
+    type PayloadOfSize = PayloadOfSize;
+    type Currency = Balances;
+    type SelfParaId = SelfParaId;
+    type RelayBlockDuration = RelayBlockDuration;
+    type MaxPolicyEntries = MaxPolicyEntries;
+    type PauseOrigin = EnsureRoot<AccountId>;
+    type MaxPausedMessages = MaxPausedMessages;
+    type MaxSendAttempts = MaxSendAttempts;
+    type MaxOutboxMessages = MaxOutboxMessages;
+    type RetryBackoff = RetryBackoff;
+    type AckTimeout = AckTimeout;
+    type MaxUnacked = MaxUnacked;
+    type CancelTimeout = CancelTimeout;
+    type WeightInfo = ();
+}
+
+static INIT_DATA: [u64; 6] = [1, 2, 100, 101, 200, 201];
+
+pub fn account(id: u64) -> AccountId {
+    let mut b = [0_u8; 32];
+    let id = id.to_ne_bytes();
+
+    unsafe { std::ptr::copy_nonoverlapping(id.as_ptr(), b.as_mut_ptr(), id.len()) };
+    b.into()
 }
 
 // Build genesis storage according to the mock runtime.
 pub fn new_test_ext() -> sp_io::TestExternalities {
-    system::GenesisConfig::default()
+    const INITIAL_BALANCE: Balance = 100_000_000;
+
+    let mut storage = system::GenesisConfig::default()
         .build_storage::<Test>()
-        .unwrap()
-        .into()
+        .unwrap();
+
+    pallet_balances::GenesisConfig::<Test> {
+        // Provide some initial balances
+        balances: INIT_DATA
+            .iter()
+            .map(|&id| (account(id), INITIAL_BALANCE))
+            .collect(),
+    }
+    .assimilate_storage(&mut storage)
+    .unwrap();
+
+    storage.into()
 }
@@ -1,44 +1,898 @@
-use crate::{mock::*, Error};
+use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+use frame_support::dispatch::DispatchResult;
+use frame_support::traits::ReservableCurrency;
 use frame_support::{assert_noop, assert_ok};
-use frame_system::ensure_signed;
 
-fn account(id: u64) -> AccountId {
-    let mut b = [0_u8; 32];
-    let id = id.to_ne_bytes();
+use crate::{mock::*, DeviceProfile, DeviceState, Error, Telemetry, XCMPMessageOf};
+use pallet_common::{Messaging, PROTOCOL_VERSION};
 
-    unsafe { std::ptr::copy_nonoverlapping(id.as_ptr(), b.as_mut_ptr(), id.len()) };
-    b.into()
+const DEV1: u64 = 100;
+const DEV2: u64 = 101;
+
+const CL1: u64 = 200;
+const CL2: u64 = 201;
+
+const SERVICE: u32 = 300;
+const OTHER_SERVICE: u32 = 301;
+
+const PENALTY: Balance = 10_000;
+const DEFAULT_WCD: Moment = 1000;
+const DEFAULT_FEE: Balance = 100_000;
+
+type Moment = <Test as pallet_timestamp::Config>::Moment;
+type OutOrder = crate::OrderBaseOf<Test>;
+
+fn service() -> ParaId {
+    SERVICE.into()
+}
+
+/// Delivers the message sent by the parachain
+fn receive_from(sender: u32, msg: XCMPMessageOf<Test>) {
+    ClientModule::handle_blob_message(sender.into(), 0, msg.to_wire(PROTOCOL_VERSION, None));
+}
+
+fn receive(msg: XCMPMessageOf<Test>) {
+    receive_from(SERVICE, msg)
+}
+
+/// Messages sent to the service parachain
+fn sent() -> Vec<XCMPMessageOf<Test>> {
+    sent_messages()
+        .into_iter()
+        .filter(|(para, _)| *para == service())
+        .filter_map(|(_, blob)| XCMPMessageOf::<Test>::from_wire(&blob).ok())
+        .map(|(_, _, msg)| msg)
+        .collect()
+}
+
+/// Negotiates the protocol with the service parachain and mirrors its device
+fn aux_mirror_device(id: u64) {
+    receive(XCMPMessageOf::<Test>::HandshakeAck(PROTOCOL_VERSION));
+    crate::DeviceParas::<Test>::insert(account(id), service());
+    crate::Device::<Test>::insert(
+        account(id),
+        DeviceProfile {
+            state: DeviceState::Ready,
+            penalty: PENALTY,
+            wcd: DEFAULT_WCD,
+            paraid: service(),
+        },
+    );
+}
+
+/// Acknowledges everything sent to the service parachain
+fn ack_all() {
+    let nonces: Vec<_> = crate::Unacked::<Test>::iter_prefix(service())
+        .map(|(nonce, _)| nonce)
+        .collect();
+    for nonce in nonces {
+        receive(XCMPMessageOf::<Test>::Ack(nonce));
+    }
+}
+
+/// Completes the open order to the device
+fn aux_done(dev_id: u64, client: u64) {
+    let order = ClientModule::orders(account(dev_id)).unwrap();
+    receive(XCMPMessageOf::<Test>::OrderDone(
+        order.id,
+        account(client),
+        account(dev_id),
+        true,
+    ));
+    ack_all();
+}
+
+fn aux_order(dev_id: u64, client: u64) -> DispatchResult {
+    let until = crate::Timestamp::<Test>::now() + DEFAULT_WCD;
+
+    ClientModule::order(
+        Origin::signed(account(client)),
+        OutOrder {
+            until,
+            until_relay: None,
+            data: vec![1, 2, 3],
+            fee: DEFAULT_FEE,
+            device: account(dev_id),
+        },
+    )
+}
+
+fn telemetry(progress: u8) -> Telemetry {
+    Telemetry {
+        progress,
+        position: None,
+        digest: [0; 32],
+    }
+}
+
+#[test]
+fn order_is_sent_to_service() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(aux_order(DEV1, CL1), Error::<Test>::NoDevice);
+        aux_mirror_device(DEV1);
+
+        assert_ok!(aux_order(DEV1, CL1));
+        let order = ClientModule::orders(account(DEV1)).unwrap();
+        assert_eq!(order.client, account(CL1));
+        assert_eq!(order.paraid, service());
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().state,
+            DeviceState::Busy
+        );
+        assert_eq!(Balances::reserved_balance(account(CL1)), DEFAULT_FEE);
+        assert_eq!(Balances::reserved_balance(account(DEV1)), PENALTY);
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::NewOrder(0, client, _)) if client == account(CL1)
+        ));
+
+        assert_noop!(aux_order(DEV1, CL2), Error::<Test>::IllegalState);
+    });
+}
+
+#[test]
+fn order_needs_handshake() {
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        crate::ProtocolVersions::<Test>::remove(service());
+
+        assert_noop!(aux_order(DEV1, CL1), Error::<Test>::NoHandshake);
+    });
 }
 
 #[test]
-fn it_works_for_default_value() {
+fn progress_is_checked() {
     new_test_ext().execute_with(|| {
-        // Dispatch a signed extrinsic.
-        assert_ok!(TemplateModule::do_something(Origin::signed(account(1)), 42));
-        // Read pallet storage and assert an expected result.
-        assert_eq!(TemplateModule::something(), Some(42));
+        aux_mirror_device(DEV1);
+        aux_mirror_device(DEV2);
+        assert_ok!(aux_order(DEV1, CL1));
+
+        // Out of range progress
+        let bad =
+            XCMPMessageOf::<Test>::OrderProgress(0, account(CL1), account(DEV1), telemetry(101));
+        receive(bad);
+        assert!(ClientModule::progress(account(DEV1)).is_none());
+
+        // Reported by a parachain not hosting the device
+        let msg =
+            XCMPMessageOf::<Test>::OrderProgress(0, account(CL1), account(DEV1), telemetry(5));
+        receive_from(OTHER_SERVICE, msg.clone());
+        assert!(ClientModule::progress(account(DEV1)).is_none());
+
+        // Reported for another client
+        let other =
+            XCMPMessageOf::<Test>::OrderProgress(0, account(CL2), account(DEV1), telemetry(50));
+        receive(other);
+        assert!(ClientModule::progress(account(DEV1)).is_none());
+
+        receive(msg);
+        let (_, progress) = ClientModule::progress(account(DEV1)).unwrap();
+        assert_eq!(progress.progress, 5);
+    });
+}
+
+#[test]
+fn failed_order_released() {
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        assert_ok!(aux_order(DEV1, CL1));
+        let failed = XCMPMessageOf::<Test>::OrderFailed(0, account(CL1), account(DEV1));
+
+        // Spoofed by a parachain not hosting the device
+        receive_from(OTHER_SERVICE, failed.clone());
+        assert!(ClientModule::orders(account(DEV1)).is_some());
+        assert_eq!(Balances::reserved_balance(account(CL1)), DEFAULT_FEE);
+
+        receive(failed);
+        assert!(ClientModule::orders(account(DEV1)).is_none());
+        assert_eq!(Balances::reserved_balance(account(CL1)), 0);
+        assert_eq!(Balances::reserved_balance(account(DEV1)), 0);
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().state,
+            DeviceState::Ready
+        );
     });
 }
 
 #[test]
-fn correct_error_for_none_value() {
+fn replies_match_order_id() {
     new_test_ext().execute_with(|| {
-        // Ensure the expected error is thrown when no value is present.
+        aux_mirror_device(DEV1);
+        assert_ok!(aux_order(DEV1, CL1));
+        assert_ok!(ClientModule::cancel(
+            Origin::signed(account(CL1)),
+            account(DEV1)
+        ));
+        receive(XCMPMessageOf::<Test>::OrderCancelled(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+        assert_ok!(aux_order(DEV1, CL1));
+        let order = ClientModule::orders(account(DEV1)).unwrap();
+        assert_eq!(order.id, 1);
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::NewOrder(1, _, _))
+        ));
+
+        // Late reply to the cancelled order leaves the new one open
+        receive(XCMPMessageOf::<Test>::OrderDone(
+            0,
+            account(CL1),
+            account(DEV1),
+            true,
+        ));
+        assert_eq!(ClientModule::orders(account(DEV1)), Some(order));
+
+        receive(XCMPMessageOf::<Test>::OrderAccept(
+            1,
+            account(CL1),
+            account(DEV1),
+        ));
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().state,
+            DeviceState::Busy2
+        );
+    });
+}
+
+#[test]
+fn amend_rejected() {
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        assert_ok!(aux_order(DEV1, CL1));
+        let order = ClientModule::orders(account(DEV1)).unwrap();
+        let amendment = crate::AmendmentOf::<Test> {
+            until: order.until * 2,
+            until_relay: None,
+            fee: DEFAULT_FEE * 2,
+        };
+        assert_ok!(ClientModule::amend_order(
+            Origin::signed(account(CL1)),
+            account(DEV1),
+            amendment
+        ));
+        assert_eq!(Balances::reserved_balance(account(CL1)), DEFAULT_FEE * 2);
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::OrderAmend(0, ..))
+        ));
+
+        receive(XCMPMessageOf::<Test>::OrderAmendReject(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+        assert!(ClientModule::amendments(account(DEV1)).is_none());
+        assert_eq!(Balances::reserved_balance(account(CL1)), DEFAULT_FEE);
+        assert_eq!(ClientModule::orders(account(DEV1)), Some(order));
+    });
+}
+
+#[test]
+fn cancel_waits_for_service() {
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        assert_ok!(aux_order(DEV1, CL1));
+        let order = ClientModule::orders(account(DEV1)).unwrap();
+        let free = Balances::free_balance(account(CL1));
+        assert_ok!(ClientModule::cancel(
+            Origin::signed(account(CL1)),
+            account(DEV1)
+        ));
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::OrderCancel(0, ..))
+        ));
+
+        // Nothing is refunded until the service parachain confirms
+        assert_eq!(ClientModule::orders(account(DEV1)), Some(order.clone()));
+        assert_eq!(Balances::reserved_balance(account(CL1)), DEFAULT_FEE);
+        assert_eq!(Balances::reserved_balance(account(DEV1)), PENALTY);
         assert_noop!(
-            TemplateModule::cause_error(Origin::signed(account(1))),
-            Error::<Test>::NoneValue
+            ClientModule::cancel(Origin::signed(account(CL1)), account(DEV1)),
+            Error::<Test>::CancelPending
+        );
+        let amendment = crate::AmendmentOf::<Test> {
+            until: order.until * 2,
+            until_relay: None,
+            fee: DEFAULT_FEE * 2,
+        };
+        assert_noop!(
+            ClientModule::amend_order(Origin::signed(account(CL1)), account(DEV1), amendment),
+            Error::<Test>::CancelPending
+        );
+
+        // Settled as of the request, though the deadline passed meanwhile
+        crate::Timestamp::<Test>::set_timestamp(order.until + 1);
+        receive(XCMPMessageOf::<Test>::OrderCancelled(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+        assert!(ClientModule::orders(account(DEV1)).is_none());
+        assert!(ClientModule::cancelled(account(DEV1)).is_none());
+        assert!(System::events().iter().any(|record| matches!(
+            record.event,
+            Event::pallet_client(crate::Event::Cancelled(0, ..))
+        )));
+        assert_eq!(Balances::reserved_balance(account(CL1)), 0);
+        assert_eq!(Balances::reserved_balance(account(DEV1)), 0);
+        assert_eq!(Balances::free_balance(account(CL1)), free + DEFAULT_FEE);
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().state,
+            DeviceState::Ready
         );
     });
 }
 
 #[test]
-fn hrmp_origin_converter() {
+fn cancel_rejected_restores_order() {
     new_test_ext().execute_with(|| {
-        for paraid in &[100, 200, 1000] {
-            let origin = TemplateModule::origin_convert(*paraid).unwrap();
-            println!("{} origin: {:?}", *paraid, origin);
-            let account: AccountId = ensure_signed(origin).unwrap();
-            println!("{} account: {:?}", *paraid, account);
+        aux_mirror_device(DEV1);
+        assert_ok!(aux_order(DEV1, CL1));
+        receive(XCMPMessageOf::<Test>::OrderAccept(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+        let order = ClientModule::orders(account(DEV1)).unwrap();
+
+        // Accepted orders are withdrawn only once overdue
+        assert_noop!(
+            ClientModule::cancel(Origin::signed(account(CL1)), account(DEV1)),
+            Error::<Test>::Prohibited
+        );
+        crate::Timestamp::<Test>::set_timestamp(order.until + 1);
+        assert_ok!(ClientModule::cancel(
+            Origin::signed(account(CL1)),
+            account(DEV1)
+        ));
+
+        receive(XCMPMessageOf::<Test>::OrderCancelRejected(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+        assert!(ClientModule::cancelled(account(DEV1)).is_none());
+        assert_eq!(ClientModule::orders(account(DEV1)), Some(order));
+        assert_eq!(Balances::reserved_balance(account(CL1)), DEFAULT_FEE);
+        assert_eq!(Balances::reserved_balance(account(DEV1)), PENALTY);
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().state,
+            DeviceState::Busy2
+        );
+    });
+}
+
+#[test]
+fn late_done_settles_pending_cancel() {
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        assert_ok!(aux_order(DEV1, CL1));
+        assert_ok!(ClientModule::cancel(
+            Origin::signed(account(CL1)),
+            account(DEV1)
+        ));
+
+        // The device finished before the service parachain saw the withdrawal
+        let free = Balances::free_balance(account(DEV1));
+        receive(XCMPMessageOf::<Test>::OrderDone(
+            0,
+            account(CL1),
+            account(DEV1),
+            true,
+        ));
+        assert!(ClientModule::orders(account(DEV1)).is_none());
+        assert_eq!(
+            Balances::free_balance(account(DEV1)),
+            free + DEFAULT_FEE + PENALTY
+        );
+        assert_eq!(Balances::reserved_balance(account(CL1)), 0);
+        assert!(ClientModule::cancelled(account(DEV1)).is_none());
+
+        // The answer to the withdrawal of the closed order changes nothing
+        receive(XCMPMessageOf::<Test>::OrderCancelRejected(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+        assert_ok!(aux_order(DEV1, CL1));
+        receive(XCMPMessageOf::<Test>::OrderCancelled(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+        assert!(ClientModule::orders(account(DEV1)).is_some());
+
+        // The next order can be withdrawn
+        assert_ok!(ClientModule::cancel(
+            Origin::signed(account(CL1)),
+            account(DEV1)
+        ));
+        assert_eq!(
+            ClientModule::cancelled(account(DEV1)).map(|(id, _)| id),
+            Some(1)
+        );
+    });
+}
+
+#[test]
+fn unanswered_cancel_settled_after_timeout() {
+    use frame_support::traits::Get;
+
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        assert_ok!(aux_order(DEV1, CL1));
+        let free = Balances::free_balance(account(CL1));
+        assert_ok!(ClientModule::cancel(
+            Origin::signed(account(CL1)),
+            account(DEV1)
+        ));
+
+        // Confirmations come from the parachain hosting the device only
+        receive_from(
+            OTHER_SERVICE,
+            XCMPMessageOf::<Test>::OrderCancelled(0, account(CL1), account(DEV1)),
+        );
+        assert!(ClientModule::orders(account(DEV1)).is_some());
+
+        crate::Timestamp::<Test>::set_timestamp(CancelTimeout::get() - 1);
+        assert_noop!(
+            ClientModule::cancel(Origin::signed(account(CL1)), account(DEV1)),
+            Error::<Test>::CancelPending
+        );
+
+        // Settled as of the request, the deadline has passed meanwhile
+        crate::Timestamp::<Test>::set_timestamp(CancelTimeout::get());
+        assert_ok!(ClientModule::cancel(
+            Origin::signed(account(CL1)),
+            account(DEV1)
+        ));
+        assert!(ClientModule::orders(account(DEV1)).is_none());
+        assert!(ClientModule::cancelled(account(DEV1)).is_none());
+        assert_eq!(Balances::free_balance(account(CL1)), free + DEFAULT_FEE);
+        assert_eq!(Balances::reserved_balance(account(DEV1)), 0);
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().state,
+            DeviceState::Ready
+        );
+    });
+}
+
+#[test]
+fn device_mirror_bound_to_service() {
+    use crate::Subscription;
+    use frame_support::dispatch::DispatchError;
+
+    new_test_ext().execute_with(|| {
+        for para in [SERVICE, OTHER_SERVICE].iter() {
+            let sub = Subscription {
+                next: 0,
+                syncing: false,
+            };
+            crate::Subscriptions::<Test>::insert(ParaId::from(*para), sub);
+        }
+        let announce = |seq| {
+            XCMPMessageOf::<Test>::DeviceAnnounce(
+                seq,
+                account(DEV1),
+                PENALTY,
+                DEFAULT_WCD,
+                DeviceState::Ready,
+            )
+        };
+
+        // Devices are mirrored only from the parachain governance bound them to
+        receive_from(OTHER_SERVICE, announce(0));
+        assert!(ClientModule::devices(account(DEV1)).is_none());
+        assert_noop!(
+            ClientModule::bind_device(Origin::signed(account(CL1)), account(DEV1), service()),
+            DispatchError::BadOrigin
+        );
+        assert_ok!(ClientModule::bind_device(
+            Origin::root(),
+            account(DEV1),
+            service()
+        ));
+        receive_from(OTHER_SERVICE, announce(1));
+        assert!(ClientModule::devices(account(DEV1)).is_none());
+
+        receive(announce(0));
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().paraid,
+            service()
+        );
+        let off = XCMPMessageOf::<Test>::DeviceStateChanged(2, account(DEV1), DeviceState::Off);
+        receive_from(OTHER_SERVICE, off);
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().state,
+            DeviceState::Ready
+        );
+
+        // Moved to another parachain, the stale profile is dropped until it is announced
+        assert_ok!(ClientModule::bind_device(
+            Origin::root(),
+            account(DEV1),
+            OTHER_SERVICE.into()
+        ));
+        assert!(ClientModule::devices(account(DEV1)).is_none());
+        receive(XCMPMessageOf::<Test>::DeviceStateChanged(
+            1,
+            account(DEV1),
+            DeviceState::Off,
+        ));
+        assert!(ClientModule::devices(account(DEV1)).is_none());
+        receive_from(OTHER_SERVICE, announce(3));
+        assert_eq!(
+            ClientModule::devices(account(DEV1)).unwrap().paraid,
+            OTHER_SERVICE.into()
+        );
+    });
+}
+
+#[test]
+fn registry_resynced_on_gap() {
+    use crate::Subscription;
+    use pallet_common::AccessPolicy;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        receive(XCMPMessageOf::<Test>::HandshakeAck(PROTOCOL_VERSION));
+        for dev in [DEV1, DEV2].iter() {
+            crate::DeviceParas::<Test>::insert(account(*dev), service());
+        }
+        let profile = |id, state| (account(id), PENALTY, DEFAULT_WCD, state, AccessPolicy::Open);
+        let state = |id| ClientModule::devices(account(id)).unwrap().state;
+        let changed = |seq, id, state| {
+            receive(XCMPMessageOf::<Test>::DeviceStateChanged(
+                seq,
+                account(id),
+                state,
+            ))
+        };
+
+        assert_ok!(ClientModule::subscribe(Origin::root(), service()));
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::Subscribe)
+        ));
+
+        // Changes up to the snapshot are part of it
+        changed(0, DEV1, DeviceState::Off);
+        assert!(ClientModule::devices(account(DEV1)).is_none());
+        receive(XCMPMessageOf::<Test>::DeviceSnapshot(
+            2,
+            vec![profile(DEV1, DeviceState::Ready)],
+        ));
+        receive(XCMPMessageOf::<Test>::DeviceSnapshot(
+            2,
+            vec![profile(DEV2, DeviceState::Ready)],
+        ));
+        assert_eq!(
+            ClientModule::subscription(service()),
+            Some(Subscription {
+                next: 2,
+                syncing: false
+            })
+        );
+        changed(1, DEV1, DeviceState::Off);
+        assert_eq!(state(DEV1), DeviceState::Ready);
+        changed(2, DEV1, DeviceState::Off);
+        assert_eq!(state(DEV1), DeviceState::Off);
+
+        // A missed change asks for a fresh snapshot, changes wait for it
+        changed(4, DEV2, DeviceState::Off);
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_client(crate::Event::RegistryGap(service(), 3, 4))));
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::Subscribe)
+        ));
+        assert_eq!(state(DEV2), DeviceState::Ready);
+        changed(5, DEV1, DeviceState::Ready);
+        assert_eq!(state(DEV1), DeviceState::Off);
+
+        receive(XCMPMessageOf::<Test>::DeviceSnapshot(
+            6,
+            vec![
+                profile(DEV1, DeviceState::Ready),
+                profile(DEV2, DeviceState::Off),
+            ],
+        ));
+        assert_eq!(state(DEV1), DeviceState::Ready);
+        assert_eq!(state(DEV2), DeviceState::Off);
+        changed(6, DEV2, DeviceState::Ready);
+        assert_eq!(state(DEV2), DeviceState::Ready);
+    });
+}
+
+#[test]
+fn policy_is_mirrored() {
+    use crate::{AccessPolicy, Subscription};
+
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        let sub = Subscription {
+            next: 0,
+            syncing: false,
+        };
+        crate::Subscriptions::<Test>::insert(service(), sub);
+        let policy = AccessPolicy::Allow(vec![(999, account(CL2))]);
+
+        // Only the parachain of the device sets its policy
+        let msg = XCMPMessageOf::<Test>::DevicePolicy(0, account(DEV1), policy.clone());
+        receive_from(OTHER_SERVICE, msg.clone());
+        assert_ok!(aux_order(DEV1, CL1));
+        assert_ok!(ClientModule::cancel(
+            Origin::signed(account(CL1)),
+            account(DEV1)
+        ));
+        receive(XCMPMessageOf::<Test>::OrderCancelled(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+
+        receive(msg);
+        assert_eq!(
+            ClientModule::policies(account(DEV1)),
+            AccessPolicy::Allow(vec![(999.into(), account(CL2))])
+        );
+        assert_noop!(aux_order(DEV1, CL1), Error::<Test>::NotAllowed);
+        assert_ok!(aux_order(DEV1, CL2));
+    });
+}
+
+#[test]
+fn full_outbox_refuses_orders() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        aux_mirror_device(DEV1);
+        assert_noop!(
+            ClientModule::handshake(Origin::signed(account(CL1)), service()),
+            frame_support::dispatch::DispatchError::BadOrigin
+        );
+
+        close_channel(true);
+        assert_ok!(ClientModule::handshake(Origin::root(), service()));
+        assert_ok!(ClientModule::handshake(Origin::root(), service()));
+
+        // Nothing is reserved or stored for an order that can't be sent
+        assert_noop!(aux_order(DEV1, CL1), Error::<Test>::OutboxFull);
+    });
+}
+
+#[test]
+fn unacked_messages_bounded() {
+    use frame_support::{traits::Hooks, weights::Weight};
+    use pallet_common::{MessageKind, PendingAck};
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        aux_mirror_device(DEV1);
+        let pending = PendingAck {
+            blob: Vec::new(),
+            kind: MessageKind::OrderCancel,
+            sent_at: 1,
+            resends: 0,
+        };
+        for nonce in 0..4 {
+            ClientModule::track_unacked(service(), nonce, pending.clone());
+        }
+        assert_noop!(aux_order(DEV1, CL1), Error::<Test>::TooManyUnacked);
+
+        // Given up after the last resend, making room again
+        for now in [6, 11, 16, 21].iter() {
+            ClientModule::on_idle(*now, Weight::max_value());
+        }
+        assert_eq!(crate::UnackedCount::<Test>::get(service()), 0);
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_client(crate::Event::UnackedAbandoned(
+                service(),
+                0,
+                MessageKind::OrderCancel
+            ))));
+        assert_ok!(aux_order(DEV1, CL1));
+    });
+}
+
+#[test]
+fn transfer_settlement_deposits_on_service() {
+    use crate::SettlementMode;
+    use polkadot_parachain::primitives::Sibling;
+    use sp_runtime::traits::AccountIdConversion;
+    use xcm::v0::{Junction, MultiAsset, MultiLocation, Order as XcmOrder, Xcm};
+
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        assert_ok!(ClientModule::set_settlement(
+            Origin::root(),
+            service(),
+            SettlementMode::Transfer
+        ));
+        assert_ok!(aux_order(DEV1, CL1));
+        receive(XCMPMessageOf::<Test>::OrderAccept(
+            0,
+            account(CL1),
+            account(DEV1),
+        ));
+        receive(XCMPMessageOf::<Test>::OrderDone(
+            0,
+            account(CL1),
+            account(DEV1),
+            true,
+        ));
+
+        // The fee stays here in reserve of the service parachain
+        let reserve: AccountId = Sibling::from(service()).into_account();
+        assert_eq!(Balances::free_balance(reserve), DEFAULT_FEE);
+        assert_eq!(Balances::reserved_balance(account(CL1)), 0);
+
+        let (dest, xcm) = sent_xcm().pop().unwrap();
+        assert_eq!(
+            dest,
+            MultiLocation::X2(Junction::Parent, Junction::Parachain { id: SERVICE })
+        );
+        match xcm {
+            Xcm::ReserveAssetDeposit { assets, effects } => {
+                assert_eq!(
+                    assets,
+                    vec![MultiAsset::ConcreteFungible {
+                        id: MultiLocation::X2(Junction::Parent, Junction::Parachain { id: 999 }),
+                        amount: DEFAULT_FEE,
+                    }]
+                );
+                assert!(matches!(
+                    effects.as_slice(),
+                    [XcmOrder::DepositAsset { .. }]
+                ));
+            }
+            _ => panic!("fees are deposited as the reserve asset"),
+        }
+    });
+}
+
+#[test]
+fn settlement_mode_fixed_per_order() {
+    use crate::SettlementMode;
+
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        assert_ok!(aux_order(DEV1, CL1));
+        assert_ok!(ClientModule::set_settlement(
+            Origin::root(),
+            service(),
+            SettlementMode::Netting
+        ));
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::SettlementChanged(
+                SettlementMode::Netting
+            ))
+        ));
+
+        // Placed before the change, the order is still paid locally
+        let free = Balances::free_balance(account(DEV1));
+        aux_done(DEV1, CL1);
+        assert_eq!(Balances::free_balance(account(DEV1)), free + DEFAULT_FEE);
+        assert_eq!(
+            ClientModule::ledger(service(), account(DEV1)),
+            Default::default()
+        );
+
+        assert_ok!(aux_order(DEV1, CL1));
+        aux_done(DEV1, CL1);
+        assert_eq!(Balances::free_balance(account(DEV1)), free + DEFAULT_FEE);
+        assert_eq!(Balances::free_balance(ClientModule::pot()), DEFAULT_FEE);
+        assert_eq!(
+            ClientModule::ledger(service(), account(DEV1)).fees,
+            DEFAULT_FEE
+        );
+    });
+}
+
+#[test]
+fn netting_settles_in_batches() {
+    use crate::{weights::WeightInfo, SettlementMode};
+    use frame_support::traits::Hooks;
+    use polkadot_parachain::primitives::Sibling;
+    use sp_runtime::traits::AccountIdConversion;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let devices = [DEV1, DEV2, 2];
+        for &dev in devices.iter() {
+            aux_mirror_device(dev);
+        }
+        assert_ok!(ClientModule::set_settlement(
+            Origin::root(),
+            service(),
+            SettlementMode::Netting
+        ));
+        for &dev in devices.iter() {
+            assert_ok!(aux_order(dev, CL1));
+            aux_done(dev, CL1);
+        }
+        assert_eq!(Balances::free_balance(ClientModule::pot()), DEFAULT_FEE * 3);
+
+        // Closing the epoch settles nothing yet
+        ClientModule::on_initialize(10);
+        assert_eq!(ClientModule::epoch(), 1);
+        assert!(sent_xcm().is_empty());
+
+        // Two entries a batch, one batch fits the weight
+        let batch = <() as WeightInfo>::settle_epoch(2);
+        ClientModule::settle_pending(10, batch);
+        assert_eq!(sent_xcm().len(), 1);
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::Reconcile(0, ref entries)) if entries.len() == 2
+        ));
+        assert_eq!(crate::Ledgers::<Test>::iter_prefix(service()).count(), 1);
+
+        ClientModule::settle_pending(11, batch);
+        assert_eq!(sent_xcm().len(), 2);
+        assert!(crate::PendingSettlements::<Test>::get().is_empty());
+        assert_eq!(crate::Ledgers::<Test>::iter_prefix(service()).count(), 0);
+
+        let record = ClientModule::settlements(service(), 0).unwrap();
+        assert_eq!(record.total, DEFAULT_FEE * 3);
+        assert_eq!(record.devices, 3);
+        let reserve: AccountId = Sibling::from(service()).into_account();
+        assert_eq!(Balances::free_balance(reserve), DEFAULT_FEE * 3);
+    });
+}
+
+#[test]
+fn penalties_netted_against_fees() {
+    use crate::SettlementMode;
+    use xcm::v0::{MultiAsset, Order as XcmOrder, Xcm};
+
+    new_test_ext().execute_with(|| {
+        aux_mirror_device(DEV1);
+        assert_ok!(ClientModule::set_settlement(
+            Origin::root(),
+            service(),
+            SettlementMode::Netting
+        ));
+        assert_ok!(aux_order(DEV1, CL1));
+        aux_done(DEV1, CL1);
+
+        // Overdue, the client is paid out of the fees the device earned
+        assert_ok!(aux_order(DEV1, CL1));
+        let order = ClientModule::orders(account(DEV1)).unwrap();
+        crate::Timestamp::<Test>::set_timestamp(order.until + 1);
+        let device = Balances::free_balance(account(DEV1));
+        let client = Balances::free_balance(account(CL1));
+        aux_done(DEV1, CL1);
+        // The collateral is released instead
+        assert_eq!(Balances::free_balance(account(DEV1)), device + PENALTY);
+        assert_eq!(Balances::reserved_balance(account(DEV1)), 0);
+        assert_eq!(Balances::free_balance(account(CL1)), client + PENALTY);
+        let entry = ClientModule::ledger(service(), account(DEV1));
+        assert_eq!(entry.fees, DEFAULT_FEE * 2);
+        assert_eq!(entry.penalties, PENALTY);
+
+        ClientModule::close_epoch();
+        ClientModule::settle_pending(10, frame_support::weights::Weight::max_value());
+        assert!(matches!(
+            sent().pop(),
+            Some(XCMPMessageOf::<Test>::Reconcile(0, ref entries))
+                if entries == &vec![(account(DEV1), DEFAULT_FEE * 2, PENALTY)]
+        ));
+        match sent_xcm().pop() {
+            Some((_, Xcm::ReserveAssetDeposit { assets, effects })) => {
+                assert!(matches!(
+                    assets.as_slice(),
+                    [MultiAsset::ConcreteFungible { amount, .. }]
+                        if *amount == DEFAULT_FEE * 2 - PENALTY
+                ));
+                assert_eq!(effects.len(), 1);
+                assert!(matches!(effects[0], XcmOrder::DepositAsset { .. }));
+            }
+            _ => panic!("net fees are deposited as the reserve asset"),
         }
     });
 }
@@ -0,0 +1,359 @@
+//! Placeholder weights for pallet_robonomics_client
+//!
+//! The values are estimated by hand and were not produced by the benchmarking CLI,
+//! the file must be regenerated on reference hardware before production use with:
+//! ./target/release/parachain-collator-client benchmark --chain dev --execution wasm --wasm-execution compiled
+//!     --pallet pallet_client --extrinsic '*' --steps 50 --repeat 20
+//!     --output pallets/client/src/weights.rs
+
+#![allow(unused_parens)]
+#![allow(unused_imports)]
+
+use frame_support::{
+    traits::Get,
+    weights::{constants::RocksDbWeight, Weight},
+};
+use sp_std::marker::PhantomData;
+
+/// Weight functions needed for pallet_client.
+pub trait WeightInfo {
+    fn test() -> Weight;
+    fn order(s: u32) -> Weight;
+    fn cancel() -> Weight;
+    fn amend_order() -> Weight;
+    fn on_accept() -> Weight;
+    fn on_reject() -> Weight;
+    fn on_done() -> Weight;
+    fn on_progress() -> Weight;
+    fn on_amend_accept() -> Weight;
+    fn on_bad_message() -> Weight;
+    fn set_pause() -> Weight;
+    fn on_queue_message() -> Weight;
+    fn handshake() -> Weight;
+    fn on_handshake() -> Weight;
+    fn on_failed() -> Weight;
+    fn retry_message() -> Weight;
+    fn on_ack() -> Weight;
+    fn resend_message() -> Weight;
+    fn on_duplicate() -> Weight;
+    fn set_settlement() -> Weight;
+    fn settle_epoch(n: u32) -> Weight;
+    fn on_cancel_rejected() -> Weight;
+    fn on_cancelled() -> Weight;
+    fn on_device_sync() -> Weight;
+    fn on_device_snapshot(n: u32) -> Weight;
+    fn subscribe() -> Weight;
+    fn unsubscribe() -> Weight;
+    fn bind_device() -> Weight;
+    fn on_amend_reject() -> Weight;
+    fn on_device_policy(n: u32) -> Weight;
+}
+
+/// Placeholder weights for pallet_client, not benchmarked.
+pub struct SubstrateWeight<T>(PhantomData<T>);
+impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
+    fn test() -> Weight {
+        (24_315_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn order(s: u32) -> Weight {
+        (118_224_000 as Weight)
+            .saturating_add((2_000 as Weight).saturating_mul(s as Weight))
+            .saturating_add(T::DbWeight::get().reads(8 as Weight))
+            .saturating_add(T::DbWeight::get().writes(6 as Weight))
+    }
+    fn cancel() -> Weight {
+        (68_533_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(5 as Weight))
+            .saturating_add(T::DbWeight::get().writes(5 as Weight))
+    }
+    fn amend_order() -> Weight {
+        (64_918_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(6 as Weight))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+    }
+    fn on_accept() -> Weight {
+        (6_129_000 as Weight)
+    }
+    fn on_reject() -> Weight {
+        (64_871_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(5 as Weight))
+            .saturating_add(T::DbWeight::get().writes(5 as Weight))
+    }
+    fn on_done() -> Weight {
+        (93_410_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(6 as Weight))
+            .saturating_add(T::DbWeight::get().writes(6 as Weight))
+    }
+    fn on_progress() -> Weight {
+        (22_978_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_amend_accept() -> Weight {
+        (26_731_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_bad_message() -> Weight {
+        (4_917_000 as Weight)
+    }
+    fn set_pause() -> Weight {
+        (14_260_000 as Weight).saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_queue_message() -> Weight {
+        (12_945_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+    }
+    fn handshake() -> Weight {
+        (19_402_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_handshake() -> Weight {
+        (23_118_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+    }
+    fn on_failed() -> Weight {
+        (58_304_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(5 as Weight))
+            .saturating_add(T::DbWeight::get().writes(6 as Weight))
+    }
+    fn retry_message() -> Weight {
+        (31_806_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_ack() -> Weight {
+        (19_442_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn resend_message() -> Weight {
+        (33_057_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_duplicate() -> Weight {
+        (21_730_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn set_settlement() -> Weight {
+        (17_954_000 as Weight).saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn settle_epoch(n: u32) -> Weight {
+        (48_612_000 as Weight)
+            .saturating_add((9_874_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().reads((2 as Weight).saturating_mul(n as Weight)))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes((1 as Weight).saturating_mul(n as Weight)))
+    }
+    fn on_cancel_rejected() -> Weight {
+        (14_603_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_cancelled() -> Weight {
+        (66_318_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(6 as Weight))
+            .saturating_add(T::DbWeight::get().writes(6 as Weight))
+    }
+    fn on_device_sync() -> Weight {
+        (12_935_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_device_snapshot(n: u32) -> Weight {
+        (15_482_000 as Weight)
+            .saturating_add((10_216_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().reads((1 as Weight).saturating_mul(n as Weight)))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes((1 as Weight).saturating_mul(n as Weight)))
+    }
+    fn subscribe() -> Weight {
+        (32_764_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes(4 as Weight))
+    }
+    fn unsubscribe() -> Weight {
+        (31_905_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes(4 as Weight))
+    }
+    fn bind_device() -> Weight {
+        (18_412_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+    }
+    fn on_amend_reject() -> Weight {
+        (27_318_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_device_policy(n: u32) -> Weight {
+        (20_416_000 as Weight)
+            .saturating_add((121_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+}
+
+// For backwards compatibility and tests
+impl WeightInfo for () {
+    fn test() -> Weight {
+        (24_315_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn order(s: u32) -> Weight {
+        (118_224_000 as Weight)
+            .saturating_add((2_000 as Weight).saturating_mul(s as Weight))
+            .saturating_add(RocksDbWeight::get().reads(8 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(6 as Weight))
+    }
+    fn cancel() -> Weight {
+        (68_533_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(5 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(5 as Weight))
+    }
+    fn amend_order() -> Weight {
+        (64_918_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(6 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+    }
+    fn on_accept() -> Weight {
+        (6_129_000 as Weight)
+    }
+    fn on_reject() -> Weight {
+        (64_871_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(5 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(5 as Weight))
+    }
+    fn on_done() -> Weight {
+        (93_410_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(6 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(6 as Weight))
+    }
+    fn on_progress() -> Weight {
+        (22_978_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_amend_accept() -> Weight {
+        (26_731_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_bad_message() -> Weight {
+        (4_917_000 as Weight)
+    }
+    fn set_pause() -> Weight {
+        (14_260_000 as Weight).saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_queue_message() -> Weight {
+        (12_945_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+    }
+    fn handshake() -> Weight {
+        (19_402_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_handshake() -> Weight {
+        (23_118_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+    }
+    fn on_failed() -> Weight {
+        (58_304_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(5 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(6 as Weight))
+    }
+    fn retry_message() -> Weight {
+        (31_806_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_ack() -> Weight {
+        (19_442_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn resend_message() -> Weight {
+        (33_057_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_duplicate() -> Weight {
+        (21_730_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn set_settlement() -> Weight {
+        (17_954_000 as Weight).saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn settle_epoch(n: u32) -> Weight {
+        (48_612_000 as Weight)
+            .saturating_add((9_874_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().reads((2 as Weight).saturating_mul(n as Weight)))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes((1 as Weight).saturating_mul(n as Weight)))
+    }
+    fn on_cancel_rejected() -> Weight {
+        (14_603_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_cancelled() -> Weight {
+        (66_318_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(6 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(6 as Weight))
+    }
+    fn on_device_sync() -> Weight {
+        (12_935_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_device_snapshot(n: u32) -> Weight {
+        (15_482_000 as Weight)
+            .saturating_add((10_216_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().reads((1 as Weight).saturating_mul(n as Weight)))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes((1 as Weight).saturating_mul(n as Weight)))
+    }
+    fn subscribe() -> Weight {
+        (32_764_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(4 as Weight))
+    }
+    fn unsubscribe() -> Weight {
+        (31_905_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(4 as Weight))
+    }
+    fn bind_device() -> Weight {
+        (18_412_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+    }
+    fn on_amend_reject() -> Weight {
+        (27_318_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_device_policy(n: u32) -> Weight {
+        (20_416_000 as Weight)
+            .saturating_add((121_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+}
@@ -1,11 +1,22 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 use codec::{Decode, Encode};
+use frame_support::sp_std::prelude::*;
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
 
+use frame_support::sp_std::marker::PhantomData;
 use frame_support::{
-    sp_runtime::traits::Hash,
+    sp_runtime::traits::{AtLeast32Bit, Hash},
     sp_runtime::RuntimeDebug,
     traits::{BalanceStatus::Free, Currency, Get, ReservableCurrency},
+    weights::{GetDispatchInfo, Weight},
 };
+use xcm::v0::{Order as XcmOrder, Xcm};
+
+pub mod messaging;
+pub mod migrations;
+
+pub use messaging::{Messaging, MessagingError, MessagingEvent, WireMessage};
 
 // use cumulus_primitives_core::{
 //     relay_chain,
@@ -15,8 +26,8 @@ use frame_support::{
 //     ServiceQuality, UpwardMessage, UpwardMessageSender, XcmpMessageHandler, XcmpMessageSender,
 // };
 
-#[cfg_attr(feature = "std", derive(Debug))]
-#[derive(Encode, Decode, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug, Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, Copy, PartialEq)]
 pub enum DeviceState {
     /// Device is off
     Off,
@@ -35,9 +46,66 @@ impl Default for DeviceState {
     }
 }
 
+/// Storage layout version of the robonomics pallets
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, RuntimeDebug)]
+pub enum Releases {
+    /// Unversioned layout, orders without relay chain deadline
+    V0,
+    /// Orders carry relay chain block deadline
+    V1,
+    /// Orders carry order id
+    V2,
+}
+
+impl Default for Releases {
+    fn default() -> Self {
+        Releases::V0
+    }
+}
+
+/// Relay chain block number
+pub type RelayBlockNumber = u32;
+
+/// Order identifier, unique within the chain
+pub type OrderId = u64;
+
+fn deadline_passed<Moment: PartialOrd>(
+    until: &Moment,
+    until_relay: Option<RelayBlockNumber>,
+    now: &Moment,
+    relay_now: RelayBlockNumber,
+) -> bool {
+    match until_relay {
+        Some(until_relay) => relay_now >= until_relay,
+        None => now >= until,
+    }
+}
+
+/// Checks the order deadline leaves the device at least one work circle
+///
+/// Relay chain deadlines are converted to time by `block_duration` per relay block.
+pub fn leaves_wcd<Moment: AtLeast32Bit + Copy>(
+    until: Moment,
+    until_relay: Option<RelayBlockNumber>,
+    now: Moment,
+    wcd: Moment,
+    relay_now: RelayBlockNumber,
+    block_duration: Moment,
+) -> bool {
+    match until_relay {
+        Some(until) => {
+            let blocks = until.saturating_sub(relay_now);
+            block_duration.saturating_mul(blocks.into()) >= wcd
+        }
+        None => until >= now.saturating_add(wcd),
+    }
+}
+
 #[derive(Encode, Decode, Default, Clone, RuntimeDebug, PartialEq)]
 pub struct OrderBase<Payload: Encode + Decode, Balance, Moment, AccountId> {
     pub until: Moment,
+    /// Relay chain block deadline, takes precedence over `until` if set
+    pub until_relay: Option<RelayBlockNumber>,
     pub data: Payload,
     pub fee: Balance,
     pub device: AccountId,
@@ -48,22 +116,160 @@ impl<Payload: Encode + Decode, Balance, Moment, AccountId>
 {
     pub fn convert<ParaId: From<u32>>(
         self,
+        id: OrderId,
         client: AccountId,
     ) -> Order<Payload, Balance, Moment, AccountId, ParaId> {
         Order {
+            id,
             until: self.until,
+            until_relay: self.until_relay,
             data: self.data,
             fee: self.fee,
             client,
             paraid: 0.into(),
         }
     }
+
+    pub fn is_overdue(&self, now: &Moment, relay_now: RelayBlockNumber) -> bool
+    where
+        Moment: PartialOrd,
+    {
+        deadline_passed(&self.until, self.until_relay, now, relay_now)
+    }
+}
+
+/// How the client parachain pays the fee of a completed order
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, Copy, RuntimeDebug, PartialEq, Eq)]
+pub enum SettlementMode {
+    /// Paid to the device account on the client parachain
+    Local,
+    /// Transferred to the device account on the service parachain, backed by the client
+    /// parachain reserve
+    Transfer,
+    /// Accumulated on this parachain and transferred like `Transfer` once per settlement epoch
+    Netting,
+}
+
+impl Default for SettlementMode {
+    fn default() -> Self {
+        SettlementMode::Local
+    }
+}
+
+/// Index of a net settlement epoch
+pub type EpochIndex = u32;
+
+/// Fees and penalties of a device accumulated over a settlement epoch
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Default, Clone, RuntimeDebug, PartialEq)]
+pub struct LedgerEntry<Balance> {
+    /// Fees owed to the device
+    pub fees: Balance,
+    /// Device collateral paid to clients out of the fees
+    pub penalties: Balance,
+}
+
+/// Net settlement of an epoch with a service parachain
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Default, Clone, RuntimeDebug, PartialEq)]
+pub struct SettlementRecord<Balance, BlockNumber> {
+    /// Fees transferred to devices, net of penalties
+    pub total: Balance,
+    /// Penalties netted against the fees
+    pub penalties: Balance,
+    /// Devices with a ledger entry
+    pub devices: u32,
+    /// Block the epoch was settled at
+    pub settled_at: BlockNumber,
+}
+
+/// Clients permitted to order a device, entries are (paraid, client)
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, RuntimeDebug, PartialEq)]
+pub enum AccessPolicy<ParaId, AccountId> {
+    /// Any client
+    Open,
+    /// Listed clients only
+    Allow(Vec<(ParaId, AccountId)>),
+    /// Any client except the listed ones
+    Deny(Vec<(ParaId, AccountId)>),
+}
+
+impl<ParaId, AccountId> Default for AccessPolicy<ParaId, AccountId> {
+    fn default() -> Self {
+        AccessPolicy::Open
+    }
+}
+
+impl<ParaId: PartialEq, AccountId: PartialEq> AccessPolicy<ParaId, AccountId> {
+    pub fn permits(&self, paraid: &ParaId, client: &AccountId) -> bool {
+        let listed = |entries: &Vec<(ParaId, AccountId)>| {
+            entries.iter().any(|(p, c)| p == paraid && c == client)
+        };
+        match self {
+            AccessPolicy::Open => true,
+            AccessPolicy::Allow(entries) => listed(entries),
+            AccessPolicy::Deny(entries) => !listed(entries),
+        }
+    }
+
+    /// Number of listed clients
+    pub fn entries(&self) -> usize {
+        match self {
+            AccessPolicy::Open => 0,
+            AccessPolicy::Allow(entries) | AccessPolicy::Deny(entries) => entries.len(),
+        }
+    }
+}
+
+impl<ParaId, AccountId> AccessPolicy<ParaId, AccountId> {
+    /// Converts the parachain ids of the listed clients
+    pub fn convert<P: From<ParaId>>(self) -> AccessPolicy<P, AccountId> {
+        let convert = |entries: Vec<(ParaId, AccountId)>| {
+            entries
+                .into_iter()
+                .map(|(paraid, client)| (paraid.into(), client))
+                .collect()
+        };
+        match self {
+            AccessPolicy::Open => AccessPolicy::Open,
+            AccessPolicy::Allow(entries) => AccessPolicy::Allow(convert(entries)),
+            AccessPolicy::Deny(entries) => AccessPolicy::Deny(convert(entries)),
+        }
+    }
+}
+
+/// Upper bound of the telemetry progress value, percent
+pub const MAX_PROGRESS: u8 = 100;
+
+/// Device telemetry snapshot reported during an order
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Default, Clone, RuntimeDebug, PartialEq)]
+pub struct Telemetry {
+    /// Order progress, percent
+    pub progress: u8,
+    /// Device position (latitude, longitude in microdegrees, altitude in millimeters)
+    pub position: Option<(i32, i32, i32)>,
+    /// Digest of the raw sensor data
+    pub digest: [u8; 32],
+}
+
+impl Telemetry {
+    pub fn is_valid(&self) -> bool {
+        self.progress <= MAX_PROGRESS
+    }
 }
 
 //#[cfg_attr(feature = "std", derive(PartialEq))]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
 #[derive(Encode, Decode, Default, Clone, RuntimeDebug, PartialEq)]
 pub struct Order<Payload: Encode + Decode, Balance, Moment, AccountId, ParaId> {
+    pub id: OrderId,
     pub until: Moment,
+    /// Relay chain block deadline, takes precedence over `until` if set
+    pub until_relay: Option<RelayBlockNumber>,
     pub data: Payload,
     pub fee: Balance,
     pub client: AccountId,
@@ -76,23 +282,604 @@ impl<Payload: Encode + Decode, Balance, Moment, AccountId, ParaId>
     pub fn convert(self, device: AccountId) -> OrderBase<Payload, Balance, Moment, AccountId> {
         OrderBase {
             until: self.until,
+            until_relay: self.until_relay,
             data: self.data,
             fee: self.fee,
             device,
         }
     }
+
+    pub fn is_overdue(&self, now: &Moment, relay_now: RelayBlockNumber) -> bool
+    where
+        Moment: PartialOrd,
+    {
+        deadline_passed(&self.until, self.until_relay, now, relay_now)
+    }
+
+    pub fn amend(&mut self, amendment: Amendment<Balance, Moment>) {
+        self.until = amendment.until;
+        self.until_relay = amendment.until_relay;
+        self.fee = amendment.fee;
+    }
+}
+
+/// Deadline extension and fee top up of an open order
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[derive(Encode, Decode, Default, Clone, RuntimeDebug, PartialEq)]
+pub struct Amendment<Balance, Moment> {
+    /// New deadline
+    pub until: Moment,
+    /// New relay chain block deadline
+    pub until_relay: Option<RelayBlockNumber>,
+    /// New fee
+    pub fee: Balance,
+}
+
+impl<Balance: PartialOrd, Moment: PartialOrd> Amendment<Balance, Moment> {
+    /// Amendment changes the order and only extends its deadline or raises its fee
+    pub fn extends<Payload: Encode + Decode, AccountId, ParaId>(
+        &self,
+        order: &Order<Payload, Balance, Moment, AccountId, ParaId>,
+    ) -> bool {
+        let relay_extended = match (order.until_relay, self.until_relay) {
+            (Some(old), Some(new)) => new >= old,
+            (None, None) => true,
+            _ => false,
+        };
+        let changed = self.until != order.until
+            || self.until_relay != order.until_relay
+            || self.fee != order.fee;
+
+        changed && relay_extended && self.until >= order.until && self.fee >= order.fee
+    }
+}
+
+/// Funds moved when an order is closed
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Default, Clone, RuntimeDebug, PartialEq)]
+pub struct Settlement<Balance> {
+    /// Fee paid to the device
+    pub paid: Balance,
+    /// Fee returned to the client
+    pub refunded: Balance,
+    /// Device collateral released or slashed
+    pub penalty: Balance,
+    /// Collateral was slashed in favour of the client
+    pub slashed: bool,
 }
 
-#[derive(codec::Encode, codec::Decode)]
+/// Device profile exposed to the runtime API
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[derive(Encode, Decode, Clone, RuntimeDebug, PartialEq)]
+pub struct DeviceInfo<Balance, Moment> {
+    pub state: DeviceState,
+    /// Collateral reserved while the device has an order
+    pub penalty: Balance,
+    /// Work circle duration
+    pub wcd: Moment,
+    /// Parachain the device lives on, `None` for local devices
+    pub paraid: Option<u32>,
+}
+
+/// Costs and constraints of a prospective order
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[derive(Encode, Decode, Clone, RuntimeDebug, PartialEq)]
+pub struct OrderQuote<Balance, Moment> {
+    /// Fee reserved from the client
+    pub fee: Balance,
+    /// Device collateral reserved for the order
+    pub penalty: Balance,
+    /// Fee of the order extrinsic
+    pub dispatch_fee: Balance,
+    /// Earliest deadline leaving the device a full work circle
+    pub min_until: Moment,
+    /// Device is able to take the order right now
+    pub available: bool,
+}
+
+#[derive(codec::Encode, codec::Decode, Clone)]
 pub enum XCMPMessage<XAccountId, XBalance, Payout: Encode + Decode, Moment> {
     /// Transfer tokens to the given account from the Parachain account.
     //TransferToken(XAccountId, XBalance),
-    /// Order sent to device (client, order)
+    /// Order sent to device (orderid, client, order),
+    /// the order id is assigned by the client parachain
+    NewOrder(
+        OrderId,
+        XAccountId,
+        OrderBase<Payout, XBalance, Moment, XAccountId>,
+    ),
+    /// Order accepted by device (orderid, clientid, deviceid)
+    OrderAccept(OrderId, XAccountId, XAccountId),
+    /// Order rejected by device (orderid, clientid, deviceid, on/off)
+    OrderReject(OrderId, XAccountId, XAccountId, bool),
+    /// Order completed (orderid, clientid, deviceid, on/off)
+    OrderDone(OrderId, XAccountId, XAccountId, bool),
+    /// Order progress reported by device (orderid, clientid, deviceid, telemetry)
+    OrderProgress(OrderId, XAccountId, XAccountId, Telemetry),
+    /// Order withdrawn by client before acceptance (orderid, clientid, deviceid)
+    OrderCancel(OrderId, XAccountId, XAccountId),
+    /// Order amendment requested by client (orderid, clientid, deviceid, amendment)
+    OrderAmend(OrderId, XAccountId, XAccountId, Amendment<XBalance, Moment>),
+    /// Order amendment acknowledged by device (orderid, clientid, deviceid)
+    OrderAmendAccept(OrderId, XAccountId, XAccountId),
+    /// Order refused by the paused device parachain (orderid, clientid, deviceid)
+    OrderFailed(OrderId, XAccountId, XAccountId),
+    /// Protocol negotiation request (highest supported version)
+    Handshake(ProtocolVersion),
+    /// Protocol negotiation reply (agreed version)
+    HandshakeAck(ProtocolVersion),
+    /// Message processed by the receiver (nonce)
+    Ack(Nonce),
+    /// Message refused by the receiver (nonce)
+    Nack(Nonce),
+    /// Ledger of a settled epoch (epoch, [(deviceid, fees, penalties)])
+    Reconcile(EpochIndex, Vec<(XAccountId, XBalance, XBalance)>),
+    /// Order withdrawal refused, the order was closed or accepted before
+    /// (orderid, clientid, deviceid)
+    OrderCancelRejected(OrderId, XAccountId, XAccountId),
+    /// Device registered on the service parachain (seq, deviceid, penalty, wcd, state)
+    DeviceAnnounce(u64, XAccountId, XBalance, Moment, DeviceState),
+    /// Device collateral or work circle changed (seq, deviceid, penalty, wcd)
+    DeviceUpdate(u64, XAccountId, XBalance, Moment),
+    /// Device state changed (seq, deviceid, state)
+    DeviceStateChanged(u64, XAccountId, DeviceState),
+    /// Request of the device registry snapshot and its changes
+    Subscribe,
+    /// Device registry changes no longer wanted
+    Unsubscribe,
+    /// Part of the device registry
+    /// (seq of the next change, [(deviceid, penalty, wcd, state, policy)])
+    DeviceSnapshot(
+        u64,
+        Vec<(
+            XAccountId,
+            XBalance,
+            Moment,
+            DeviceState,
+            AccessPolicy<u32, XAccountId>,
+        )>,
+    ),
+    /// Order amendment refused, the order is closed or can't be amended that way
+    /// (orderid, clientid, deviceid)
+    OrderAmendReject(OrderId, XAccountId, XAccountId),
+    /// Device access policy changed (seq, deviceid, policy)
+    DevicePolicy(u64, XAccountId, AccessPolicy<u32, XAccountId>),
+    /// Fee settlement of orders placed from now on (mode)
+    SettlementChanged(SettlementMode),
+    /// Order withdrawal done, the order is closed (orderid, clientid, deviceid)
+    OrderCancelled(OrderId, XAccountId, XAccountId),
+}
+
+impl<XAccountId, XBalance, Payout: Encode + Decode, Moment>
+    XCMPMessage<XAccountId, XBalance, Payout, Moment>
+{
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            XCMPMessage::NewOrder(..) => MessageKind::NewOrder,
+            XCMPMessage::OrderAccept(..) => MessageKind::OrderAccept,
+            XCMPMessage::OrderReject(..) => MessageKind::OrderReject,
+            XCMPMessage::OrderDone(..) => MessageKind::OrderDone,
+            XCMPMessage::OrderProgress(..) => MessageKind::OrderProgress,
+            XCMPMessage::OrderCancel(..) => MessageKind::OrderCancel,
+            XCMPMessage::OrderAmend(..) => MessageKind::OrderAmend,
+            XCMPMessage::OrderAmendAccept(..) => MessageKind::OrderAmendAccept,
+            XCMPMessage::OrderFailed(..) => MessageKind::OrderFailed,
+            XCMPMessage::Handshake(..) => MessageKind::Handshake,
+            XCMPMessage::HandshakeAck(..) => MessageKind::HandshakeAck,
+            XCMPMessage::Ack(..) => MessageKind::Ack,
+            XCMPMessage::Nack(..) => MessageKind::Nack,
+            XCMPMessage::Reconcile(..) => MessageKind::Reconcile,
+            XCMPMessage::OrderCancelRejected(..) => MessageKind::OrderCancelRejected,
+            XCMPMessage::DeviceAnnounce(..) => MessageKind::DeviceAnnounce,
+            XCMPMessage::DeviceUpdate(..) => MessageKind::DeviceUpdate,
+            XCMPMessage::DeviceStateChanged(..) => MessageKind::DeviceStateChanged,
+            XCMPMessage::Subscribe => MessageKind::Subscribe,
+            XCMPMessage::Unsubscribe => MessageKind::Unsubscribe,
+            XCMPMessage::DeviceSnapshot(..) => MessageKind::DeviceSnapshot,
+            XCMPMessage::OrderAmendReject(..) => MessageKind::OrderAmendReject,
+            XCMPMessage::DevicePolicy(..) => MessageKind::DevicePolicy,
+            XCMPMessage::SettlementChanged(..) => MessageKind::SettlementChanged,
+            XCMPMessage::OrderCancelled(..) => MessageKind::OrderCancelled,
+        }
+    }
+
+    /// Encodes the message in the wire format of the protocol version,
+    /// the nonce is dropped by versions without acknowledgements
+    pub fn to_wire(&self, version: ProtocolVersion, nonce: Option<Nonce>) -> Vec<u8>
+    where
+        Self: Encode + Clone,
+        LegacyMessage<XAccountId, XBalance, Payout, Moment>: Encode,
+    {
+        // Messages unknown to the legacy layout are sent as they are, the receiver refuses them
+        let legacy = || match self.clone().downgrade() {
+            Some(msg) => msg.encode(),
+            None => self.encode(),
+        };
+        let payload = match version {
+            PROTOCOL_V0 => return self.encode(),
+            PROTOCOL_V1 => legacy(),
+            PROTOCOL_V2 => (nonce, legacy()).encode(),
+            _ => (nonce, self).encode(),
+        };
+        Envelope {
+            magic: MESSAGE_MAGIC,
+            version,
+            kind: self.kind(),
+            payload,
+        }
+        .encode()
+    }
+
+    /// Decodes an enveloped or a bare message along with its protocol version and nonce,
+    /// each version is read in its own layout
+    pub fn from_wire(blob: &[u8]) -> Result<(ProtocolVersion, Option<Nonce>, Self), codec::Error>
+    where
+        Self: Decode,
+        LegacyMessage<XAccountId, XBalance, Payout, Moment>: Decode,
+    {
+        if !blob.starts_with(&MESSAGE_MAGIC) {
+            return Self::decode(&mut &blob[..]).map(|msg| (PROTOCOL_V0, None, msg));
+        }
+
+        let envelope = Envelope::decode(&mut &blob[..])?;
+        let payload = &mut envelope.payload.as_slice();
+        let (nonce, msg) = match envelope.version {
+            PROTOCOL_V1 => (None, LegacyMessage::decode(payload)?.upgrade()?),
+            PROTOCOL_V2 => {
+                let (nonce, msg) = <(Option<Nonce>, LegacyMessage<_, _, _, _>)>::decode(payload)?;
+                (nonce, msg.upgrade()?)
+            }
+            PROTOCOL_V3 => <(Option<Nonce>, Self)>::decode(payload)?,
+            _ => return Err("Unsupported protocol version".into()),
+        };
+        if msg.kind() != envelope.kind {
+            return Err("Message kind mismatch".into());
+        }
+        Ok((envelope.version, nonce, msg))
+    }
+
+    /// Message in the layout of protocol versions 1 and 2, `None` if it has no counterpart
+    fn downgrade(self) -> Option<LegacyMessage<XAccountId, XBalance, Payout, Moment>> {
+        use LegacyMessage as Legacy;
+        Some(match self {
+            XCMPMessage::NewOrder(_, client, order) => Legacy::NewOrder(client, order),
+            XCMPMessage::OrderAccept(_, client, device) => Legacy::OrderAccept(client, device),
+            XCMPMessage::OrderReject(_, client, device, onoff) => {
+                Legacy::OrderReject(client, device, onoff)
+            }
+            XCMPMessage::OrderDone(_, client, device, onoff) => {
+                Legacy::OrderDone(client, device, onoff)
+            }
+            XCMPMessage::OrderProgress(_, client, device, telemetry) => {
+                Legacy::OrderProgress(client, device, telemetry)
+            }
+            XCMPMessage::OrderCancel(_, client, device) => Legacy::OrderCancel(client, device),
+            XCMPMessage::OrderAmend(_, client, device, amendment) => {
+                Legacy::OrderAmend(client, device, amendment)
+            }
+            XCMPMessage::OrderAmendAccept(_, client, device) => {
+                Legacy::OrderAmendAccept(client, device)
+            }
+            XCMPMessage::OrderFailed(_, client, device) => Legacy::OrderFailed(client, device),
+            XCMPMessage::Handshake(version) => Legacy::Handshake(version),
+            XCMPMessage::HandshakeAck(version) => Legacy::HandshakeAck(version),
+            XCMPMessage::Ack(nonce) => Legacy::Ack(nonce),
+            XCMPMessage::Nack(nonce) => Legacy::Nack(nonce),
+            XCMPMessage::Reconcile(epoch, entries) => Legacy::Reconcile(epoch, entries),
+            XCMPMessage::OrderCancelRejected(_, client, device) => {
+                Legacy::OrderCancelRejected(client, device)
+            }
+            XCMPMessage::DeviceAnnounce(seq, device, penalty, wcd, state) => {
+                Legacy::DeviceAnnounce(seq, device, penalty, wcd, state)
+            }
+            XCMPMessage::DeviceUpdate(seq, device, penalty, wcd) => {
+                Legacy::DeviceUpdate(seq, device, penalty, wcd)
+            }
+            XCMPMessage::DeviceStateChanged(seq, device, state) => {
+                Legacy::DeviceStateChanged(seq, device, state)
+            }
+            XCMPMessage::Subscribe => Legacy::Subscribe,
+            XCMPMessage::Unsubscribe => Legacy::Unsubscribe,
+            XCMPMessage::DeviceSnapshot(next, devices) => Legacy::DeviceSnapshot(
+                next,
+                devices
+                    .into_iter()
+                    .map(|(device, penalty, wcd, state, _)| (device, penalty, wcd, state))
+                    .collect(),
+            ),
+            XCMPMessage::OrderAmendReject(..)
+            | XCMPMessage::DevicePolicy(..)
+            | XCMPMessage::SettlementChanged(..)
+            | XCMPMessage::OrderCancelled(..) => return None,
+        })
+    }
+}
+
+/// Wire layout of protocol versions 1 and 2, order messages carry no order id
+#[derive(codec::Encode, codec::Decode, Clone)]
+pub enum LegacyMessage<XAccountId, XBalance, Payout: Encode + Decode, Moment> {
     NewOrder(XAccountId, OrderBase<Payout, XBalance, Moment, XAccountId>),
-    /// Order accepted by device (clientid, deviceid)
     OrderAccept(XAccountId, XAccountId),
-    /// Order rejected by device (clientid, deviceid, on/off)
     OrderReject(XAccountId, XAccountId, bool),
-    /// Order completed (clientid, deviceid, on/off)
     OrderDone(XAccountId, XAccountId, bool),
+    OrderProgress(XAccountId, XAccountId, Telemetry),
+    OrderCancel(XAccountId, XAccountId),
+    OrderAmend(XAccountId, XAccountId, Amendment<XBalance, Moment>),
+    OrderAmendAccept(XAccountId, XAccountId),
+    OrderFailed(XAccountId, XAccountId),
+    Handshake(ProtocolVersion),
+    HandshakeAck(ProtocolVersion),
+    Ack(Nonce),
+    Nack(Nonce),
+    Reconcile(EpochIndex, Vec<(XAccountId, XBalance, XBalance)>),
+    OrderCancelRejected(XAccountId, XAccountId),
+    DeviceAnnounce(u64, XAccountId, XBalance, Moment, DeviceState),
+    DeviceUpdate(u64, XAccountId, XBalance, Moment),
+    DeviceStateChanged(u64, XAccountId, DeviceState),
+    Subscribe,
+    Unsubscribe,
+    DeviceSnapshot(u64, Vec<(XAccountId, XBalance, Moment, DeviceState)>),
+}
+
+impl<XAccountId, XBalance, Payout: Encode + Decode, Moment>
+    LegacyMessage<XAccountId, XBalance, Payout, Moment>
+{
+    /// Message in the current layout, order messages are refused as they can't be told apart
+    /// without the order id
+    fn upgrade(self) -> Result<XCMPMessage<XAccountId, XBalance, Payout, Moment>, codec::Error> {
+        use LegacyMessage as Legacy;
+        Ok(match self {
+            Legacy::Handshake(version) => XCMPMessage::Handshake(version),
+            Legacy::HandshakeAck(version) => XCMPMessage::HandshakeAck(version),
+            Legacy::Ack(nonce) => XCMPMessage::Ack(nonce),
+            Legacy::Nack(nonce) => XCMPMessage::Nack(nonce),
+            Legacy::Reconcile(epoch, entries) => XCMPMessage::Reconcile(epoch, entries),
+            Legacy::DeviceAnnounce(seq, device, penalty, wcd, state) => {
+                XCMPMessage::DeviceAnnounce(seq, device, penalty, wcd, state)
+            }
+            Legacy::DeviceUpdate(seq, device, penalty, wcd) => {
+                XCMPMessage::DeviceUpdate(seq, device, penalty, wcd)
+            }
+            Legacy::DeviceStateChanged(seq, device, state) => {
+                XCMPMessage::DeviceStateChanged(seq, device, state)
+            }
+            Legacy::Subscribe => XCMPMessage::Subscribe,
+            Legacy::Unsubscribe => XCMPMessage::Unsubscribe,
+            Legacy::DeviceSnapshot(next, devices) => XCMPMessage::DeviceSnapshot(
+                next,
+                devices
+                    .into_iter()
+                    .map(|(device, penalty, wcd, state)| {
+                        (device, penalty, wcd, state, AccessPolicy::Open)
+                    })
+                    .collect(),
+            ),
+            _ => return Err("Order message without order id".into()),
+        })
+    }
+}
+
+/// Version of the cross-chain message wire format
+pub type ProtocolVersion = u8;
+
+/// Bare SCALE encoded `XCMPMessage`, spoken by chains which never completed a handshake.
+/// It carries no version and is read in the current layout
+pub const PROTOCOL_V0: ProtocolVersion = 0;
+
+/// Enveloped messages without acknowledgements, order messages identified by the device alone
+pub const PROTOCOL_V1: ProtocolVersion = 1;
+
+/// Enveloped messages carrying nonces, acknowledged by the receiver
+pub const PROTOCOL_V2: ProtocolVersion = 2;
+
+/// Order messages carry the order id assigned by the client parachain
+pub const PROTOCOL_V3: ProtocolVersion = 3;
+
+/// Highest supported protocol version
+pub const PROTOCOL_VERSION: ProtocolVersion = PROTOCOL_V3;
+
+/// Per-channel sequence number of an acknowledged message
+pub type Nonce = u64;
+
+/// Prefix of enveloped messages, never a valid first byte of a bare message
+pub const MESSAGE_MAGIC: [u8; 4] = *b"rbnx";
+
+/// Cross-chain message waiting in the outbox for delivery
+#[derive(Encode, Decode, Clone, RuntimeDebug, PartialEq)]
+pub struct OutboundMessage<BlockNumber> {
+    /// Message in the wire format
+    pub blob: Vec<u8>,
+    pub kind: MessageKind,
+    /// Failed delivery attempts
+    pub attempts: u32,
+    /// Block the next delivery attempt is made at
+    pub next_attempt: BlockNumber,
+}
+
+/// Position of an inbound nonce relative to the sequence window
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum Sequence {
+    /// Next expected nonce
+    InOrder,
+    /// Within the window, nonces from the expected one are still missing
+    Gap(Nonce),
+    /// Already processed
+    Duplicate,
+    /// Beyond the window
+    TooFar,
+}
+
+/// Inbound nonces processed from a parachain
+#[derive(Encode, Decode, Clone, Default, RuntimeDebug, PartialEq)]
+pub struct SeqWindow {
+    /// Every nonce below is processed
+    pub watermark: Nonce,
+    /// Nonces processed ahead of the watermark, bit `i` stands for `watermark + 1 + i`
+    pub seen: u64,
+}
+
+impl SeqWindow {
+    /// Nonces accepted ahead of the watermark
+    pub const SIZE: Nonce = 64;
+
+    pub fn check(&self, nonce: Nonce) -> Sequence {
+        if nonce < self.watermark {
+            return Sequence::Duplicate;
+        }
+        if nonce == self.watermark {
+            return Sequence::InOrder;
+        }
+        let offset = nonce - self.watermark - 1;
+        if offset >= Self::SIZE {
+            Sequence::TooFar
+        } else if self.seen & (1 << offset) != 0 {
+            Sequence::Duplicate
+        } else {
+            Sequence::Gap(self.watermark)
+        }
+    }
+
+    /// Marks the nonce processed, duplicates and nonces beyond the window are ignored
+    pub fn mark(&mut self, nonce: Nonce) {
+        match self.check(nonce) {
+            Sequence::InOrder => {
+                // Bit `i` stands for `watermark + i` while moving the watermark
+                self.watermark += 1;
+                while self.seen & 1 != 0 {
+                    self.seen >>= 1;
+                    self.watermark += 1;
+                }
+                self.seen >>= 1;
+            }
+            Sequence::Gap(_) => self.seen |= 1 << (nonce - self.watermark - 1),
+            Sequence::Duplicate | Sequence::TooFar => {}
+        }
+    }
+}
+
+/// Sent message waiting for the receiver acknowledgement
+#[derive(Encode, Decode, Clone, RuntimeDebug, PartialEq)]
+pub struct PendingAck<BlockNumber> {
+    /// Message in the wire format
+    pub blob: Vec<u8>,
+    pub kind: MessageKind,
+    /// Block the message was last sent at
+    pub sent_at: BlockNumber,
+    /// Times the message was sent again
+    pub resends: u32,
+}
+
+/// Versioned wire format of cross-chain messages
+#[derive(Encode, Decode, Clone, RuntimeDebug, PartialEq)]
+pub struct Envelope {
+    pub magic: [u8; 4],
+    pub version: ProtocolVersion,
+    pub kind: MessageKind,
+    /// Encoded `XCMPMessage`, preceded by its nonce since version 2, in the legacy layout
+    /// before version 3
+    pub payload: Vec<u8>,
+}
+
+/// Kind of the cross-chain message
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum MessageKind {
+    NewOrder,
+    OrderAccept,
+    OrderReject,
+    OrderDone,
+    OrderProgress,
+    OrderCancel,
+    OrderAmend,
+    OrderAmendAccept,
+    OrderFailed,
+    Handshake,
+    HandshakeAck,
+    Ack,
+    Nack,
+    Reconcile,
+    OrderCancelRejected,
+    DeviceAnnounce,
+    DeviceUpdate,
+    DeviceStateChanged,
+    Subscribe,
+    Unsubscribe,
+    DeviceSnapshot,
+    OrderAmendReject,
+    DevicePolicy,
+    SettlementChanged,
+    OrderCancelled,
+}
+
+impl MessageKind {
+    /// Order messages are acknowledged by the receiver
+    pub fn needs_ack(&self) -> bool {
+        !matches!(
+            self,
+            MessageKind::Handshake
+                | MessageKind::HandshakeAck
+                | MessageKind::Ack
+                | MessageKind::Nack
+        )
+    }
+}
+
+/// Scope of a cross-chain message processing pause
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum PauseTarget<ParaId> {
+    /// Emergency pause of all messages and new orders
+    All,
+    /// Messages from the parachain
+    Para(ParaId),
+    /// Messages of the kind
+    Kind(MessageKind),
+}
+
+/// Weight of inbound XCM, `None` if the message can't be weighed
+pub trait WeighXcm {
+    fn weigh(xcm: &Xcm) -> Option<Weight>;
+}
+
+/// Weighs every instruction and order of the message at `InstructionWeight`, calls
+/// dispatched by `Transact` add their own dispatch weight
+pub struct FixedXcmWeight<InstructionWeight, Call>(PhantomData<(InstructionWeight, Call)>);
+
+impl<InstructionWeight: Get<Weight>, Call: Decode + GetDispatchInfo>
+    FixedXcmWeight<InstructionWeight, Call>
+{
+    fn weigh_orders(orders: &[XcmOrder]) -> Option<Weight> {
+        orders.iter().try_fold(0 as Weight, |weight, order| {
+            let nested = match order {
+                XcmOrder::DepositReserveAsset { effects, .. }
+                | XcmOrder::InitiateReserveWithdraw { effects, .. }
+                | XcmOrder::InitiateTeleport { effects, .. } => Self::weigh_orders(effects)?,
+                _ => 0,
+            };
+            weight
+                .checked_add(InstructionWeight::get())?
+                .checked_add(nested)
+        })
+    }
+}
+
+impl<InstructionWeight: Get<Weight>, Call: Decode + GetDispatchInfo> WeighXcm
+    for FixedXcmWeight<InstructionWeight, Call>
+{
+    fn weigh(xcm: &Xcm) -> Option<Weight> {
+        let nested = match xcm {
+            Xcm::WithdrawAsset { effects, .. }
+            | Xcm::ReserveAssetDeposit { effects, .. }
+            | Xcm::TeleportAsset { effects, .. } => Self::weigh_orders(effects)?,
+            Xcm::Transact { call, .. } => {
+                let call = Call::decode(&mut &call[..]).ok()?;
+                call.get_dispatch_info().weight
+            }
+            _ => 0,
+        };
+        InstructionWeight::get().checked_add(nested)
+    }
 }
@@ -0,0 +1,561 @@
+//! Cross-chain messaging shared by the service and client pallets
+//!
+//! Delivery through the outbox, acknowledgements and resends, inbound sequencing and the
+//! queue of paused messages work alike on both ends of a channel. The pallets provide the
+//! storage items, their events and errors and the processing of single messages.
+use super::{
+    LegacyMessage, MessageKind, Nonce, OutboundMessage, PauseTarget, PendingAck, ProtocolVersion,
+    SeqWindow, Sequence, XCMPMessage, PROTOCOL_V0, PROTOCOL_V2, PROTOCOL_VERSION,
+};
+use codec::{Decode, Encode, FullCodec};
+use frame_support::{
+    dispatch::{DispatchError, DispatchResult},
+    sp_std::{fmt::Debug, prelude::*},
+    storage::{IterableStorageDoubleMap, StorageDoubleMap, StorageMap, StorageValue},
+    traits::Get,
+    weights::Weight,
+};
+
+pub type BlockNumberOf<M> = <<M as Messaging>::Runtime as frame_system::Config>::BlockNumber;
+pub type PendingAckOf<M> = PendingAck<BlockNumberOf<M>>;
+pub type OutboundMessageOf<M> = OutboundMessage<BlockNumberOf<M>>;
+
+/// Message exchanged over the channel
+pub trait WireMessage: FullCodec {
+    fn kind(&self) -> MessageKind;
+
+    /// Encodes the message in the wire format of the protocol version
+    fn to_wire(&self, version: ProtocolVersion, nonce: Option<Nonce>) -> Vec<u8>;
+
+    /// Reply to the processed message carrying the nonce
+    fn ack(nonce: Nonce) -> Self;
+
+    /// Reply to the refused message carrying the nonce
+    fn nack(nonce: Nonce) -> Self;
+}
+
+impl<XAccountId, XBalance, Payout: Encode + Decode, Moment> WireMessage
+    for XCMPMessage<XAccountId, XBalance, Payout, Moment>
+where
+    Self: FullCodec + Clone,
+    LegacyMessage<XAccountId, XBalance, Payout, Moment>: Encode,
+{
+    fn kind(&self) -> MessageKind {
+        XCMPMessage::kind(self)
+    }
+
+    fn to_wire(&self, version: ProtocolVersion, nonce: Option<Nonce>) -> Vec<u8> {
+        XCMPMessage::to_wire(self, version, nonce)
+    }
+
+    fn ack(nonce: Nonce) -> Self {
+        XCMPMessage::Ack(nonce)
+    }
+
+    fn nack(nonce: Nonce) -> Self {
+        XCMPMessage::Nack(nonce)
+    }
+}
+
+/// Events of the channel, deposited as the pallet's own
+pub enum MessagingEvent<ParaId> {
+    /// Message could not be sent, deferred to the outbox \[dest, kind\]
+    MessageDeferred(ParaId, MessageKind),
+    /// Deferred message sent \[dest, kind\]
+    MessageDelivered(ParaId, MessageKind),
+    /// Deferred message dropped after too many attempts \[dest, kind, attempts\]
+    MessageAbandoned(ParaId, MessageKind, u32),
+    /// Message not sent, the outbox of the destination is full \[dest, kind\]
+    MessageRefused(ParaId, MessageKind),
+    /// Message not acknowledged in time, sent again \[dest, nonce, kind, resends\]
+    MessageUnacknowledged(ParaId, Nonce, MessageKind, u32),
+    /// Message still not acknowledged after the last resend, given up \[dest, nonce, kind\]
+    UnackedAbandoned(ParaId, Nonce, MessageKind),
+    /// Message received ahead of the expected one \[sender, expected, received\]
+    SequenceGap(ParaId, Nonce, Nonce),
+    /// Already processed message dropped \[sender, nonce\]
+    DuplicateDropped(ParaId, Nonce),
+    /// Message received while paused, queued for processing \[sender, kind\]
+    MessageQueued(ParaId, MessageKind),
+    /// Message received while paused and dropped, the queue is full \[sender, kind\]
+    MessageDropped(ParaId, MessageKind),
+}
+
+/// Errors of the channel, returned as the pallet's own
+pub enum MessagingError {
+    OutboxFull,
+    TooManyUnacked,
+}
+
+/// Cross-chain channels of a pallet
+pub trait Messaging {
+    type Runtime: frame_system::Config;
+    type ParaId: FullCodec + Copy + PartialEq + Debug;
+    type Message: WireMessage;
+    type SendError: Debug;
+    type Error: From<MessagingError> + Into<DispatchError>;
+
+    /// Blocks a sent message waits for the acknowledgement before it is sent again
+    type AckTimeout: Get<BlockNumberOf<Self>>;
+    /// Messages awaiting acknowledgement per destination
+    type MaxUnacked: Get<u32>;
+    /// Delivery attempts and resends before a message is given up
+    type MaxSendAttempts: Get<u32>;
+    /// Deferred messages per destination
+    type MaxOutboxMessages: Get<u32>;
+    /// Delay before the first delivery retry
+    type RetryBackoff: Get<BlockNumberOf<Self>>;
+    /// Messages queued while paused
+    type MaxPausedMessages: Get<u32>;
+
+    type ProtocolVersions: StorageMap<
+        Self::ParaId,
+        ProtocolVersion,
+        Query = Option<ProtocolVersion>,
+    >;
+    type NextNonce: StorageMap<Self::ParaId, Nonce, Query = Nonce>;
+    type Unacked: StorageDoubleMap<
+            Self::ParaId,
+            Nonce,
+            PendingAckOf<Self>,
+            Query = Option<PendingAckOf<Self>>,
+        > + IterableStorageDoubleMap<Self::ParaId, Nonce, PendingAckOf<Self>>;
+    type UnackedCount: StorageMap<Self::ParaId, u32, Query = u32>;
+    type UnackedDestinations: StorageValue<Vec<Self::ParaId>, Query = Vec<Self::ParaId>>;
+    type UnackedCursor: StorageValue<u32, Query = u32>;
+    type InboundSequence: StorageMap<Self::ParaId, SeqWindow, Query = SeqWindow>;
+    type InboundRefused: StorageDoubleMap<Self::ParaId, Nonce, (), Query = Option<()>>;
+    type Outbox: StorageDoubleMap<
+        Self::ParaId,
+        u64,
+        OutboundMessageOf<Self>,
+        Query = Option<OutboundMessageOf<Self>>,
+    >;
+    type OutboxBounds: StorageMap<Self::ParaId, (u64, u64), Query = (u64, u64)>;
+    type OutboxDestinations: StorageValue<Vec<Self::ParaId>, Query = Vec<Self::ParaId>>;
+    type OutboxCursor: StorageValue<u32, Query = u32>;
+    type Pauses: StorageMap<PauseTarget<Self::ParaId>, bool, Query = bool>;
+    type PausedMessages: StorageMap<
+        u64,
+        (Self::ParaId, Self::Message),
+        Query = Option<(Self::ParaId, Self::Message)>,
+    >;
+    type PausedMessagesBounds: StorageValue<(u64, u64), Query = (u64, u64)>;
+
+    fn deposit_messaging_event(event: MessagingEvent<Self::ParaId>);
+
+    /// Hands the blob to the XCMP queue
+    fn send_blob_message(dest: Self::ParaId, blob: Vec<u8>) -> Result<(), Self::SendError>;
+
+    /// Worst case weight of the message handler
+    fn message_weight(msg: &Self::Message) -> Weight;
+
+    fn process_message(sender: Self::ParaId, msg: Self::Message) -> DispatchResult;
+
+    fn resend_message_weight() -> Weight;
+
+    fn retry_message_weight() -> Weight;
+
+    fn queue_message_weight() -> Weight;
+
+    /// Protocol version the message kind is encoded with for `dest`
+    fn wire_version(dest: Self::ParaId, kind: MessageKind) -> ProtocolVersion {
+        // Handshakes are understood by any chain able to negotiate
+        match kind {
+            MessageKind::Handshake | MessageKind::HandshakeAck => PROTOCOL_VERSION,
+            _ => Self::ProtocolVersions::get(dest).unwrap_or(PROTOCOL_V0),
+        }
+    }
+
+    /// Fails while the outbox of `dest` is full or, for messages awaiting acknowledgement,
+    /// too many of them are unacknowledged
+    fn ensure_can_send(dest: Self::ParaId, kind: MessageKind) -> DispatchResult {
+        let (head, tail) = Self::OutboxBounds::get(dest);
+        if tail - head >= Self::MaxOutboxMessages::get() as u64 {
+            return Err(Self::Error::from(MessagingError::OutboxFull).into());
+        }
+        if Self::wire_version(dest, kind) >= PROTOCOL_V2
+            && kind.needs_ack()
+            && Self::UnackedCount::get(dest) >= Self::MaxUnacked::get()
+        {
+            return Err(Self::Error::from(MessagingError::TooManyUnacked).into());
+        }
+        Ok(())
+    }
+
+    /// Sends the message, tracking its acknowledgement when the parachain supports it,
+    /// refused while `dest` can't take it
+    fn send_message(dest: Self::ParaId, msg: Self::Message) -> DispatchResult {
+        let kind = msg.kind();
+        Self::ensure_can_send(dest, kind)?;
+
+        let version = Self::wire_version(dest, kind);
+        let nonce = if version >= PROTOCOL_V2 && kind.needs_ack() {
+            Some(Self::NextNonce::mutate(dest, |next| {
+                *next += 1;
+                *next - 1
+            }))
+        } else {
+            None
+        };
+        let blob = msg.to_wire(version, nonce);
+
+        if let Some(nonce) = nonce {
+            let pending = PendingAck {
+                blob: blob.clone(),
+                kind,
+                sent_at: frame_system::Pallet::<Self::Runtime>::block_number(),
+                resends: 0,
+            };
+            Self::track_unacked(dest, nonce, pending);
+        }
+        Self::send_blob(dest, blob, kind);
+        Ok(())
+    }
+
+    /// Sends the message unless the outbox is full, for messages whose loss the receiver
+    /// recovers from
+    fn try_send(dest: Self::ParaId, msg: Self::Message) {
+        let kind = msg.kind();
+        if let Err(e) = Self::send_message(dest, msg) {
+            log::warn!("{:?} to {:?} dropped: {:?}", kind, dest, e);
+            Self::deposit_messaging_event(MessagingEvent::MessageRefused(dest, kind));
+        }
+    }
+
+    /// Sends the blob or defers it to the outbox, keeping the order of messages to `dest`
+    fn send_blob(dest: Self::ParaId, blob: Vec<u8>, kind: MessageKind) {
+        let now = frame_system::Pallet::<Self::Runtime>::block_number();
+
+        let attempts = if Self::OutboxBounds::contains_key(dest) {
+            0
+        } else {
+            match Self::send_blob_message(dest, blob.clone()) {
+                Ok(_) => return,
+                Err(e) => {
+                    log::warn!("{:?} to {:?} deferred: {:?}", kind, dest, e);
+                    1
+                }
+            }
+        };
+
+        let next_attempt = if attempts == 0 {
+            now
+        } else {
+            Self::OutboxDestinations::append(dest);
+            now + Self::backoff(attempts)
+        };
+        Self::OutboxBounds::mutate(dest, |(_, tail)| {
+            Self::Outbox::insert(
+                dest,
+                *tail,
+                OutboundMessage {
+                    blob,
+                    kind,
+                    attempts,
+                    next_attempt,
+                },
+            );
+            *tail += 1;
+        });
+        Self::deposit_messaging_event(MessagingEvent::MessageDeferred(dest, kind));
+    }
+
+    /// Delay before the next delivery attempt, doubled with every failed one
+    fn backoff(attempts: u32) -> BlockNumberOf<Self> {
+        Self::RetryBackoff::get() * (1u32 << attempts.saturating_sub(1).min(16)).into()
+    }
+
+    /// Retries delivery of deferred messages, within the weight limit,
+    /// destinations take turns so a congested one doesn't hold back the rest
+    fn retry_outbox(now: BlockNumberOf<Self>, limit: Weight) -> Weight {
+        let db = <Self::Runtime as frame_system::Config>::DbWeight::get();
+        let per_message = Self::retry_message_weight();
+        let mut destinations = Self::OutboxDestinations::get();
+        let mut used = db.reads(2);
+        if destinations.is_empty() {
+            return used;
+        }
+
+        let rounds = destinations.len();
+        let mut index = Self::OutboxCursor::get() as usize % rounds;
+        let mut drained = false;
+        for _ in 0..rounds {
+            if used + db.reads_writes(1, 1) + per_message > limit {
+                break;
+            }
+            used += db.reads_writes(1, 1);
+            let dest = destinations[index];
+            let (start, tail) = Self::OutboxBounds::get(dest);
+            let mut head = start;
+
+            while head < tail && used + per_message <= limit {
+                let mut pending = match Self::Outbox::get(dest, head) {
+                    Some(pending) => pending,
+                    None => {
+                        head += 1;
+                        continue;
+                    }
+                };
+                if pending.next_attempt > now {
+                    break;
+                }
+                used += per_message;
+
+                if Self::send_blob_message(dest, pending.blob.clone()).is_ok() {
+                    Self::Outbox::remove(dest, head);
+                    head += 1;
+                    Self::deposit_messaging_event(MessagingEvent::MessageDelivered(
+                        dest,
+                        pending.kind,
+                    ));
+                    continue;
+                }
+
+                pending.attempts += 1;
+                if pending.attempts >= Self::MaxSendAttempts::get() {
+                    Self::Outbox::remove(dest, head);
+                    head += 1;
+                    Self::deposit_messaging_event(MessagingEvent::MessageAbandoned(
+                        dest,
+                        pending.kind,
+                        pending.attempts,
+                    ));
+                } else {
+                    // Later messages wait for this one to keep the channel order
+                    pending.next_attempt = now + Self::backoff(pending.attempts);
+                    Self::Outbox::insert(dest, head, pending);
+                    break;
+                }
+            }
+
+            if head == tail {
+                Self::OutboxBounds::remove(dest);
+                destinations.remove(index);
+                drained = true;
+                if destinations.is_empty() {
+                    break;
+                }
+            } else {
+                if head != start {
+                    Self::OutboxBounds::insert(dest, (head, tail));
+                }
+                index += 1;
+            }
+            index %= destinations.len();
+        }
+
+        if drained {
+            Self::OutboxDestinations::put(&destinations);
+        }
+        Self::OutboxCursor::put(index as u32);
+        used + db.writes(2)
+    }
+
+    /// Tracks the sent message until the receiver acknowledges it
+    fn track_unacked(dest: Self::ParaId, nonce: Nonce, pending: PendingAckOf<Self>) {
+        let count = Self::UnackedCount::mutate(dest, |count| {
+            *count += 1;
+            *count
+        });
+        if count == 1 {
+            Self::UnackedDestinations::append(dest);
+        }
+        Self::Unacked::insert(dest, nonce, pending);
+    }
+
+    /// Stops tracking the message, returns it if it was still unacknowledged
+    fn take_unacked(dest: Self::ParaId, nonce: Nonce) -> Option<PendingAckOf<Self>> {
+        let pending = Self::Unacked::take(dest, nonce)?;
+        let count = Self::UnackedCount::get(dest).saturating_sub(1);
+        if count == 0 {
+            Self::UnackedCount::remove(dest);
+            Self::UnackedDestinations::mutate(|dests| dests.retain(|d| *d != dest));
+        } else {
+            Self::UnackedCount::insert(dest, count);
+        }
+        Some(pending)
+    }
+
+    /// Sends again messages not acknowledged in time, within the weight limit,
+    /// destinations take turns and are given up after `MaxSendAttempts` resends
+    fn resend_unacked(now: BlockNumberOf<Self>, limit: Weight) -> Weight {
+        let db = <Self::Runtime as frame_system::Config>::DbWeight::get();
+        let timeout = Self::AckTimeout::get();
+        let per_message = Self::resend_message_weight();
+        let max_unacked = Self::MaxUnacked::get() as Weight;
+        // Every message of the destination is checked once started
+        let per_destination = db.reads(1 + max_unacked) + per_message * max_unacked;
+        let destinations = Self::UnackedDestinations::get();
+        let mut used = db.reads(2);
+        if destinations.is_empty() {
+            return used;
+        }
+
+        let rounds = destinations.len();
+        let mut index = Self::UnackedCursor::get() as usize % rounds;
+        for _ in 0..rounds {
+            if used + per_destination > limit {
+                break;
+            }
+            let dest = destinations[index];
+            index = (index + 1) % rounds;
+            used += db.reads(1);
+            // A message waiting in the outbox is delivered from there, not appended again
+            let deferred = Self::OutboxBounds::contains_key(dest);
+
+            let pending: Vec<_> = Self::Unacked::iter_prefix(dest).collect();
+            for (nonce, mut pending) in pending {
+                used += db.reads(1);
+                if pending.sent_at + timeout > now {
+                    continue;
+                }
+                used += per_message;
+
+                if pending.resends >= Self::MaxSendAttempts::get() {
+                    Self::take_unacked(dest, nonce);
+                    Self::deposit_messaging_event(MessagingEvent::UnackedAbandoned(
+                        dest,
+                        nonce,
+                        pending.kind,
+                    ));
+                    continue;
+                }
+                pending.sent_at = now;
+                if !deferred {
+                    pending.resends += 1;
+                    if let Err(e) = Self::send_blob_message(dest, pending.blob.clone()) {
+                        log::warn!("{:?} to {:?} not resent: {:?}", pending.kind, dest, e);
+                    }
+                    Self::deposit_messaging_event(MessagingEvent::MessageUnacknowledged(
+                        dest,
+                        nonce,
+                        pending.kind,
+                        pending.resends,
+                    ));
+                }
+                Self::Unacked::insert(dest, nonce, pending);
+            }
+        }
+        Self::UnackedCursor::put(index as u32);
+        used + db.writes(1)
+    }
+
+    /// Records the inbound nonce, false if the message must be dropped
+    fn check_sequence(sender: Self::ParaId, nonce: Nonce) -> bool {
+        let mut window = Self::InboundSequence::get(sender);
+        match window.check(nonce) {
+            Sequence::InOrder => {}
+            Sequence::Gap(expected) => {
+                Self::deposit_messaging_event(MessagingEvent::SequenceGap(sender, expected, nonce));
+            }
+            Sequence::Duplicate => {
+                // The reply could be lost, the sender resends until it gets one.
+                // Outcomes are known a window behind the watermark, older duplicates get none
+                if nonce + SeqWindow::SIZE > window.watermark {
+                    let reply = if Self::InboundRefused::contains_key(sender, nonce) {
+                        Self::Message::nack(nonce)
+                    } else {
+                        Self::Message::ack(nonce)
+                    };
+                    Self::try_send(sender, reply);
+                }
+                Self::deposit_messaging_event(MessagingEvent::DuplicateDropped(sender, nonce));
+                return false;
+            }
+            Sequence::TooFar => {
+                log::warn!("nonce {:?} from {:?} beyond the window", nonce, sender);
+                Self::deposit_messaging_event(MessagingEvent::SequenceGap(
+                    sender,
+                    window.watermark,
+                    nonce,
+                ));
+                // Refused, the sender would wait for an acknowledgement otherwise
+                Self::try_send(sender, Self::Message::nack(nonce));
+                return false;
+            }
+        }
+        window.mark(nonce);
+        Self::InboundSequence::insert(sender, window);
+        // Nonces processed later are at most a window ahead of the watermark
+        if let Some(stale) = nonce.checked_sub(2 * SeqWindow::SIZE) {
+            Self::InboundRefused::remove(sender, stale);
+        }
+        true
+    }
+
+    /// Reports the processing result of the message carrying a nonce to its sender
+    fn acknowledge(sender: Self::ParaId, nonce: Option<Nonce>, result: DispatchResult) {
+        if let Some(nonce) = nonce {
+            let reply = match result {
+                Ok(_) => Self::Message::ack(nonce),
+                Err(_) => {
+                    Self::InboundRefused::insert(sender, nonce, ());
+                    Self::Message::nack(nonce)
+                }
+            };
+            Self::try_send(sender, reply);
+        }
+    }
+
+    /// Processing of the message kind from the sender is paused
+    fn is_paused(sender: Self::ParaId, kind: MessageKind) -> bool {
+        Self::Pauses::get(PauseTarget::All)
+            || Self::Pauses::get(PauseTarget::Para(sender))
+            || Self::Pauses::get(PauseTarget::Kind(kind))
+    }
+
+    /// Holds the message until processing is resumed
+    fn queue_paused(sender: Self::ParaId, msg: Self::Message) -> Weight {
+        let kind = msg.kind();
+        let (head, tail) = Self::PausedMessagesBounds::get();
+
+        if tail - head >= Self::MaxPausedMessages::get() as u64 {
+            log::warn!("paused message queue is full, {:?} dropped", kind);
+            Self::deposit_messaging_event(MessagingEvent::MessageDropped(sender, kind));
+        } else {
+            Self::PausedMessages::insert(tail, (sender, msg));
+            Self::PausedMessagesBounds::put((head, tail + 1));
+            Self::deposit_messaging_event(MessagingEvent::MessageQueued(sender, kind));
+        }
+        Self::queue_message_weight()
+    }
+
+    /// Processes messages queued while paused, within the weight limit
+    fn replay_paused(limit: Weight) -> Weight {
+        let db = <Self::Runtime as frame_system::Config>::DbWeight::get();
+        let (start, tail) = Self::PausedMessagesBounds::get();
+        let mut head = start;
+        let mut used = db.reads(1);
+
+        while head < tail {
+            used += db.reads(4);
+            let (sender, msg) = match Self::PausedMessages::get(head) {
+                Some(queued) => queued,
+                None => {
+                    head += 1;
+                    continue;
+                }
+            };
+            // Messages are processed in order, the paused head holds back the rest
+            if Self::is_paused(sender, msg.kind()) {
+                break;
+            }
+            let weight = Self::message_weight(&msg) + db.writes(1);
+            if used + weight > limit {
+                break;
+            }
+            Self::PausedMessages::remove(head);
+            head += 1;
+            used += weight;
+            // Acknowledged when queued
+            let _ = Self::process_message(sender, msg);
+        }
+
+        if head != start {
+            Self::PausedMessagesBounds::put((head, tail));
+            used += db.writes(1);
+        }
+        used
+    }
+}
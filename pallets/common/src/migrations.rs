@@ -0,0 +1,165 @@
+//! Storage migrations shared by the service and client pallets
+//!
+//! Both pallets keep orders in the `Orders` map keyed by device with `Twox64Concat`,
+//! the helpers address it by the pallet prefix.
+use super::{Order, OrderId, RelayBlockNumber};
+use codec::{Decode, Encode};
+use frame_support::{
+    sp_std::{collections::btree_map::BTreeMap, prelude::*},
+    storage::{
+        migration::{put_storage_value, storage_key_iter},
+        unhashed,
+    },
+    StorageHasher, Twox64Concat,
+};
+
+const ORDERS: &[u8] = b"Orders";
+
+/// Order layout of `Releases::V0`, without relay chain deadline
+#[derive(Encode, Decode)]
+pub struct OrderV0<Payload, Balance, Moment, AccountId, ParaId> {
+    pub until: Moment,
+    pub data: Payload,
+    pub fee: Balance,
+    pub client: AccountId,
+    pub paraid: ParaId,
+}
+
+impl<Payload, Balance, Moment, AccountId, ParaId>
+    OrderV0<Payload, Balance, Moment, AccountId, ParaId>
+{
+    pub fn upgrade(self) -> OrderV1<Payload, Balance, Moment, AccountId, ParaId> {
+        OrderV1 {
+            until: self.until,
+            until_relay: None,
+            data: self.data,
+            fee: self.fee,
+            client: self.client,
+            paraid: self.paraid,
+        }
+    }
+}
+
+/// Order layout of `Releases::V1`, without order id
+#[derive(Encode, Decode)]
+pub struct OrderV1<Payload, Balance, Moment, AccountId, ParaId> {
+    pub until: Moment,
+    pub until_relay: Option<RelayBlockNumber>,
+    pub data: Payload,
+    pub fee: Balance,
+    pub client: AccountId,
+    pub paraid: ParaId,
+}
+
+impl<Payload: Encode + Decode, Balance, Moment, AccountId, ParaId>
+    OrderV1<Payload, Balance, Moment, AccountId, ParaId>
+{
+    pub fn upgrade(self, id: OrderId) -> Order<Payload, Balance, Moment, AccountId, ParaId> {
+        Order {
+            id,
+            until: self.until,
+            until_relay: self.until_relay,
+            data: self.data,
+            fee: self.fee,
+            client: self.client,
+            paraid: self.paraid,
+        }
+    }
+}
+
+/// Rewrites every order of the pallet with `f` in the key order, returns the number of orders
+pub fn translate_orders<AccountId, Old, New>(
+    pallet: &[u8],
+    mut f: impl FnMut(&AccountId, Old) -> New,
+) -> u64
+where
+    AccountId: Encode + Decode,
+    Old: Decode,
+    New: Encode,
+{
+    let orders: Vec<(AccountId, Old)> =
+        storage_key_iter::<AccountId, Old, Twox64Concat>(pallet, ORDERS).collect();
+    let translated = orders.len() as u64;
+    for (device, old) in orders {
+        let key = Twox64Concat::hash(&device.encode());
+        put_storage_value(pallet, ORDERS, &key, f(&device, old));
+    }
+    translated
+}
+
+/// Order ids of a cross-chain channel counted from zero per parachain.
+///
+/// The client and the service parachain key orders by the same devices, so visiting them
+/// in the key order both sides assign the same id to an order they hold.
+#[derive(Default)]
+pub struct ParaOrderIds<ParaId>(BTreeMap<ParaId, OrderId>);
+
+impl<ParaId: Ord> ParaOrderIds<ParaId> {
+    /// Next id of the orders exchanged with the parachain
+    pub fn next(&mut self, paraid: ParaId) -> OrderId {
+        let id = self.0.entry(paraid).or_default();
+        *id += 1;
+        *id - 1
+    }
+
+    /// Lowest id not assigned to any parachain
+    pub fn end(&self) -> OrderId {
+        self.0.values().copied().max().unwrap_or_default()
+    }
+}
+
+fn count_key(pallet: &[u8], version: u8) -> Vec<u8> {
+    let mut key = b":robonomics:".to_vec();
+    key.extend_from_slice(pallet);
+    key.extend_from_slice(b":migration:orders:v");
+    key.push(b'0' + version);
+    key
+}
+
+/// Remembers the number of orders before the migration to `version`
+pub fn count_orders<AccountId: Decode, Old: Decode>(pallet: &[u8], version: u8) {
+    let orders = storage_key_iter::<AccountId, Old, Twox64Concat>(pallet, ORDERS).count() as u32;
+    unhashed::put(&count_key(pallet, version), &orders);
+}
+
+/// Checks whether orders were counted before the migration to `version`
+pub fn is_counted(pallet: &[u8], version: u8) -> bool {
+    unhashed::exists(&count_key(pallet, version))
+}
+
+/// Checks the migration to `version` kept every order decodable as `New`
+pub fn verify_orders<AccountId: Decode, New: Decode>(
+    pallet: &[u8],
+    version: u8,
+) -> Result<(), &'static str> {
+    let orders: u32 = unhashed::take(&count_key(pallet, version)).unwrap_or_default();
+    if storage_key_iter::<AccountId, New, Twox64Concat>(pallet, ORDERS).count() as u32 != orders {
+        return Err("orders are lost");
+    }
+    Ok(())
+}
+
+/// Checks the assigned order ids are unique and below `next_id`
+pub fn verify_order_ids(mut ids: Vec<OrderId>, next_id: OrderId) -> Result<(), &'static str> {
+    let orders = ids.len();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.len() != orders || ids.iter().any(|id| *id >= next_id) {
+        return Err("order ids are not unique");
+    }
+    Ok(())
+}
+
+/// Checks the order ids of every parachain count from zero without gaps
+pub fn verify_para_order_ids<ParaId: Ord>(
+    ids: impl Iterator<Item = (ParaId, OrderId)>,
+) -> Result<(), &'static str> {
+    let mut by_para = BTreeMap::<ParaId, Vec<OrderId>>::new();
+    for (paraid, id) in ids {
+        by_para.entry(paraid).or_default().push(id);
+    }
+    by_para.into_iter().try_for_each(|(_, ids)| {
+        let next_id = ids.len() as OrderId;
+        verify_order_ids(ids, next_id)
+    })
+}
@@ -0,0 +1,36 @@
+//! Runtime API of the robonomics service and client pallets
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::unnecessary_mut_passed)]
+
+use codec::Codec;
+use sp_std::prelude::*;
+
+pub use pallet_common::{DeviceInfo, DeviceState, OrderId, OrderQuote};
+
+/// Order as exposed to the runtime API, paraid is the raw parachain id
+pub type OrderInfo<Payload, Balance, Moment, AccountId> =
+    pallet_common::Order<Payload, Balance, Moment, AccountId, u32>;
+
+/// Prospective order to be quoted
+pub type OrderRequest<Payload, Balance, Moment, AccountId> =
+    pallet_common::OrderBase<Payload, Balance, Moment, AccountId>;
+
+sp_api::decl_runtime_apis! {
+    pub trait RobobankApi<AccountId, Balance, Moment, Payload> where
+        AccountId: Codec,
+        Balance: Codec,
+        Moment: Codec,
+        Payload: Codec,
+    {
+        /// Profile of the device
+        fn device(device: AccountId) -> Option<DeviceInfo<Balance, Moment>>;
+        /// Order currently assigned to the device
+        fn order(device: AccountId) -> Option<OrderInfo<Payload, Balance, Moment, AccountId>>;
+        /// Devices ready to accept an order
+        fn ready_devices() -> Vec<AccountId>;
+        /// Reservations and fees of a prospective order, `None` if the device is unknown
+        fn quote_order(
+            order: OrderRequest<Payload, Balance, Moment, AccountId>,
+        ) -> Option<OrderQuote<Balance, Moment>>;
+    }
+}
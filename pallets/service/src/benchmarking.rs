@@ -0,0 +1,493 @@
+//! Benchmarks of the service pallet
+use super::*;
+
+use frame_benchmarking::{account, benchmarks, impl_benchmark_test_suite};
+use frame_support::sp_runtime::traits::Bounded;
+use frame_system::RawOrigin;
+
+const SEED: u32 = 0;
+const WCD: u32 = 1_000;
+const UNTIL: u32 = 1_000_000;
+const SENDER: u32 = 2_000;
+
+fn funded<T: Config>(name: &'static str) -> T::AccountId {
+    let who: T::AccountId = account(name, 0, SEED);
+    T::Currency::make_free_balance_be(&who, BalanceOf::<T>::max_value() / 4u32.into());
+    who
+}
+
+fn device<T: Config>(state: DeviceState) -> T::AccountId {
+    let device = funded::<T>("device");
+    Device::<T>::insert(
+        &device,
+        DeviceProfile {
+            state,
+            penalty: 1_000u32.into(),
+            wcd: WCD.into(),
+        },
+    );
+    device
+}
+
+fn order_base<T: Config>(device: &T::AccountId) -> OrderBaseOf<T> {
+    OrderBaseOf::<T> {
+        until: UNTIL.into(),
+        until_relay: None,
+        data: Default::default(),
+        fee: 1_000u32.into(),
+        device: device.clone(),
+    }
+}
+
+/// Places a local order straight into storage, funds are reserved
+fn place_order<T: Config>(device: &T::AccountId, client: &T::AccountId, state: DeviceState) {
+    let OrderBaseOf::<T> {
+        until,
+        until_relay,
+        data,
+        fee,
+        ..
+    } = order_base::<T>(device);
+
+    T::Currency::reserve(device, 1_000u32.into()).unwrap();
+    T::Currency::reserve(client, fee).unwrap();
+    Orders::<T>::insert(
+        device,
+        OrderOf::<T> {
+            id: 0,
+            until,
+            until_relay,
+            data,
+            fee,
+            client: client.clone(),
+            paraid: T::SelfParaId::get(),
+        },
+    );
+    Device::<T>::mutate(device, |dev| {
+        if let Some(dev) = dev {
+            dev.state = state
+        }
+    });
+}
+
+benchmarks! {
+    order {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        let order = order_base::<T>(&device);
+    }: _(RawOrigin::Signed(client), order)
+    verify {
+        assert!(Orders::<T>::contains_key(&device));
+    }
+
+    cancel {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        Timestamp::<T>::set_timestamp((UNTIL + 1).into());
+    }: _(RawOrigin::Signed(client), device.clone())
+    verify {
+        assert!(!Orders::<T>::contains_key(&device));
+    }
+
+    accept {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+    }: _(RawOrigin::Signed(device.clone()), false, true)
+    verify {
+        assert_eq!(Device::<T>::get(&device).unwrap().state, DeviceState::Busy2);
+    }
+
+    // Overdue rejection slashes the penalty, the heaviest path
+    reject {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy);
+        Timestamp::<T>::set_timestamp((UNTIL + 1).into());
+    }: accept(RawOrigin::Signed(device.clone()), true, true)
+    verify {
+        assert!(!Orders::<T>::contains_key(&device));
+    }
+
+    // Overdue completion slashes the penalty, the heaviest path
+    done {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        Timestamp::<T>::set_timestamp((UNTIL + 1).into());
+    }: _(RawOrigin::Signed(device.clone()), true, Some(Default::default()))
+    verify {
+        assert_eq!(Device::<T>::get(&device).unwrap().state, DeviceState::Ready);
+    }
+
+    amend_order {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        let amendment = AmendmentOf::<T> {
+            until: (UNTIL * 2).into(),
+            until_relay: None,
+            fee: 2_000u32.into(),
+        };
+    }: _(RawOrigin::Signed(client), device.clone(), amendment)
+    verify {
+        assert!(Amendments::<T>::contains_key(&device));
+    }
+
+    accept_amendment {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        let amendment = AmendmentOf::<T> {
+            until: (UNTIL * 2).into(),
+            until_relay: None,
+            fee: 2_000u32.into(),
+        };
+        Pallet::<T>::amend_order(RawOrigin::Signed(client).into(), device.clone(), amendment)?;
+    }: _(RawOrigin::Signed(device.clone()))
+    verify {
+        assert_eq!(Orders::<T>::get(&device).unwrap().fee, 2_000u32.into());
+    }
+
+    // Progress of a sibling order is relayed to the client parachain, the heaviest path
+    report {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        Orders::<T>::mutate(&device, |order| {
+            if let Some(order) = order {
+                order.paraid = SENDER.into()
+            }
+        });
+        ProtocolVersions::<T>::insert(ParaId::from(SENDER), PROTOCOL_VERSION);
+        let telemetry = Telemetry {
+            progress: MAX_PROGRESS,
+            position: Some((1, 2, 3)),
+            digest: [0xff; 32],
+        };
+    }: _(RawOrigin::Signed(device.clone()), telemetry, true)
+    verify {
+        assert!(Progress::<T>::contains_key(&device));
+        assert!(Unacked::<T>::get(ParaId::from(SENDER), 0).is_some());
+    }
+
+    register {
+        let device = funded::<T>("device");
+    }: _(RawOrigin::Signed(device.clone()), 1_000u32.into(), WCD.into(), true)
+    verify {
+        assert!(Device::<T>::contains_key(&device));
+    }
+
+    set_policy {
+        let n in 0 .. T::MaxPolicyEntries::get();
+        let device = device::<T>(DeviceState::Ready);
+        let entries = (0..n).map(|i| (SENDER.into(), account("client", i, SEED))).collect();
+    }: _(RawOrigin::Signed(device.clone()), AccessPolicyOf::<T>::Allow(entries))
+    verify {
+        assert!(Policies::<T>::contains_key(&device));
+    }
+
+    set_state {
+        let device = device::<T>(DeviceState::Off);
+    }: _(RawOrigin::Signed(device.clone()), true)
+    verify {
+        assert_eq!(Device::<T>::get(&device).unwrap().state, DeviceState::Ready);
+    }
+
+    on_new_order {
+        let device = device::<T>(DeviceState::Ready);
+        let client: T::AccountId = account("client", 0, SEED);
+        let blob = XCMPMessageOf::<T>::NewOrder(0, client, order_base::<T>(&device)).encode();
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert!(Orders::<T>::contains_key(&device));
+    }
+
+    on_cancel {
+        let device = device::<T>(DeviceState::Ready);
+        let client: T::AccountId = account("client", 0, SEED);
+        let order = XCMPMessageOf::<T>::NewOrder(0, client.clone(), order_base::<T>(&device));
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, order.encode());
+        Device::<T>::mutate(&device, |dev| {
+            if let Some(dev) = dev {
+                dev.state = DeviceState::Busy
+            }
+        });
+        let blob = XCMPMessageOf::<T>::OrderCancel(0, client, device.clone()).encode();
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert!(!Orders::<T>::contains_key(&device));
+    }
+
+    on_amend {
+        let device = device::<T>(DeviceState::Ready);
+        let client: T::AccountId = account("client", 0, SEED);
+        let order = XCMPMessageOf::<T>::NewOrder(0, client.clone(), order_base::<T>(&device));
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, order.encode());
+        let amendment = AmendmentOf::<T> {
+            until: (UNTIL * 2).into(),
+            until_relay: None,
+            fee: 2_000u32.into(),
+        };
+        let blob = XCMPMessageOf::<T>::OrderAmend(0, client, device.clone(), amendment).encode();
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert!(Amendments::<T>::contains_key(&device));
+    }
+
+    set_pause {
+        let origin = T::PauseOrigin::successful_origin();
+        let target = PauseTarget::Para(SENDER.into());
+    }: {
+        Pallet::<T>::set_pause(origin, target, true)?;
+    }
+    verify {
+        assert!(Pauses::<T>::get(target));
+    }
+
+    on_paused_order {
+        let device = device::<T>(DeviceState::Ready);
+        let client: T::AccountId = account("client", 0, SEED);
+        Pauses::<T>::insert(PauseTarget::Para(SENDER.into()), true);
+        let blob = XCMPMessageOf::<T>::NewOrder(0, client, order_base::<T>(&device)).encode();
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert!(!Orders::<T>::contains_key(&device));
+    }
+
+    on_queue_message {
+        let device = device::<T>(DeviceState::Ready);
+        let client: T::AccountId = account("client", 0, SEED);
+        Pauses::<T>::insert(PauseTarget::Kind(MessageKind::OrderCancel), true);
+        let blob = XCMPMessageOf::<T>::OrderCancel(0, client, device).encode();
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert_eq!(PausedMessagesBounds::<T>::get(), (0, 1));
+    }
+
+    handshake {
+    }: _(RawOrigin::Root, SENDER.into())
+
+    on_handshake {
+        let blob = XCMPMessageOf::<T>::Handshake(PROTOCOL_VERSION).to_wire(PROTOCOL_VERSION, None);
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert_eq!(ProtocolVersions::<T>::get(ParaId::from(SENDER)), Some(PROTOCOL_VERSION));
+    }
+
+    retry_message {
+        let dest = ParaId::from(SENDER);
+        let blob = XCMPMessageOf::<T>::Handshake(PROTOCOL_VERSION).to_wire(PROTOCOL_VERSION, None);
+        let now = frame_system::Pallet::<T>::block_number();
+        let pending = OutboundMessage {
+            blob,
+            kind: MessageKind::Handshake,
+            attempts: 1,
+            next_attempt: now,
+        };
+        Outbox::<T>::insert(dest, 0, pending);
+        OutboxBounds::<T>::insert(dest, (0, 1));
+        OutboxDestinations::<T>::put(vec![dest]);
+    }: {
+        Pallet::<T>::retry_outbox(now, Weight::max_value());
+    }
+    verify {
+        assert!(Outbox::<T>::get(dest, 0).map_or(true, |m| m.attempts == 2));
+    }
+
+    on_ack {
+        let dest = ParaId::from(SENDER);
+        let pending = PendingAck {
+            blob: vec![0; 64],
+            kind: MessageKind::OrderDone,
+            sent_at: frame_system::Pallet::<T>::block_number(),
+            resends: 0,
+        };
+        Pallet::<T>::track_unacked(dest, 0, pending);
+        let blob = XCMPMessageOf::<T>::Ack(0).to_wire(PROTOCOL_VERSION, None);
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert!(Unacked::<T>::get(dest, 0).is_none());
+    }
+
+    resend_message {
+        let dest = ParaId::from(SENDER);
+        let blob = XCMPMessageOf::<T>::OrderCancel(0, funded::<T>("client"), funded::<T>("device"))
+            .to_wire(PROTOCOL_VERSION, Some(0));
+        let pending = PendingAck {
+            blob,
+            kind: MessageKind::OrderCancel,
+            sent_at: frame_system::Pallet::<T>::block_number(),
+            resends: 0,
+        };
+        Pallet::<T>::track_unacked(dest, 0, pending);
+        let now = frame_system::Pallet::<T>::block_number() + T::AckTimeout::get();
+    }: {
+        Pallet::<T>::resend_unacked(now, Weight::max_value());
+    }
+    verify {
+        assert_eq!(Unacked::<T>::get(dest, 0).map(|m| m.resends), Some(1));
+    }
+
+    on_duplicate {
+        let dest = ParaId::from(SENDER);
+        let window = SeqWindow {
+            watermark: 1,
+            seen: 0,
+        };
+        InboundSequence::<T>::insert(dest, window);
+        let blob = XCMPMessageOf::<T>::OrderCancel(0, funded::<T>("client"), funded::<T>("device"))
+            .to_wire(PROTOCOL_VERSION, Some(0));
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert_eq!(InboundSequence::<T>::get(dest).watermark, 1);
+    }
+
+    on_reconcile {
+        let n in 1 .. 100;
+        let dest = ParaId::from(SENDER);
+        let fee: BalanceOf<T> = 1_000u32.into();
+        let mut entries = Vec::new();
+        for i in 0 .. n {
+            let device: T::AccountId = account("device", i, SEED);
+            let entry = LedgerEntryOf::<T> {
+                fees: fee,
+                penalties: fee,
+            };
+            Ledgers::<T>::insert(dest, &device, entry);
+            entries.push((device, fee, fee));
+        }
+        let blob = XCMPMessageOf::<T>::Reconcile(0, entries).to_wire(PROTOCOL_VERSION, Some(0));
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert_eq!(Ledgers::<T>::iter_prefix(dest).count(), 0);
+    }
+
+    set_subscriber {
+    }: _(RawOrigin::Root, SENDER.into(), true)
+    verify {
+        assert!(Subscribers::<T>::get(ParaId::from(SENDER)));
+    }
+
+    on_subscribe {
+        ApprovedSubscribers::<T>::insert(ParaId::from(SENDER), true);
+        let blob = XCMPMessageOf::<T>::Subscribe.to_wire(PROTOCOL_VERSION, Some(0));
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert!(Subscribers::<T>::get(ParaId::from(SENDER)));
+    }
+
+    approve_subscriber {
+        Subscribers::<T>::insert(ParaId::from(SENDER), true);
+        SubscriberCount::<T>::put(1);
+    }: _(RawOrigin::Root, SENDER.into(), false)
+    verify {
+        assert!(!Subscribers::<T>::get(ParaId::from(SENDER)));
+    }
+
+    send_snapshot {
+        let n in 1 .. 100;
+        for i in 0 .. n {
+            let device: T::AccountId = account("device", i, SEED);
+            let profile = DeviceProfile::<T> {
+                state: DeviceState::Ready,
+                penalty: 1_000u32.into(),
+                wcd: WCD.into(),
+            };
+            Device::<T>::insert(&device, profile);
+        }
+        let paraid = ParaId::from(SENDER);
+        ProtocolVersions::<T>::insert(paraid, PROTOCOL_VERSION);
+    }: {
+        let (devices, _) = Pallet::<T>::device_page(&[], n as usize);
+        Pallet::<T>::send_message(paraid, XCMPMessageOf::<T>::DeviceSnapshot(0, devices))?;
+    }
+    verify {
+        assert_eq!(UnackedCount::<T>::get(paraid), 1);
+    }
+
+    set_fee_reserve {
+    }: _(RawOrigin::Root, SENDER.into(), true)
+    verify {
+        assert!(FeeReserves::<T>::get(ParaId::from(SENDER)));
+    }
+
+    on_unsubscribe {
+        Subscribers::<T>::insert(ParaId::from(SENDER), true);
+        let blob = XCMPMessageOf::<T>::Unsubscribe.to_wire(PROTOCOL_VERSION, Some(0));
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert!(!Subscribers::<T>::get(ParaId::from(SENDER)));
+    }
+
+    on_settlement_changed {
+        let blob = XCMPMessageOf::<T>::SettlementChanged(SettlementMode::Netting)
+            .to_wire(PROTOCOL_VERSION, Some(0));
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+    verify {
+        assert_eq!(SettlementModes::<T>::get(ParaId::from(SENDER)), SettlementMode::Netting);
+    }
+
+    push_change {
+        let s in 1 .. 100;
+        for i in 0 .. s {
+            let paraid = ParaId::from(SENDER + i);
+            ProtocolVersions::<T>::insert(paraid, PROTOCOL_VERSION);
+            Subscribers::<T>::insert(paraid, true);
+        }
+        SubscriberCount::<T>::put(s);
+        let device = device::<T>(DeviceState::Ready);
+        Pallet::<T>::broadcast(XCMPMessageOf::<T>::DeviceStateChanged(0, device, DeviceState::Off));
+    }: {
+        Pallet::<T>::push_changes(Weight::max_value());
+    }
+    verify {
+        assert_eq!(RegistrySequence::<T>::get(ParaId::from(SENDER)), 1);
+    }
+
+    on_bad_message {
+        let blob = vec![0xff; 64];
+    }: {
+        <Pallet<T> as XcmpMessageHandler>::handle_blob_message(SENDER.into(), 0, blob);
+    }
+
+    prune_archived {
+        let device = device::<T>(DeviceState::Ready);
+        let client = funded::<T>("client");
+        place_order::<T>(&device, &client, DeviceState::Busy2);
+        Pallet::<T>::done(RawOrigin::Signed(device.clone()).into(), true, None)?;
+        let now = frame_system::Pallet::<T>::block_number() + T::ArchiveRetention::get();
+    }: {
+        Pallet::<T>::prune_archive(now, Weight::max_value());
+    }
+    verify {
+        assert!(Archive::<T>::get((T::SelfParaId::get(), 0)).is_none());
+    }
+}
+
+impl_benchmark_test_suite!(Pallet, crate::mock::new_test_ext(), crate::mock::Test);
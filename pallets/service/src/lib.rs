@@ -5,10 +5,15 @@
 use codec::{Decode, Encode};
 use frame_support::traits::OnKilledAccount;
 use frame_support::{
-    dispatch::DispatchResult,
-    sp_runtime::traits::Hash,
+    dispatch::{DispatchError, DispatchResult},
+    sp_io,
+    sp_runtime::traits::{Hash, Zero},
     sp_runtime::RuntimeDebug,
+    storage::StoragePrefixedMap,
     traits::{BalanceStatus::Free, Currency, Get, ReservableCurrency},
+    transactional,
+    weights::{DispatchClass, Weight},
+    ReversibleStorageHasher, Twox64Concat,
 };
 
 use cumulus_primitives_core::{
@@ -20,17 +25,26 @@ use cumulus_primitives_core::{
 };
 
 pub use pallet::*;
-pub use pallet_common::DeviceState;
 use pallet_common::*;
+pub use pallet_common::{DeviceState, FixedXcmWeight};
+use sp_std::collections::btree_map::BTreeMap;
 use sp_std::convert::{TryFrom, TryInto};
+use sp_std::marker::PhantomData;
 use sp_std::prelude::*;
-use xcm::v0::{Error as XcmError, Junction, MultiLocation, OriginKind, SendXcm, Xcm};
+use xcm::v0::{Error as XcmError, Junction, MultiAsset, MultiLocation, OriginKind, SendXcm, Xcm};
 use xcm::VersionedXcm;
+use xcm_executor::traits::{FilterAssetLocation, LocationConversion, TransactAsset};
 
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking;
+pub mod migrations;
 #[cfg(test)]
 mod mock;
 #[cfg(test)]
 mod tests;
+pub mod weights;
+
+pub use weights::WeightInfo;
 
 #[cfg_attr(feature = "std", derive(PartialEq, Debug))]
 #[derive(Encode, Decode, Default)]
@@ -43,6 +57,40 @@ pub struct DeviceProfile<T: Config> {
     wcd: MomentOf<T>,
 }
 
+/// How the order was closed
+#[derive(Encode, Decode, Clone, Copy, PartialEq, RuntimeDebug)]
+pub enum OrderOutcome {
+    /// Completed by the device
+    Done,
+    /// Rejected by the device
+    Rejected,
+    /// Withdrawn by the client before acceptance or cancelled after the deadline
+    Cancelled,
+}
+
+/// Final record of a closed order
+#[cfg_attr(feature = "std", derive(PartialEq, Debug))]
+#[derive(Encode, Decode)]
+pub struct ArchivedOrder<T: Config> {
+    pub client: T::AccountId,
+    pub device: T::AccountId,
+    pub paraid: ParaId,
+    pub fee: BalanceOf<T>,
+    /// Order receipt time
+    pub received: MomentOf<T>,
+    /// Order acceptance time
+    pub accepted: Option<MomentOf<T>>,
+    /// Order close time
+    pub closed: MomentOf<T>,
+    pub outcome: OrderOutcome,
+    /// Hash of the work result reported by the device
+    pub result: Option<T::Hash>,
+    /// Funds moved on close, `None` for orders settled on the client parachain
+    pub settlement: Option<SettlementOf<T>>,
+    /// Block the record was archived at
+    pub archived_at: T::BlockNumber,
+}
+
 pub trait OnReceived<T: Config> {
     fn on_received(
         device: &<T as frame_system::Config>::AccountId,
@@ -77,6 +125,18 @@ pub type OrderBaseOf<T> = OrderBase<
 pub type BalanceOf<T> =
     <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
 pub type MomentOf<T> = <T as pallet_timestamp::Config>::Moment;
+pub type SettlementOf<T> = Settlement<BalanceOf<T>>;
+pub type LedgerEntryOf<T> = LedgerEntry<BalanceOf<T>>;
+pub type AmendmentOf<T> = Amendment<BalanceOf<T>, MomentOf<T>>;
+pub type AccessPolicyOf<T> = AccessPolicy<ParaId, <T as frame_system::Config>::AccountId>;
+/// Public device profile of the registry snapshot (device, penalty, wcd, state, policy)
+type SnapshotEntryOf<T> = (
+    <T as frame_system::Config>::AccountId,
+    BalanceOf<T>,
+    MomentOf<T>,
+    DeviceState,
+    AccessPolicy<u32, <T as frame_system::Config>::AccountId>,
+);
 type Timestamp<T> = pallet_timestamp::Pallet<T>;
 
 type XCMPMessageOf<T> = XCMPMessage<
@@ -91,20 +151,27 @@ pub mod pallet {
     #![allow(clippy::unused_unit)]
 
     use frame_support::dispatch::{Dispatchable, PostDispatchInfo};
+    use frame_support::sp_runtime::traits::{Convert, UniqueSaturatedFrom};
     use frame_support::traits::{
         BalanceStatus, Currency, EnsureOrigin, Get, OnUnbalanced, ReservableCurrency,
     };
     use frame_support::{
         dispatch::{DispatchResult, DispatchResultWithPostInfo},
         pallet_prelude::*,
+        transactional,
     };
     use frame_system::pallet_prelude::*;
     use sp_std::prelude::*;
+    use xcm::v0::ExecuteXcm;
     use xcm_executor::traits::ConvertOrigin;
 
     use super::{
-        BalanceOf, DeviceProfile, DeviceState, Junction, MomentOf, OnReceived, OrderBaseOf,
-        OrderOf, OriginKind, ParaId, SendXcm, Timestamp, Xcm, XcmpMessageSender,
+        AccessPolicyOf, AmendmentOf, ArchivedOrder, BalanceOf, DeviceProfile, DeviceState,
+        EpochIndex, Junction, LedgerEntryOf, MessageKind, Messaging, MomentOf, Nonce, OnReceived,
+        OrderBaseOf, OrderId, OrderOf, OrderOutcome, OriginKind, OutboundMessage, ParaId,
+        PauseTarget, PendingAck, ProtocolVersion, RelayBlockNumber, Releases, SendXcm, SeqWindow,
+        SettlementMode, SettlementOf, Telemetry, Timestamp, WeighXcm, WeightInfo, XCMPMessageOf,
+        Xcm, XcmError, XcmpMessageSender, PROTOCOL_VERSION,
     };
 
     #[pallet::config]
@@ -115,11 +182,55 @@ pub mod pallet {
         type OrderPayload: Encode + Decode + Clone + Default + Parameter;
         /// XCM interface
         type XcmpMessageSender: XcmpMessageSender;
+        /// Executor of inbound XCM
+        type XcmExecutor: ExecuteXcm;
+        /// Weighs inbound XCM before it is executed
+        type XcmWeigher: WeighXcm;
+        /// Weight available to a single inbound XCM
+        type MaxXcmWeight: Get<Weight>;
+
         //type XcmSender: SendXcm;
         /// Own parachain Id
         type SelfParaId: Get<ParaId>;
         /// Call when new order received
         type OnReceived: OnReceived<Self>;
+        /// Expected relay chain block duration
+        type RelayBlockDuration: Get<MomentOf<Self>>;
+        /// Blocks a closed order is kept in the archive
+        type ArchiveRetention: Get<Self::BlockNumber>;
+        /// Maximum number of clients listed in a device access policy
+        type MaxPolicyEntries: Get<u32>;
+        /// Delivery attempts of a deferred message, or resends of an unacknowledged one,
+        /// before it is dropped
+        type MaxSendAttempts: Get<u32>;
+        /// Deferred messages kept per destination, sends are refused while it is full
+        type MaxOutboxMessages: Get<u32>;
+        /// Delay before the first retry of a deferred message, doubled with every attempt
+        type RetryBackoff: Get<Self::BlockNumber>;
+        /// Blocks to wait for the acknowledgement before the message is sent again
+        type AckTimeout: Get<Self::BlockNumber>;
+        /// Messages awaiting acknowledgement per destination, sends are refused beyond it
+        type MaxUnacked: Get<u32>;
+        /// Origin of calls dispatched by sibling parachains, resolves to the parachain id
+        type SiblingOrigin: EnsureOrigin<Self::Origin, Success = ParaId>;
+        /// Sovereign account of a sibling parachain, the client of its orders
+        type SiblingAccount: Convert<ParaId, Self::AccountId>;
+        /// Origin allowed to pause cross-chain message processing
+        type PauseOrigin: EnsureOrigin<Self::Origin>;
+        /// Maximum number of messages held while their processing is paused
+        type MaxPausedMessages: Get<u32>;
+        /// Device profiles per device registry snapshot message
+        type SnapshotChunk: Get<u32>;
+        /// Maximum number of parachains device profiles are pushed to
+        type MaxSubscribers: Get<u32>;
+        /// Device registry changes queued for the subscribers, beyond it the queue is dropped
+        /// and every subscriber is sent a fresh snapshot
+        type MaxRegistryChanges: Get<u32>;
+        /// Blocks between the registry snapshots of a subscriber, a parachain subscribing again
+        /// sooner gets its snapshot once they passed
+        type ResubscribeInterval: Get<Self::BlockNumber>;
+        /// Weight information for extrinsics and message handlers in this pallet
+        type WeightInfo: WeightInfo;
     }
 
     #[pallet::pallet]
@@ -148,15 +259,393 @@ pub mod pallet {
         OptionQuery,
     >;
 
+    /// Receipt and acceptance time of open orders
+    #[pallet::storage]
+    pub type OrderTimings<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        <T as frame_system::Config>::AccountId,
+        (MomentOf<T>, Option<MomentOf<T>>),
+        OptionQuery,
+    >;
+
+    /// Closed orders by client parachain and order id,
+    /// ids of foreign orders are assigned by the client parachain
+    #[pallet::storage]
+    #[pallet::getter(fn archived_order)]
+    pub type Archive<T: Config> =
+        StorageMap<_, Twox64Concat, (ParaId, OrderId), ArchivedOrder<T>, OptionQuery>;
+
+    /// Closed orders by device
+    #[pallet::storage]
+    pub type DeviceArchive<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        <T as frame_system::Config>::AccountId,
+        Twox64Concat,
+        (ParaId, OrderId),
+        (),
+        OptionQuery,
+    >;
+
+    /// Closed orders by client
+    #[pallet::storage]
+    pub type ClientArchive<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        <T as frame_system::Config>::AccountId,
+        Twox64Concat,
+        (ParaId, OrderId),
+        (),
+        OptionQuery,
+    >;
+
+    /// Archived order keys in archival order
+    #[pallet::storage]
+    pub(super) type ArchiveQueue<T: Config> =
+        StorageMap<_, Twox64Concat, u64, (ParaId, OrderId), OptionQuery>;
+
+    /// Head and tail of `ArchiveQueue`
+    #[pallet::storage]
+    pub(super) type ArchiveQueueBounds<T: Config> = StorageValue<_, (u64, u64), ValueQuery>;
+
+    /// Id of the next order placed on this parachain
+    #[pallet::storage]
+    pub type NextOrderId<T: Config> = StorageValue<_, OrderId, ValueQuery>;
+
+    /// Storage layout version
+    #[pallet::storage]
+    pub(super) type StorageVersion<T: Config> = StorageValue<_, Releases, ValueQuery>;
+
+    /// Relay chain block number the current block is built on
+    #[pallet::storage]
+    #[pallet::getter(fn relay_block_number)]
+    pub type RelayParentNumber<T: Config> = StorageValue<_, RelayBlockNumber, ValueQuery>;
+
+    /// Latest telemetry snapshot of the device's current order
+    #[pallet::storage]
+    #[pallet::getter(fn progress)]
+    pub type Progress<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        <T as frame_system::Config>::AccountId,
+        (MomentOf<T>, Telemetry),
+        OptionQuery,
+    >;
+
+    /// Clients permitted to order the device
+    #[pallet::storage]
+    #[pallet::getter(fn policies)]
+    pub type Policies<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        <T as frame_system::Config>::AccountId,
+        AccessPolicyOf<T>,
+        ValueQuery,
+    >;
+
+    /// Protocol versions negotiated with sibling parachains
+    #[pallet::storage]
+    #[pallet::getter(fn protocol_version)]
+    pub type ProtocolVersions<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, ProtocolVersion, OptionQuery>;
+
+    /// Nonce of the next acknowledged message to the parachain
+    #[pallet::storage]
+    pub type NextNonce<T: Config> = StorageMap<_, Twox64Concat, ParaId, Nonce, ValueQuery>;
+
+    /// Sent messages waiting for the receiver acknowledgement
+    #[pallet::storage]
+    #[pallet::getter(fn unacked)]
+    pub type Unacked<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        ParaId,
+        Twox64Concat,
+        Nonce,
+        PendingAck<T::BlockNumber>,
+        OptionQuery,
+    >;
+
+    /// Number of the destination's `Unacked` messages
+    #[pallet::storage]
+    pub type UnackedCount<T: Config> = StorageMap<_, Twox64Concat, ParaId, u32, ValueQuery>;
+
+    /// Destinations with unacknowledged messages, in the order they are checked
+    #[pallet::storage]
+    pub type UnackedDestinations<T: Config> = StorageValue<_, Vec<ParaId>, ValueQuery>;
+
+    /// Index in `UnackedDestinations` the next resend round starts from
+    #[pallet::storage]
+    pub type UnackedCursor<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+    /// Inbound nonces processed from the parachain
+    #[pallet::storage]
+    #[pallet::getter(fn inbound_sequence)]
+    pub type InboundSequence<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, SeqWindow, ValueQuery>;
+
+    /// Inbound nonces refused, kept to answer their duplicates alike
+    #[pallet::storage]
+    pub type InboundRefused<T: Config> =
+        StorageDoubleMap<_, Twox64Concat, ParaId, Twox64Concat, Nonce, (), OptionQuery>;
+
+    /// Messages deferred after a failed send, by destination
+    #[pallet::storage]
+    pub type Outbox<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        ParaId,
+        Twox64Concat,
+        u64,
+        OutboundMessage<T::BlockNumber>,
+        OptionQuery,
+    >;
+
+    /// Head and tail of the destination's `Outbox`, present while it has messages
+    #[pallet::storage]
+    pub type OutboxBounds<T: Config> = StorageMap<_, Twox64Concat, ParaId, (u64, u64), ValueQuery>;
+
+    /// Destinations with deferred messages, in the order they are retried
+    #[pallet::storage]
+    pub type OutboxDestinations<T: Config> = StorageValue<_, Vec<ParaId>, ValueQuery>;
+
+    /// Index in `OutboxDestinations` the next retry round starts from
+    #[pallet::storage]
+    pub type OutboxCursor<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+    /// Fees of completed netted orders the client parachain has not reconciled yet and the
+    /// collateral of closed netted orders it may net against them, by client parachain and
+    /// device
+    #[pallet::storage]
+    #[pallet::getter(fn ledger)]
+    pub type Ledgers<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        ParaId,
+        Twox64Concat,
+        T::AccountId,
+        LedgerEntryOf<T>,
+        ValueQuery,
+    >;
+
+    /// Fee settlement the client parachain chose for the orders it places
+    #[pallet::storage]
+    #[pallet::getter(fn settlement_mode)]
+    pub type SettlementModes<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, SettlementMode, ValueQuery>;
+
+    /// Settlement mode of the open foreign order to the device, fixed when it was received
+    #[pallet::storage]
+    pub(super) type OrderSettlements<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, SettlementMode, ValueQuery>;
+
+    /// Parachains device profiles are pushed to
+    #[pallet::storage]
+    #[pallet::getter(fn subscribed)]
+    pub type Subscribers<T: Config> = StorageMap<_, Twox64Concat, ParaId, bool, ValueQuery>;
+
+    /// Parachains governance allows to subscribe to device profiles
+    #[pallet::storage]
+    #[pallet::getter(fn approved)]
+    pub type ApprovedSubscribers<T: Config> = StorageMap<_, Twox64Concat, ParaId, bool, ValueQuery>;
+
+    /// Device registry snapshots being sent, by subscriber: storage key of the last device
+    /// sent and the number of devices sent
+    #[pallet::storage]
+    pub(super) type Snapshots<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, (Vec<u8>, u32), OptionQuery>;
+
+    /// Number of parachains device profiles are pushed to
+    #[pallet::storage]
+    #[pallet::getter(fn subscriber_count)]
+    pub type SubscriberCount<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+    /// Sequence number of the next device registry change pushed to the subscriber
+    #[pallet::storage]
+    pub(super) type RegistrySequence<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, u64, ValueQuery>;
+
+    /// Device registry changes waiting to be pushed to the subscribers, numbered when pushed
+    #[pallet::storage]
+    pub(super) type RegistryChanges<T: Config> =
+        StorageMap<_, Twox64Concat, u64, XCMPMessageOf<T>, OptionQuery>;
+
+    /// Head and tail of `RegistryChanges`
+    #[pallet::storage]
+    pub(super) type RegistryChangesBounds<T: Config> = StorageValue<_, (u64, u64), ValueQuery>;
+
+    /// Block the latest registry snapshot of the subscriber starts at
+    #[pallet::storage]
+    pub(super) type SnapshotStarts<T: Config> =
+        StorageMap<_, Twox64Concat, ParaId, T::BlockNumber, OptionQuery>;
+
+    /// Active pauses of cross-chain message processing
+    #[pallet::storage]
+    #[pallet::getter(fn paused)]
+    pub type Pauses<T: Config> = StorageMap<_, Twox64Concat, PauseTarget<ParaId>, bool, ValueQuery>;
+
+    /// Messages received while paused, processed in order once resumed
+    #[pallet::storage]
+    pub type PausedMessages<T: Config> =
+        StorageMap<_, Twox64Concat, u64, (ParaId, XCMPMessageOf<T>), OptionQuery>;
+
+    /// Head and tail of `PausedMessages`
+    #[pallet::storage]
+    pub type PausedMessagesBounds<T: Config> = StorageValue<_, (u64, u64), ValueQuery>;
+
+    /// Order amendments awaiting device acknowledgement
+    #[pallet::storage]
+    #[pallet::getter(fn amendments)]
+    pub type Amendments<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        <T as frame_system::Config>::AccountId,
+        AmendmentOf<T>,
+        OptionQuery,
+    >;
+
+    /// Client parachains whose currency is accepted for fees, each is the reserve of its own
+    #[pallet::storage]
+    #[pallet::getter(fn fee_reserve)]
+    pub type FeeReserves<T: Config> = StorageMap<_, Twox64Concat, ParaId, bool, ValueQuery>;
+
+    /// Balances in the currencies of client parachains, backed by the sovereign account of
+    /// this parachain on the client parachain
+    #[pallet::storage]
+    #[pallet::getter(fn reserve_balance)]
+    pub type ReserveBalances<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        ParaId,
+        Twox64Concat,
+        <T as frame_system::Config>::AccountId,
+        BalanceOf<T>,
+        ValueQuery,
+    >;
+
     #[pallet::event]
-    #[pallet::metadata(T::AccountId = "AccountId")]
+    #[pallet::metadata(
+        T::AccountId = "AccountId",
+        BalanceOf<T> = "Balance",
+        Option<SettlementOf<T>> = "Option<Settlement>"
+    )]
     #[pallet::generate_deposit(pub(super) fn deposit_event)]
     pub enum Event<T: Config> {
         NewDevice(T::AccountId),
-        NewOrder(T::AccountId),
-        Accept(T::AccountId),
-        Reject(T::AccountId),
-        Done(T::AccountId),
+        /// Device access policy changed \[device\]
+        PolicyChanged(T::AccountId),
+        /// Order received \[id, client, device, paraid, fee\]
+        NewOrder(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Order accepted by the device \[id, client, device, paraid, fee\]
+        Accept(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Order rejected by the device \[id, client, device, paraid, fee, settlement\],
+        /// settlement is `None` for orders settled on the client parachain
+        Reject(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            Option<SettlementOf<T>>,
+        ),
+        /// Order completed \[id, client, device, paraid, fee, settlement\],
+        /// settlement is `None` for orders settled on the client parachain
+        Done(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            Option<SettlementOf<T>>,
+        ),
+        /// Device reported order progress \[id, client, device, paraid, fee, progress\]
+        Progress(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            u8,
+        ),
+        /// Order received while paused, client parachain notified \[id, client, device, paraid\]
+        OrderFailed(OrderId, T::AccountId, T::AccountId, ParaId),
+        /// Device profiles pushed to the parachain or not \[paraid, subscribed\]
+        SubscriberChanged(ParaId, bool),
+        /// Device registry snapshot sent to the subscriber \[paraid, devices\]
+        SnapshotSent(ParaId, u32),
+        /// Parachain allowed to subscribe to device profiles or not \[paraid, approved\]
+        SubscriberApproved(ParaId, bool),
+        /// Order withdrawal refused, client parachain notified \[id, client, device, paraid\]
+        CancelRejected(OrderId, T::AccountId, T::AccountId, ParaId),
+        /// Order withdrawn or cancelled by the client
+        /// \[id, client, device, paraid, fee, settlement\],
+        /// settlement is `None` for orders settled on the client parachain
+        Cancelled(
+            OrderId,
+            T::AccountId,
+            T::AccountId,
+            ParaId,
+            BalanceOf<T>,
+            Option<SettlementOf<T>>,
+        ),
+        /// Order amendment awaits device acknowledgement \[id, client, device, paraid, fee\],
+        /// fee is the amended one
+        AmendRequested(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Order amended \[id, client, device, paraid, fee\]
+        Amended(OrderId, T::AccountId, T::AccountId, ParaId, BalanceOf<T>),
+        /// Order amendment refused, client parachain notified \[id, client, device, paraid\]
+        AmendRejected(OrderId, T::AccountId, T::AccountId, ParaId),
+        /// Protocol version agreed with the parachain \[paraid, version\]
+        HandshakeCompleted(ParaId, ProtocolVersion),
+        /// Message could not be sent, deferred to the outbox \[dest, kind\]
+        MessageDeferred(ParaId, MessageKind),
+        /// Deferred message sent \[dest, kind\]
+        MessageDelivered(ParaId, MessageKind),
+        /// Deferred message dropped after too many attempts \[dest, kind, attempts\]
+        MessageAbandoned(ParaId, MessageKind, u32),
+        /// Message not sent, the outbox of the destination is full \[dest, kind\]
+        MessageRefused(ParaId, MessageKind),
+        /// Message acknowledged by the receiver \[dest, nonce\]
+        MessageAcked(ParaId, Nonce),
+        /// Message refused by the receiver \[dest, nonce, kind\]
+        MessageNacked(ParaId, Nonce, MessageKind),
+        /// Message not acknowledged in time, sent again \[dest, nonce, kind, resends\]
+        MessageUnacknowledged(ParaId, Nonce, MessageKind, u32),
+        /// Message still not acknowledged after the last resend, given up \[dest, nonce, kind\]
+        UnackedAbandoned(ParaId, Nonce, MessageKind),
+        /// Message received ahead of the expected one \[sender, expected, received\]
+        SequenceGap(ParaId, Nonce, Nonce),
+        /// Already processed message dropped \[sender, nonce\]
+        DuplicateDropped(ParaId, Nonce),
+        /// Cross-chain message processing paused or resumed \[target, paused\]
+        PauseChanged(PauseTarget<ParaId>, bool),
+        /// Message received while paused, queued for processing \[sender, kind\]
+        MessageQueued(ParaId, MessageKind),
+        /// Message received while paused and dropped, the queue is full \[sender, kind\]
+        MessageDropped(ParaId, MessageKind),
+        /// Epoch settlement of the client parachain matches the ledger \[paraid, epoch\]
+        Reconciled(ParaId, EpochIndex),
+        /// Client parachain settled more than recorded for the device
+        /// \[paraid, epoch, device, settled, recorded\]
+        ReconciliationMismatch(ParaId, EpochIndex, T::AccountId, BalanceOf<T>, BalanceOf<T>),
+        /// Client parachain netted more penalties than the collateral of the closed orders
+        /// \[paraid, epoch, device, netted, recorded\]
+        PenaltyMismatch(ParaId, EpochIndex, T::AccountId, BalanceOf<T>, BalanceOf<T>),
+        /// Client parachain changed the fee settlement of its orders \[paraid, mode\]
+        SettlementChanged(ParaId, SettlementMode),
+        /// Currency of the client parachain accepted for fees or not \[paraid, accepted\]
+        FeeReserveChanged(ParaId, bool),
+        /// Queued device registry changes dropped, every subscriber is sent a fresh snapshot
+        /// \[changes\]
+        RegistryChangesDropped(u32),
+        /// Inbound XCM executed \[hash, weight\]
+        XcmSuccess(<T as frame_system::Config>::Hash, Weight),
+        /// Inbound XCM failed \[hash, error\]
+        XcmFail(<T as frame_system::Config>::Hash, XcmError),
+        /// Inbound XCM dropped unexecuted, it exceeds the weight limit or can't be weighed
+        /// \[hash\]
+        XcmOverweight(<T as frame_system::Config>::Hash),
         BadVersion(<T as frame_system::Config>::Hash),
         MessageReceived(Vec<u8>),
     }
@@ -174,51 +663,104 @@ pub mod pallet {
         IllegalState,
         Overdue,
         CannotReachDestination,
+        BadTelemetry,
+        NoAmendment,
+        NotAllowed,
+        TooManyEntries,
+        Paused,
+        BadProtocolVersion,
+        UnexpectedMessage,
+        LedgerMismatch,
+        OutboxFull,
+        TooManyUnacked,
+        NotApproved,
+        TooManySubscribers,
+    }
+
+    #[pallet::genesis_config]
+    pub struct GenesisConfig<T: Config> {
+        /// Pre-registered devices (device, penalty, wcd, onoff)
+        ///
+        /// The work circle duration is given in timestamp units, `Moment` has no serde support.
+        pub devices: Vec<(T::AccountId, BalanceOf<T>, u64, bool)>,
+    }
+
+    #[cfg(feature = "std")]
+    impl<T: Config> Default for GenesisConfig<T> {
+        fn default() -> Self {
+            Self {
+                devices: Vec::new(),
+            }
+        }
+    }
+
+    #[pallet::genesis_build]
+    impl<T: Config> GenesisBuild<T> for GenesisConfig<T> {
+        fn build(&self) {
+            for (id, penalty, wcd, onoff) in &self.devices {
+                Device::<T>::insert(
+                    id,
+                    DeviceProfile {
+                        wcd: MomentOf::<T>::unique_saturated_from(*wcd),
+                        penalty: *penalty,
+                        state: if *onoff {
+                            DeviceState::Ready
+                        } else {
+                            DeviceState::Off
+                        },
+                    },
+                );
+            }
+            StorageVersion::<T>::put(Releases::V2);
+        }
     }
 
     #[pallet::hooks]
-    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {}
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        fn on_runtime_upgrade() -> Weight {
+            crate::migrations::migrate::<T>()
+        }
+
+        fn on_idle(n: BlockNumberFor<T>, remaining_weight: Weight) -> Weight {
+            let mut used = Self::retry_outbox(n, remaining_weight);
+            used += Self::resend_unacked(n, remaining_weight.saturating_sub(used));
+            used += Self::replay_paused(remaining_weight.saturating_sub(used));
+            used += Self::send_snapshots(remaining_weight.saturating_sub(used));
+            used += Self::push_changes(remaining_weight.saturating_sub(used));
+            used + Self::prune_archive(n, remaining_weight.saturating_sub(used))
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            crate::migrations::pre_migrate::<T>()
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            crate::migrations::post_migrate::<T>()
+        }
+    }
 
     #[pallet::call]
     impl<T: Config> Pallet<T> {
-        #[pallet::weight(10_000)]
+        #[pallet::weight(T::WeightInfo::order())]
         pub fn order(origin: OriginFor<T>, order: OrderBaseOf<T>) -> DispatchResult {
             let who = ensure_signed(origin)?;
-            let OrderBaseOf::<T> {
-                data,
-                until,
-                fee,
-                device,
-            } = order;
-            let order = OrderOf::<T> {
-                fee,
-                data,
-                until,
-                paraid: T::SelfParaId::get(),
-                client: who.into(),
-            };
-
-            Self::order_received(order, device)
+            Self::local_order(who, T::SelfParaId::get(), order)
         }
 
-        #[pallet::weight(10_000)]
+        #[pallet::weight(T::WeightInfo::cancel())]
         pub fn cancel(origin: OriginFor<T>, device: T::AccountId) -> DispatchResult {
             let who = ensure_signed(origin)?;
-
-            let order = Orders::<T>::get(&device).ok_or(Error::<T>::NoOrder)?;
-
-            let now = Timestamp::<T>::get();
-
-            if now < order.until || order.client != who {
-                return Err(Error::<T>::Prohibited.into());
-            }
-
-            let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
-            // Note. we don't change device state
-            Self::order_reject(Some(&order), now, device, &mut dev, false)
+            Self::client_cancel(who, device)
         }
 
-        #[pallet::weight(10_000)]
+        #[pallet::weight(if *reject {
+            T::WeightInfo::reject()
+        } else {
+            T::WeightInfo::accept()
+        })]
+        #[transactional]
         pub fn accept(origin: OriginFor<T>, reject: bool, onoff: bool) -> DispatchResult {
             let id = ensure_signed(origin)?;
 
@@ -236,23 +778,30 @@ pub mod pallet {
                 } else {
                     DeviceState::Off
                 };
-                return Self::order_reject(order.as_ref(), now, id, &mut dev, onoff);
+                return Self::order_reject(
+                    order.as_ref(),
+                    now,
+                    id,
+                    &mut dev,
+                    onoff,
+                    OrderOutcome::Rejected,
+                );
             }
             if dev.state != DeviceState::Busy {
                 return Err(Error::<T>::IllegalState.into());
             }
             let order = order.ok_or(Error::<T>::NoOrder)?;
 
-            if now >= order.until {
+            if Self::is_overdue(&order, &now) {
                 return Err(Error::<T>::Overdue.into());
             }
 
-            Self::order_accept(&order, now, id, &mut dev);
-            Ok(())
+            Self::order_accept(&order, now, id, &mut dev)
         }
 
-        #[pallet::weight(10_000)]
-        pub fn done(origin: OriginFor<T>, onoff: bool) -> DispatchResult {
+        #[pallet::weight(T::WeightInfo::done())]
+        #[transactional]
+        pub fn done(origin: OriginFor<T>, onoff: bool, result: Option<T::Hash>) -> DispatchResult {
             let id = ensure_signed(origin)?;
 
             let mut dev = Device::<T>::get(&id).ok_or(Error::<T>::NoDevice)?;
@@ -264,10 +813,94 @@ pub mod pallet {
             let order = Orders::<T>::take(&id).ok_or(Error::<T>::NoOrder)?;
             let now = Timestamp::<T>::get();
 
-            Self::order_done(&order, now, id, &mut dev, onoff)
+            Self::order_done(&order, now, id, &mut dev, onoff, result)
+        }
+
+        /// Extends the deadline and/or raises the fee of the order, the device has to accept
+        /// the amendment before it binds
+        #[pallet::weight(T::WeightInfo::amend_order())]
+        pub fn amend_order(
+            origin: OriginFor<T>,
+            device: T::AccountId,
+            amendment: AmendmentOf<T>,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+
+            let order = Orders::<T>::get(&device).ok_or(Error::<T>::NoOrder)?;
+            if order.client != who || !Self::is_local(&order) {
+                return Err(Error::<T>::Prohibited.into());
+            }
+
+            Self::amend_received(&order, device, amendment)
+        }
+
+        #[pallet::weight(T::WeightInfo::accept_amendment())]
+        #[transactional]
+        pub fn accept_amendment(origin: OriginFor<T>) -> DispatchResult {
+            let id = ensure_signed(origin)?;
+
+            let mut order = Orders::<T>::get(&id).ok_or(Error::<T>::NoOrder)?;
+            let amendment = Amendments::<T>::take(&id).ok_or(Error::<T>::NoAmendment)?;
+
+            if !Self::is_local(&order) {
+                let msg: XCMPMessageOf<T> = XCMPMessageOf::<T>::OrderAmendAccept(
+                    order.id,
+                    order.client.clone(),
+                    id.clone(),
+                );
+                Self::send_message(order.paraid, msg)?;
+            }
+
+            order.amend(amendment);
+            Orders::<T>::insert(&id, &order);
+            Self::deposit_event(Event::Amended(
+                order.id,
+                order.client,
+                id,
+                order.paraid,
+                order.fee,
+            ));
+            Ok(())
+        }
+
+        #[pallet::weight(T::WeightInfo::report())]
+        pub fn report(origin: OriginFor<T>, telemetry: Telemetry, relay: bool) -> DispatchResult {
+            let id = ensure_signed(origin)?;
+
+            if !telemetry.is_valid() {
+                return Err(Error::<T>::BadTelemetry.into());
+            }
+
+            let dev = Device::<T>::get(&id).ok_or(Error::<T>::NoDevice)?;
+            if dev.state != DeviceState::Busy2 {
+                return Err(Error::<T>::IllegalState.into());
+            }
+            let order = Orders::<T>::get(&id).ok_or(Error::<T>::NoOrder)?;
+
+            if relay && !Self::is_local(&order) {
+                let msg: XCMPMessageOf<T> = XCMPMessageOf::<T>::OrderProgress(
+                    order.id,
+                    order.client.clone(),
+                    id.clone(),
+                    telemetry.clone(),
+                );
+                Self::send_message(order.paraid, msg)?;
+            }
+
+            let progress = telemetry.progress;
+            Progress::<T>::insert(&id, (Timestamp::<T>::get(), telemetry));
+            Self::deposit_event(Event::Progress(
+                order.id,
+                order.client,
+                id,
+                order.paraid,
+                order.fee,
+                progress,
+            ));
+            Ok(())
         }
 
-        #[pallet::weight(10_000)]
+        #[pallet::weight(T::WeightInfo::register())]
         pub fn register(
             origin: OriginFor<T>,
             penalty: BalanceOf<T>,
@@ -281,85 +914,298 @@ pub mod pallet {
             }
             // Despite the order doesn't exist, device can be in Busy,Busy2 state.
             //
-            Device::<T>::insert(
-                &id,
-                DeviceProfile {
+            let dev = DeviceProfile {
+                wcd,
+                penalty,
+                state: if onoff {
+                    DeviceState::Ready
+                } else {
+                    DeviceState::Off
+                },
+            };
+            if Device::<T>::contains_key(&id) {
+                Self::broadcast(XCMPMessageOf::<T>::DeviceUpdate(
+                    0,
+                    id.clone(),
+                    penalty,
                     wcd,
+                ));
+                Self::put_device(&id, &dev);
+            } else {
+                Device::<T>::insert(&id, &dev);
+                Self::broadcast(XCMPMessageOf::<T>::DeviceAnnounce(
+                    0,
+                    id.clone(),
                     penalty,
-                    state: if onoff {
-                        DeviceState::Ready
-                    } else {
-                        DeviceState::Off
-                    },
-                },
-            );
+                    wcd,
+                    dev.state,
+                ));
+            }
             Self::deposit_event(Event::NewDevice(id));
             Ok(())
         }
 
-        #[pallet::weight(10_000)]
-        pub fn set_state(origin: OriginFor<T>, onoff: bool) -> DispatchResult {
+        /// Restricts the clients permitted to order the device, subscribed parachains mirror it
+        #[pallet::weight(T::WeightInfo::set_policy(policy.entries() as u32))]
+        pub fn set_policy(origin: OriginFor<T>, policy: AccessPolicyOf<T>) -> DispatchResult {
             let id = ensure_signed(origin)?;
 
-            Device::<T>::try_mutate(&id, |d| {
-                if let Some(ref mut dev) = d {
-                    dev.state = match (onoff, &dev.state) {
-                        (false, DeviceState::Ready | DeviceState::Off) => DeviceState::Off,
-                        //(false, DeviceState::Busy2 | DeviceState::Standby) => DeviceState::Standby,
-                        (true, DeviceState::Off) => DeviceState::Ready,
-                        (_) => return Err(Error::<T>::IllegalState.into()),
-                    };
-                    Ok(())
-                } else {
-                    Err(Error::<T>::NoDevice.into())
-                }
-            })
+            if !Device::<T>::contains_key(&id) {
+                return Err(Error::<T>::NoDevice.into());
+            }
+
+            if policy.entries() > T::MaxPolicyEntries::get() as usize {
+                return Err(Error::<T>::TooManyEntries.into());
+            }
+
+            Self::broadcast(XCMPMessageOf::<T>::DevicePolicy(
+                0,
+                id.clone(),
+                policy.clone().convert(),
+            ));
+            if policy == AccessPolicyOf::<T>::Open {
+                Policies::<T>::remove(&id);
+            } else {
+                Policies::<T>::insert(&id, policy);
+            }
+            Self::deposit_event(Event::PolicyChanged(id));
+            Ok(())
         }
-    }
-}
 
-impl<T: Config> Pallet<T> {
-    pub fn order_received(order: OrderOf<T>, device: T::AccountId) -> DispatchResult {
-        let now = Timestamp::<T>::get();
+        /// Starts protocol version negotiation with the parachain
+        #[pallet::weight(T::WeightInfo::handshake())]
+        pub fn handshake(origin: OriginFor<T>, paraid: ParaId) -> DispatchResult {
+            ensure_root(origin)?;
 
-        if now >= order.until {
-            return Err(Error::<T>::Overdue.into());
+            Self::send_message(paraid, XCMPMessageOf::<T>::Handshake(PROTOCOL_VERSION))
         }
 
-        if Orders::<T>::contains_key(&device) {
-            return Err(Error::<T>::IllegalState.into());
+        /// Pauses or resumes processing of cross-chain messages
+        #[pallet::weight(T::WeightInfo::set_pause())]
+        pub fn set_pause(
+            origin: OriginFor<T>,
+            target: PauseTarget<ParaId>,
+            paused: bool,
+        ) -> DispatchResult {
+            T::PauseOrigin::ensure_origin(origin)?;
+
+            if paused {
+                Pauses::<T>::insert(target, true);
+            } else {
+                Pauses::<T>::remove(target);
+            }
+            Self::deposit_event(Event::PauseChanged(target, paused));
+            Ok(())
         }
 
-        let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
-        if dev.state != DeviceState::Ready {
-            return Err(Error::<T>::IllegalState.into());
+        #[pallet::weight(T::WeightInfo::set_state())]
+        pub fn set_state(origin: OriginFor<T>, onoff: bool) -> DispatchResult {
+            let id = ensure_signed(origin)?;
+
+            let mut dev = Device::<T>::get(&id).ok_or(Error::<T>::NoDevice)?;
+            dev.state = match (onoff, &dev.state) {
+                (false, DeviceState::Ready | DeviceState::Off) => DeviceState::Off,
+                //(false, DeviceState::Busy2 | DeviceState::Standby) => DeviceState::Standby,
+                (true, DeviceState::Off) => DeviceState::Ready,
+                (_) => return Err(Error::<T>::IllegalState.into()),
+            };
+            Self::put_device(&id, &dev);
+            Ok(())
         }
 
-        if order.until < (now + dev.wcd) {
-            return Err(Error::<T>::BadOrderDetails.into());
+        /// Places an order on behalf of a sibling parachain, dispatched by an XCM `Transact`
+        #[pallet::weight(T::WeightInfo::order())]
+        pub fn sibling_order(origin: OriginFor<T>, order: OrderBaseOf<T>) -> DispatchResult {
+            let paraid = T::SiblingOrigin::ensure_origin(origin)?;
+            Self::local_order(T::SiblingAccount::convert(paraid), paraid, order)
         }
 
-        dev.state = T::OnReceived::on_received(&device, &order).ok_or(Error::<T>::IllegalState)?;
+        /// Cancels an order placed by the sibling parachain
+        #[pallet::weight(T::WeightInfo::cancel())]
+        pub fn sibling_cancel(origin: OriginFor<T>, device: T::AccountId) -> DispatchResult {
+            let paraid = T::SiblingOrigin::ensure_origin(origin)?;
+            Self::client_cancel(T::SiblingAccount::convert(paraid), device)
+        }
 
-        debug_assert!(matches!(dev.state, DeviceState::Busy | DeviceState::Busy2));
+        /// Starts or stops pushing device profiles to the parachain
+        #[pallet::weight(T::WeightInfo::set_subscriber())]
+        pub fn set_subscriber(
+            origin: OriginFor<T>,
+            paraid: ParaId,
+            subscribed: bool,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
 
-        if order.paraid == T::SelfParaId::get() {
-            if !T::Currency::can_reserve(&order.client, order.fee) {
+            if subscribed {
+                ApprovedSubscribers::<T>::insert(paraid, true);
+                Self::subscribe(paraid)?;
+            } else {
+                ApprovedSubscribers::<T>::remove(paraid);
+                Self::unsubscribe(paraid);
+            }
+            Self::deposit_event(Event::SubscriberApproved(paraid, subscribed));
+            Ok(())
+        }
+
+        /// Allows the parachain to subscribe to device profiles, a revoked one is unsubscribed
+        #[pallet::weight(T::WeightInfo::approve_subscriber())]
+        pub fn approve_subscriber(
+            origin: OriginFor<T>,
+            paraid: ParaId,
+            approved: bool,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            if approved {
+                ApprovedSubscribers::<T>::insert(paraid, true);
+            } else {
+                ApprovedSubscribers::<T>::remove(paraid);
+                Self::unsubscribe(paraid);
+            }
+            Self::deposit_event(Event::SubscriberApproved(paraid, approved));
+            Ok(())
+        }
+
+        /// Accepts the currency of the client parachain for fees or stops accepting it
+        #[pallet::weight(T::WeightInfo::set_fee_reserve())]
+        pub fn set_fee_reserve(
+            origin: OriginFor<T>,
+            paraid: ParaId,
+            accepted: bool,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            if accepted {
+                FeeReserves::<T>::insert(paraid, true);
+            } else {
+                FeeReserves::<T>::remove(paraid);
+            }
+            Self::deposit_event(Event::FeeReserveChanged(paraid, accepted));
+            Ok(())
+        }
+    }
+}
+
+impl<T: Config> Pallet<T> {
+    /// Places an order settled on this parachain, `paraid` is the parachain it came from
+    fn local_order(who: T::AccountId, paraid: ParaId, order: OrderBaseOf<T>) -> DispatchResult {
+        if Pauses::<T>::get(PauseTarget::All) || Pauses::<T>::get(PauseTarget::Para(paraid)) {
+            return Err(Error::<T>::Paused.into());
+        }
+        let OrderBaseOf::<T> {
+            data,
+            until,
+            until_relay,
+            fee,
+            device,
+        } = order;
+        let order = OrderOf::<T> {
+            // assigned on receipt
+            id: 0,
+            fee,
+            data,
+            until,
+            until_relay,
+            paraid,
+            client: who.into(),
+        };
+
+        Self::order_received(order, device)
+    }
+
+    /// Withdraws or closes the overdue order of the client
+    fn client_cancel(who: T::AccountId, device: T::AccountId) -> DispatchResult {
+        let order = Orders::<T>::get(&device).ok_or(Error::<T>::NoOrder)?;
+
+        let now = Timestamp::<T>::get();
+
+        if order.client != who {
+            return Err(Error::<T>::Prohibited.into());
+        }
+
+        let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+        if !Self::is_overdue(&order, &now) {
+            // Withdrawal before acceptance, collateral is released without penalty
+            if dev.state != DeviceState::Busy {
+                return Err(Error::<T>::Prohibited.into());
+            }
+            dev.state = DeviceState::Ready;
+        }
+        // Note. we don't change device state of overdue orders
+        Self::order_reject(
+            Some(&order),
+            now,
+            device,
+            &mut dev,
+            false,
+            OrderOutcome::Cancelled,
+        )
+    }
+
+    #[transactional]
+    pub fn order_received(order: OrderOf<T>, device: T::AccountId) -> DispatchResult {
+        let now = Timestamp::<T>::get();
+
+        if Self::is_overdue(&order, &now) {
+            return Err(Error::<T>::Overdue.into());
+        }
+
+        if Orders::<T>::contains_key(&device) {
+            return Err(Error::<T>::IllegalState.into());
+        }
+
+        let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+        if dev.state != DeviceState::Ready {
+            return Err(Error::<T>::IllegalState.into());
+        }
+
+        if !Policies::<T>::get(&device).permits(&order.paraid, &order.client) {
+            return Err(Error::<T>::NotAllowed.into());
+        }
+
+        if !Self::leaves_wcd(order.until, order.until_relay, now, dev.wcd) {
+            return Err(Error::<T>::BadOrderDetails.into());
+        }
+
+        dev.state = T::OnReceived::on_received(&device, &order).ok_or(Error::<T>::IllegalState)?;
+
+        debug_assert!(matches!(dev.state, DeviceState::Busy | DeviceState::Busy2));
+
+        if Self::is_local(&order) {
+            if !T::Currency::can_reserve(&order.client, order.fee) {
                 return Err(Error::<T>::DeviceLowBail.into());
             }
             T::Currency::reserve(&device, dev.penalty)?;
             T::Currency::reserve(&order.client, order.fee)?;
         }
 
+        let order = if Self::is_local(&order) {
+            OrderOf::<T> {
+                id: Self::next_order_id(),
+                ..order
+            }
+        } else {
+            order
+        };
         Orders::<T>::insert(&device, &order);
-        Self::deposit_event(Event::NewOrder(device.clone()));
+        if !Self::is_local(&order) {
+            OrderSettlements::<T>::insert(&device, SettlementModes::<T>::get(order.paraid));
+        }
+        OrderTimings::<T>::insert(&device, (now, None::<MomentOf<T>>));
+        Self::deposit_event(Event::NewOrder(
+            order.id,
+            order.client.clone(),
+            device.clone(),
+            order.paraid,
+            order.fee,
+        ));
 
         if dev.state == DeviceState::Busy2 {
-            Self::order_accept(&order, now, device, &mut dev);
+            Self::order_accept(&order, now, device, &mut dev)
         } else {
-            Device::<T>::insert(&device, &dev);
+            Self::put_device(&device, &dev);
+            Ok(())
         }
-        Ok(())
     }
 
     fn order_done(
@@ -368,6 +1214,7 @@ impl<T: Config> Pallet<T> {
         device: T::AccountId,
         dev: &mut DeviceProfile<T>,
         onoff: bool,
+        result: Option<T::Hash>,
     ) -> DispatchResult {
         // TODO Send XCM with done
         dev.state = if onoff {
@@ -376,56 +1223,97 @@ impl<T: Config> Pallet<T> {
             DeviceState::Off
         };
 
-        let para_id = T::SelfParaId::get();
-        Device::<T>::insert(&device, &*dev);
+        Self::put_device(&device, dev);
+        Self::drop_amendment(order, &device);
 
-        if order.paraid == para_id {
-            T::Currency::repatriate_reserved(&order.client, &device, order.fee, Free)?;
+        let settlement = if Self::is_local(order) {
+            let unpaid = T::Currency::repatriate_reserved(&order.client, &device, order.fee, Free)?;
+            let (penalty, slashed) = Self::settle_penalty(order, &now, &device, dev)?;
 
-            if now < order.until {
-                T::Currency::unreserve(&device, dev.penalty);
-            } else {
-                T::Currency::repatriate_reserved(&device, &order.client, dev.penalty, Free)?;
-            }
+            Some(SettlementOf::<T> {
+                paid: order.fee - unpaid,
+                refunded: Zero::zero(),
+                penalty,
+                slashed,
+            })
         } else {
+            Self::book_netted(order, &device, dev, order.fee);
             log::info!("send OrderDone message");
-            let msg: XCMPMessageOf<T> =
-                XCMPMessageOf::<T>::OrderDone(order.client.clone(), device.clone(), onoff);
-            T::XcmpMessageSender::send_blob_message(
-                order.paraid,
-                msg.encode(),
-                ServiceQuality::Ordered,
-            )
-            .map_err(|_| Error::<T>::CannotReachDestination)?;
+            let msg: XCMPMessageOf<T> = XCMPMessageOf::<T>::OrderDone(
+                order.id,
+                order.client.clone(),
+                device.clone(),
+                onoff,
+            );
+            Self::send_message(order.paraid, msg)?;
             log::info!("OrderDone's sent");
-        }
-
-        Self::deposit_event(Event::Done(device));
+            None
+        };
+        Progress::<T>::remove(&device);
+        Self::archive(
+            order,
+            &device,
+            now,
+            OrderOutcome::Done,
+            result,
+            settlement.clone(),
+        );
+
+        Self::deposit_event(Event::Done(
+            order.id,
+            order.client.clone(),
+            device,
+            order.paraid,
+            order.fee,
+            settlement,
+        ));
         Ok(())
     }
 
+    /// Records the closed foreign order for reconciliation if the client parachain nets its
+    /// fee, the collateral is the most it may net as penalty
+    fn book_netted(
+        order: &OrderOf<T>,
+        device: &T::AccountId,
+        dev: &DeviceProfile<T>,
+        fee: BalanceOf<T>,
+    ) {
+        if OrderSettlements::<T>::take(device) == SettlementMode::Netting {
+            Ledgers::<T>::mutate(order.paraid, device, |entry| {
+                entry.fees += fee;
+                entry.penalties += dev.penalty;
+            });
+        }
+    }
+
     fn order_accept(
         order: &OrderOf<T>,
-        _now: T::Moment,
+        now: T::Moment,
         device: T::AccountId,
         dev: &mut DeviceProfile<T>,
-    ) {
+    ) -> DispatchResult {
         dev.state = DeviceState::Busy2;
-        Device::<T>::insert(&device, &*dev);
-        let para_id = T::SelfParaId::get();
+        Self::put_device(&device, dev);
+        OrderTimings::<T>::mutate(&device, |timings| {
+            if let Some((_, accepted)) = timings {
+                *accepted = Some(now);
+            }
+        });
 
-        if order.paraid != para_id {
+        if !Self::is_local(order) {
             let msg: XCMPMessageOf<T> =
-                XCMPMessageOf::<T>::OrderAccept(order.client.clone(), device.clone());
-            T::XcmpMessageSender::send_blob_message(
-                order.paraid,
-                msg.encode(),
-                ServiceQuality::Ordered,
-            )
-            .map_err(|_| Error::<T>::CannotReachDestination);
+                XCMPMessageOf::<T>::OrderAccept(order.id, order.client.clone(), device.clone());
+            Self::send_message(order.paraid, msg)?;
         }
 
-        Self::deposit_event(Event::Accept(device));
+        Self::deposit_event(Event::Accept(
+            order.id,
+            order.client.clone(),
+            device,
+            order.paraid,
+            order.fee,
+        ));
+        Ok(())
     }
 
     fn order_reject(
@@ -434,106 +1322,979 @@ impl<T: Config> Pallet<T> {
         device: T::AccountId,
         dev: &mut DeviceProfile<T>,
         onoff: bool,
+        outcome: OrderOutcome,
     ) -> DispatchResult {
         if let Some(order) = order {
-            let para_id = T::SelfParaId::get();
+            Self::drop_amendment(order, &device);
 
-            if order.paraid == para_id {
-                T::Currency::unreserve(&order.client, order.fee);
-                if now < order.until {
-                    T::Currency::unreserve(&device, dev.penalty);
+            let settlement = if Self::is_local(order) {
+                let unrefunded = T::Currency::unreserve(&order.client, order.fee);
+                let (penalty, slashed) = Self::settle_penalty(order, &now, &device, dev)?;
+
+                Some(SettlementOf::<T> {
+                    paid: Zero::zero(),
+                    refunded: order.fee - unrefunded,
+                    penalty,
+                    slashed,
+                })
+            } else {
+                Self::book_netted(order, &device, dev, Zero::zero());
+                // The client parachain settles cancels once they are confirmed
+                let msg: XCMPMessageOf<T> = if outcome == OrderOutcome::Cancelled {
+                    XCMPMessageOf::<T>::OrderCancelled(
+                        order.id,
+                        order.client.clone(),
+                        device.clone(),
+                    )
                 } else {
-                    T::Currency::repatriate_reserved(&device, &order.client, dev.penalty, Free)?;
-                }
+                    XCMPMessageOf::<T>::OrderReject(
+                        order.id,
+                        order.client.clone(),
+                        device.clone(),
+                        onoff,
+                    )
+                };
+                log::info!("send {:?} message", msg.kind());
+                Self::send_message(order.paraid, msg)?;
+                None
+            };
+            Self::archive(order, &device, now, outcome, None, settlement.clone());
+            let (id, client, paraid, fee) =
+                (order.id, order.client.clone(), order.paraid, order.fee);
+            Self::deposit_event(if outcome == OrderOutcome::Cancelled {
+                Event::Cancelled(id, client, device.clone(), paraid, fee, settlement)
             } else {
-                log::info!("send OrderReject message");
-                let msg: XCMPMessageOf<T> =
-                    XCMPMessageOf::<T>::OrderReject(order.client.clone(), device.clone(), onoff);
-                T::XcmpMessageSender::send_blob_message(
-                    order.paraid,
-                    msg.encode(),
-                    ServiceQuality::Ordered,
-                )
-                .map_err(|_| Error::<T>::CannotReachDestination)?;
-                log::info!("OrderReject's sent");
-            }
+                Event::Reject(id, client, device.clone(), paraid, fee, settlement)
+            });
         }
         Orders::<T>::remove(&device);
+        Progress::<T>::remove(&device);
+
+        Self::put_device(&device, dev);
+
+        Ok(())
+    }
+
+    /// Stores the device profile, subscribed parachains are told about state changes
+    fn put_device(device: &T::AccountId, dev: &DeviceProfile<T>) {
+        let changed = Device::<T>::get(device).map_or(true, |old| old.state != dev.state);
+        Device::<T>::insert(device, dev);
+        if changed {
+            Self::broadcast(XCMPMessageOf::<T>::DeviceStateChanged(
+                0,
+                device.clone(),
+                dev.state,
+            ));
+        }
+    }
+
+    /// Queues the device registry change for the subscribed parachains, it is pushed once
+    /// there is weight left for every subscriber
+    fn broadcast(msg: XCMPMessageOf<T>) {
+        if SubscriberCount::<T>::get() == 0 {
+            return;
+        }
+        let (head, tail) = RegistryChangesBounds::<T>::get();
+        if tail - head >= T::MaxRegistryChanges::get() as u64 {
+            Self::resync_subscribers(head, tail);
+            return;
+        }
+        RegistryChanges::<T>::insert(tail, msg);
+        RegistryChangesBounds::<T>::put((head, tail + 1));
+    }
+
+    /// Drops the queued registry changes and restarts the snapshot of every subscriber, the
+    /// snapshots carry the changes
+    fn resync_subscribers(head: u64, tail: u64) {
+        for index in head..tail {
+            RegistryChanges::<T>::remove(index);
+        }
+        RegistryChangesBounds::<T>::put((tail, tail));
+        for (paraid, _) in Subscribers::<T>::iter() {
+            Snapshots::<T>::insert(paraid, (Vec::<u8>::new(), 0));
+        }
+        Self::deposit_event(Event::RegistryChangesDropped((tail - head) as u32));
+    }
+
+    /// Sends queued registry changes to every subscribed parachain in order, numbered so
+    /// the subscriber can detect a missed one
+    pub(crate) fn push_changes(limit: Weight) -> Weight {
+        let db = T::DbWeight::get();
+        let (start, tail) = RegistryChangesBounds::<T>::get();
+        let weight = T::WeightInfo::push_change(SubscriberCount::<T>::get());
+        let mut head = start;
+        let mut used = db.reads(2);
+
+        while head < tail && used + weight <= limit {
+            if let Some(msg) = RegistryChanges::<T>::take(head) {
+                let key = Self::changed_device(&msg).map(Device::<T>::hashed_key_for);
+                for (paraid, _) in Subscribers::<T>::iter() {
+                    // Mid-snapshot, only changes of devices already sent are pushed, the
+                    // others reach the subscriber with the snapshot
+                    if let Some((last, _)) = Snapshots::<T>::get(paraid) {
+                        if !matches!(&key, Some(key) if *key <= last) {
+                            continue;
+                        }
+                    }
+                    let seq = RegistrySequence::<T>::mutate(paraid, |next| {
+                        *next += 1;
+                        *next - 1
+                    });
+                    // A change dropped on a full outbox shows up as a gap, the subscriber resyncs
+                    Self::try_send(paraid, Self::sequenced(&msg, seq));
+                }
+            }
+            head += 1;
+            used += weight;
+        }
+
+        if head != start {
+            RegistryChangesBounds::<T>::put((head, tail));
+            used += db.writes(1);
+        }
+        used
+    }
+
+    /// Device the registry change is about
+    fn changed_device(msg: &XCMPMessageOf<T>) -> Option<&T::AccountId> {
+        match msg {
+            XCMPMessageOf::<T>::DeviceAnnounce(_, device, ..)
+            | XCMPMessageOf::<T>::DeviceUpdate(_, device, ..)
+            | XCMPMessageOf::<T>::DeviceStateChanged(_, device, _)
+            | XCMPMessageOf::<T>::DevicePolicy(_, device, _) => Some(device),
+            _ => None,
+        }
+    }
 
-        Device::<T>::insert(&device, &*dev);
-        Self::deposit_event(Event::Reject(device));
+    /// Registry change numbered for the subscriber
+    fn sequenced(msg: &XCMPMessageOf<T>, seq: u64) -> XCMPMessageOf<T> {
+        let mut msg = msg.clone();
+        match msg {
+            XCMPMessageOf::<T>::DeviceAnnounce(ref mut next, ..)
+            | XCMPMessageOf::<T>::DeviceUpdate(ref mut next, ..)
+            | XCMPMessageOf::<T>::DeviceStateChanged(ref mut next, ..)
+            | XCMPMessageOf::<T>::DevicePolicy(ref mut next, ..) => *next = seq,
+            _ => {}
+        }
+        msg
+    }
 
+    /// Records the subscription of the approved parachain, the device registry snapshot is
+    /// sent in chunks from `on_idle`. A subscriber that missed a change subscribes again,
+    /// the snapshot pending for it already covers the change
+    fn subscribe(paraid: ParaId) -> DispatchResult {
+        if !ApprovedSubscribers::<T>::get(paraid) {
+            return Err(Error::<T>::NotApproved.into());
+        }
+        if Snapshots::<T>::contains_key(paraid) {
+            return Ok(());
+        }
+        if !Subscribers::<T>::contains_key(paraid) {
+            if SubscriberCount::<T>::get() >= T::MaxSubscribers::get() {
+                return Err(Error::<T>::TooManySubscribers.into());
+            }
+            SubscriberCount::<T>::mutate(|count| *count += 1);
+        }
+        Subscribers::<T>::insert(paraid, true);
+        let now = frame_system::Pallet::<T>::block_number();
+        let start = match SnapshotStarts::<T>::get(paraid) {
+            Some(last) => now.max(last.saturating_add(T::ResubscribeInterval::get())),
+            None => now,
+        };
+        SnapshotStarts::<T>::insert(paraid, start);
+        Snapshots::<T>::insert(paraid, (Vec::<u8>::new(), 0));
+        Self::deposit_event(Event::SubscriberChanged(paraid, true));
         Ok(())
     }
+
+    /// Sends the next chunks of pending registry snapshots, as many as the weight allows.
+    /// A chunk the outbox has no room for is sent again later
+    pub(crate) fn send_snapshots(limit: Weight) -> Weight {
+        let db = T::DbWeight::get();
+        let chunk = T::SnapshotChunk::get().max(1);
+        let weight = T::WeightInfo::send_snapshot(chunk);
+        let now = frame_system::Pallet::<T>::block_number();
+        let pending: Vec<_> = Snapshots::<T>::iter().collect();
+        let mut used = db.reads(1 + 3 * pending.len() as Weight);
+
+        for (paraid, (mut last, mut sent)) in pending {
+            // Snapshots asked for again too soon wait for the interval
+            if SnapshotStarts::<T>::get(paraid).map_or(false, |start| start > now) {
+                continue;
+            }
+            // Changes of devices already sent may have been pushed between the chunks
+            let next = RegistrySequence::<T>::get(paraid);
+            while used + weight <= limit {
+                used += weight;
+                let (devices, key) = Self::device_page(&last, chunk as usize);
+                let count = devices.len() as u32;
+                let msg = XCMPMessageOf::<T>::DeviceSnapshot(next, devices);
+                if let Err(e) = Self::send_message(paraid, msg) {
+                    log::warn!("snapshot to {:?} postponed: {:?}", paraid, e);
+                    break;
+                }
+                sent += count;
+                // A short chunk is the last one, an empty registry is sent too
+                if count < chunk {
+                    Snapshots::<T>::remove(paraid);
+                    Self::deposit_event(Event::SnapshotSent(paraid, sent));
+                    break;
+                }
+                last = key;
+                Snapshots::<T>::insert(paraid, (last.clone(), sent));
+            }
+        }
+        used
+    }
+
+    /// Up to `limit` public device profiles stored after the raw storage key, from the first
+    /// one for an empty key, with the key of the last one
+    fn device_page(after: &[u8], limit: usize) -> (Vec<SnapshotEntryOf<T>>, Vec<u8>) {
+        let prefix = Device::<T>::final_prefix();
+        let mut key = if after.is_empty() {
+            prefix.to_vec()
+        } else {
+            after.to_vec()
+        };
+        let mut devices = Vec::new();
+        while devices.len() < limit {
+            match sp_io::storage::next_key(&key) {
+                Some(next) if next.starts_with(&prefix) => key = next,
+                _ => break,
+            }
+            let mut raw = Twox64Concat::reverse(&key[prefix.len()..]);
+            let id = match T::AccountId::decode(&mut raw) {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            if let Some(dev) = Device::<T>::get(&id) {
+                let policy = Policies::<T>::get(&id).convert();
+                devices.push((id, dev.penalty, dev.wcd, dev.state, policy));
+            }
+        }
+        (devices, key)
+    }
+
+    fn unsubscribe(paraid: ParaId) {
+        if Subscribers::<T>::take(paraid) {
+            SubscriberCount::<T>::mutate(|count| *count = count.saturating_sub(1));
+        }
+        Snapshots::<T>::remove(paraid);
+        RegistrySequence::<T>::remove(paraid);
+        Self::deposit_event(Event::SubscriberChanged(paraid, false));
+    }
+
+    /// Closes the foreign order withdrawn by the client before acceptance or after the deadline,
+    /// the client parachain is told if the order can't be withdrawn
+    fn on_cancel(
+        sender: ParaId,
+        id: OrderId,
+        client: T::AccountId,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        let order = match Orders::<T>::get(&device) {
+            Some(order) if Self::is_foreign(&order, sender, id, &client) => order,
+            // Closed before the cancel arrived
+            _ => return Self::refuse_cancel(sender, id, client, device),
+        };
+
+        let now = Timestamp::<T>::get();
+        let mut dev = Device::<T>::get(&device).ok_or(Error::<T>::NoDevice)?;
+        if !Self::is_overdue(&order, &now) {
+            if dev.state != DeviceState::Busy {
+                return Self::refuse_cancel(sender, id, client, device);
+            }
+            dev.state = DeviceState::Ready;
+        }
+        // Note. we don't change device state of overdue orders
+        Self::order_reject(
+            Some(&order),
+            now,
+            device,
+            &mut dev,
+            false,
+            OrderOutcome::Cancelled,
+        )
+    }
+
+    fn refuse_cancel(
+        sender: ParaId,
+        id: OrderId,
+        client: T::AccountId,
+        device: T::AccountId,
+    ) -> DispatchResult {
+        Self::send_message(
+            sender,
+            XCMPMessageOf::<T>::OrderCancelRejected(id, client.clone(), device.clone()),
+        )?;
+        Self::deposit_event(Event::CancelRejected(id, client, device, sender));
+        Ok(())
+    }
+
+    /// Records the amendment of an open order until the device accepts it
+    fn amend_received(
+        order: &OrderOf<T>,
+        device: T::AccountId,
+        amendment: AmendmentOf<T>,
+    ) -> DispatchResult {
+        let now = Timestamp::<T>::get();
+
+        if Self::is_overdue(order, &now) {
+            return Err(Error::<T>::Overdue.into());
+        }
+
+        if Amendments::<T>::contains_key(&device) {
+            return Err(Error::<T>::IllegalState.into());
+        }
+
+        if !amendment.extends(order) {
+            return Err(Error::<T>::BadOrderDetails.into());
+        }
+
+        if Self::is_local(order) {
+            T::Currency::reserve(&order.client, amendment.fee - order.fee)?;
+        }
+
+        let fee = amendment.fee;
+        Amendments::<T>::insert(&device, amendment);
+        Self::deposit_event(Event::AmendRequested(
+            order.id,
+            order.client.clone(),
+            device,
+            order.paraid,
+            fee,
+        ));
+        Ok(())
+    }
+
+    /// Relays the amendment of a foreign order to the device,
+    /// the client parachain is told if the order can't be amended
+    fn on_amend(
+        sender: ParaId,
+        id: OrderId,
+        client: T::AccountId,
+        device: T::AccountId,
+        amendment: AmendmentOf<T>,
+    ) -> DispatchResult {
+        let result = match Orders::<T>::get(&device) {
+            Some(order) if Self::is_foreign(&order, sender, id, &client) => {
+                Self::amend_received(&order, device.clone(), amendment)
+            }
+            // Closed before the amendment arrived
+            _ => Err(Error::<T>::NoOrder.into()),
+        };
+        if let Err(e) = result {
+            log::warn!("amendment of order {:?} refused: {:?}", id, e);
+            Self::send_message(
+                sender,
+                XCMPMessageOf::<T>::OrderAmendReject(id, client.clone(), device.clone()),
+            )?;
+            Self::deposit_event(Event::AmendRejected(id, client, device, sender));
+        }
+        Ok(())
+    }
+
+    /// Discards the pending amendment of a closed order, releasing its extra fee
+    fn drop_amendment(order: &OrderOf<T>, device: &T::AccountId) {
+        if let Some(amendment) = Amendments::<T>::take(device) {
+            if Self::is_local(order) {
+                T::Currency::unreserve(&order.client, amendment.fee - order.fee);
+            }
+        }
+    }
+
+    /// Releases the device collateral, or slashes it in favour of the client if overdue
+    fn settle_penalty(
+        order: &OrderOf<T>,
+        now: &MomentOf<T>,
+        device: &T::AccountId,
+        dev: &DeviceProfile<T>,
+    ) -> Result<(BalanceOf<T>, bool), DispatchError> {
+        if !Self::is_overdue(order, now) {
+            let remaining = T::Currency::unreserve(device, dev.penalty);
+            Ok((dev.penalty - remaining, false))
+        } else {
+            let remaining =
+                T::Currency::repatriate_reserved(device, &order.client, dev.penalty, Free)?;
+            Ok((dev.penalty - remaining, true))
+        }
+    }
+
+    /// Moves the closed order to the archive
+    fn archive(
+        order: &OrderOf<T>,
+        device: &T::AccountId,
+        now: MomentOf<T>,
+        outcome: OrderOutcome,
+        result: Option<T::Hash>,
+        settlement: Option<SettlementOf<T>>,
+    ) {
+        let (received, accepted) = OrderTimings::<T>::take(device).unwrap_or((now, None));
+
+        // Locally settled orders take their ids from `NextOrderId`
+        let key = if Self::is_local(order) {
+            (T::SelfParaId::get(), order.id)
+        } else {
+            (order.paraid, order.id)
+        };
+        Archive::<T>::insert(
+            key,
+            ArchivedOrder {
+                client: order.client.clone(),
+                device: device.clone(),
+                paraid: order.paraid,
+                fee: order.fee,
+                received,
+                accepted,
+                closed: now,
+                outcome,
+                result,
+                settlement,
+                archived_at: frame_system::Pallet::<T>::block_number(),
+            },
+        );
+        DeviceArchive::<T>::insert(device, key, ());
+        ClientArchive::<T>::insert(&order.client, key, ());
+        ArchiveQueueBounds::<T>::mutate(|(_, tail)| {
+            ArchiveQueue::<T>::insert(*tail, key);
+            *tail += 1;
+        });
+    }
+
+    /// Removes archived orders older than `ArchiveRetention`, within the weight limit
+    pub(crate) fn prune_archive(now: T::BlockNumber, limit: Weight) -> Weight {
+        let db = T::DbWeight::get();
+        let per_order = T::WeightInfo::prune_archived();
+        let (mut head, tail) = ArchiveQueueBounds::<T>::get();
+        let mut used = db.reads(1);
+
+        while head < tail && used + per_order + db.writes(1) <= limit {
+            if let Some(key) = ArchiveQueue::<T>::get(head) {
+                if let Some(order) = Archive::<T>::get(key) {
+                    if order.archived_at + T::ArchiveRetention::get() > now {
+                        break;
+                    }
+                    Archive::<T>::remove(key);
+                    DeviceArchive::<T>::remove(&order.device, key);
+                    ClientArchive::<T>::remove(&order.client, key);
+                }
+            }
+            ArchiveQueue::<T>::remove(head);
+            head += 1;
+            used += per_order;
+        }
+
+        if head != ArchiveQueueBounds::<T>::get().0 {
+            ArchiveQueueBounds::<T>::put((head, tail));
+            used += db.writes(1);
+        }
+        used
+    }
+
+    /// Closed orders of the device by client parachain and order id
+    pub fn device_history(device: &T::AccountId) -> Vec<(ParaId, OrderId)> {
+        DeviceArchive::<T>::iter_prefix(device)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Closed orders of the client by client parachain and order id
+    pub fn client_history(client: &T::AccountId) -> Vec<(ParaId, OrderId)> {
+        ClientArchive::<T>::iter_prefix(client)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    fn next_order_id() -> OrderId {
+        NextOrderId::<T>::mutate(|id| {
+            let next = *id;
+            *id = id.wrapping_add(1);
+            next
+        })
+    }
+
+    fn is_overdue(order: &OrderOf<T>, now: &MomentOf<T>) -> bool {
+        order.is_overdue(now, RelayParentNumber::<T>::get())
+    }
+
+    /// Checks the order deadline leaves the device at least one work circle
+    fn leaves_wcd(
+        until: MomentOf<T>,
+        until_relay: Option<RelayBlockNumber>,
+        now: MomentOf<T>,
+        wcd: MomentOf<T>,
+    ) -> bool {
+        pallet_common::leaves_wcd(
+            until,
+            until_relay,
+            now,
+            wcd,
+            RelayParentNumber::<T>::get(),
+            T::RelayBlockDuration::get(),
+        )
+    }
+
+    /// Device profile, used by the runtime API
+    pub fn device_info(device: &T::AccountId) -> Option<DeviceInfo<BalanceOf<T>, MomentOf<T>>> {
+        Device::<T>::get(device).map(|dev| DeviceInfo {
+            state: dev.state,
+            penalty: dev.penalty,
+            wcd: dev.wcd,
+            paraid: None,
+        })
+    }
+
+    /// Orders placed on this parachain, directly or by a sibling parachain, are settled here
+    fn is_local(order: &OrderOf<T>) -> bool {
+        order.paraid == T::SelfParaId::get()
+            || order.client == T::SiblingAccount::convert(order.paraid)
+    }
+
+    /// The order was sent by the client parachain under the given id
+    fn is_foreign(order: &OrderOf<T>, sender: ParaId, id: OrderId, client: &T::AccountId) -> bool {
+        !Self::is_local(order)
+            && order.paraid == sender
+            && order.id == id
+            && order.client == *client
+    }
+
+    /// Current order of the device, used by the runtime API
+    pub fn order_info(
+        device: &T::AccountId,
+    ) -> Option<Order<T::OrderPayload, BalanceOf<T>, MomentOf<T>, T::AccountId, u32>> {
+        Orders::<T>::get(device).map(|order| Order {
+            id: order.id,
+            until: order.until,
+            until_relay: order.until_relay,
+            data: order.data,
+            fee: order.fee,
+            client: order.client,
+            paraid: order.paraid.into(),
+        })
+    }
+
+    /// Devices ready to accept an order
+    pub fn ready_devices() -> Vec<T::AccountId> {
+        Device::<T>::iter()
+            .filter(|(_, dev)| dev.state == DeviceState::Ready)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Quotes a prospective local order, `dispatch_fee` is the fee of the `order` extrinsic
+    pub fn quote_order(
+        order: &OrderBaseOf<T>,
+        dispatch_fee: BalanceOf<T>,
+    ) -> Option<OrderQuote<BalanceOf<T>, MomentOf<T>>> {
+        let dev = Device::<T>::get(&order.device)?;
+        let now = Timestamp::<T>::get();
+
+        let available = dev.state == DeviceState::Ready
+            && !Orders::<T>::contains_key(&order.device)
+            && !order.is_overdue(&now, RelayParentNumber::<T>::get())
+            && Self::leaves_wcd(order.until, order.until_relay, now, dev.wcd);
+
+        Some(OrderQuote {
+            fee: order.fee,
+            penalty: dev.penalty,
+            dispatch_fee,
+            min_until: now.saturating_add(dev.wcd),
+            available,
+        })
+    }
+
+    /// Agrees on the highest protocol version both parachains support
+    fn on_handshake(sender: ParaId, version: ProtocolVersion) -> DispatchResult {
+        let agreed = version.min(PROTOCOL_VERSION);
+        Self::send_message(sender, XCMPMessageOf::<T>::HandshakeAck(agreed))?;
+
+        ProtocolVersions::<T>::insert(sender, agreed);
+        Self::deposit_event(Event::HandshakeCompleted(sender, agreed));
+        Ok(())
+    }
+
+    fn on_handshake_ack(sender: ParaId, version: ProtocolVersion) -> DispatchResult {
+        if version > PROTOCOL_VERSION {
+            return Err(Error::<T>::BadProtocolVersion.into());
+        }
+
+        ProtocolVersions::<T>::insert(sender, version);
+        Self::deposit_event(Event::HandshakeCompleted(sender, version));
+        Ok(())
+    }
+
+    fn on_ack(sender: ParaId, nonce: Nonce) -> DispatchResult {
+        // Duplicated acknowledgements of resent messages are ignored
+        if Self::take_unacked(sender, nonce).is_some() {
+            Self::deposit_event(Event::MessageAcked(sender, nonce));
+        }
+        Ok(())
+    }
+
+    fn on_nack(sender: ParaId, nonce: Nonce) -> DispatchResult {
+        if let Some(pending) = Self::take_unacked(sender, nonce) {
+            Self::deposit_event(Event::MessageNacked(sender, nonce, pending.kind));
+        }
+        Ok(())
+    }
+
+    /// Orders the client parachain places from now on are settled the chosen way
+    fn on_settlement_changed(sender: ParaId, mode: SettlementMode) {
+        SettlementModes::<T>::insert(sender, mode);
+        Self::deposit_event(Event::SettlementChanged(sender, mode));
+    }
+
+    /// Checks the client parachain settled no more than the fees of orders completed here,
+    /// netted no more than the collateral of orders closed here, and removes both from the
+    /// ledger
+    fn on_reconcile(
+        sender: ParaId,
+        epoch: EpochIndex,
+        entries: Vec<(T::AccountId, BalanceOf<T>, BalanceOf<T>)>,
+    ) -> DispatchResult {
+        // The same device may be listed more than once
+        let mut totals = BTreeMap::<T::AccountId, (BalanceOf<T>, BalanceOf<T>)>::new();
+        for (device, fees, penalties) in entries {
+            let total = totals.entry(device).or_insert((Zero::zero(), Zero::zero()));
+            total.0 = total.0.saturating_add(fees);
+            total.1 = total.1.saturating_add(penalties);
+        }
+
+        // Orders completed after the epoch was closed are still unknown to the client
+        let mut matched = true;
+        let mut remaining = Vec::with_capacity(totals.len());
+        for (device, (fees, penalties)) in totals {
+            let recorded = Ledgers::<T>::get(sender, &device);
+            let fees_left = recorded.fees.checked_sub(&fees);
+            if fees_left.is_none() {
+                matched = false;
+                Self::deposit_event(Event::ReconciliationMismatch(
+                    sender,
+                    epoch,
+                    device.clone(),
+                    fees,
+                    recorded.fees,
+                ));
+            }
+            let penalties_left = recorded.penalties.checked_sub(&penalties);
+            if penalties_left.is_none() {
+                matched = false;
+                Self::deposit_event(Event::PenaltyMismatch(
+                    sender,
+                    epoch,
+                    device.clone(),
+                    penalties,
+                    recorded.penalties,
+                ));
+            }
+            if let (Some(fees), Some(penalties)) = (fees_left, penalties_left) {
+                remaining.push((device, LedgerEntryOf::<T> { fees, penalties }));
+            }
+        }
+        if !matched {
+            return Err(Error::<T>::LedgerMismatch.into());
+        }
+
+        for (device, entry) in remaining {
+            // Collateral of orders the client has settled all fees of is no longer netted
+            if entry.fees.is_zero() {
+                Ledgers::<T>::remove(sender, &device);
+            } else {
+                Ledgers::<T>::insert(sender, &device, entry);
+            }
+        }
+        Self::deposit_event(Event::Reconciled(sender, epoch));
+        Ok(())
+    }
+
+    /// Refuses orders and queues other messages received while paused
+    fn on_paused(sender: ParaId, msg: XCMPMessageOf<T>) -> Weight {
+        if let XCMPMessageOf::<T>::NewOrder(id, client, order) = msg {
+            let reply = XCMPMessageOf::<T>::OrderFailed(id, client.clone(), order.device.clone());
+            Self::try_send(sender, reply);
+            Self::deposit_event(Event::OrderFailed(id, client, order.device, sender));
+            return T::WeightInfo::on_paused_order();
+        }
+        Self::queue_paused(sender, msg)
+    }
 }
 
-impl<T: Config> OnKilledAccount<T::AccountId> for Pallet<T> {
-    /// The account with the given id was reaped.
-    fn on_killed_account(who: &T::AccountId) {
-        //Timewait
-        if let Some(dev) = Device::<T>::get(who) {
-            if dev.state == DeviceState::Off {
-                Device::<T>::remove(who);
+impl<T: Config> From<MessagingEvent<ParaId>> for Event<T> {
+    fn from(event: MessagingEvent<ParaId>) -> Self {
+        match event {
+            MessagingEvent::MessageDeferred(dest, kind) => Event::MessageDeferred(dest, kind),
+            MessagingEvent::MessageDelivered(dest, kind) => Event::MessageDelivered(dest, kind),
+            MessagingEvent::MessageAbandoned(dest, kind, attempts) => {
+                Event::MessageAbandoned(dest, kind, attempts)
+            }
+            MessagingEvent::MessageRefused(dest, kind) => Event::MessageRefused(dest, kind),
+            MessagingEvent::MessageUnacknowledged(dest, nonce, kind, resends) => {
+                Event::MessageUnacknowledged(dest, nonce, kind, resends)
             }
+            MessagingEvent::UnackedAbandoned(dest, nonce, kind) => {
+                Event::UnackedAbandoned(dest, nonce, kind)
+            }
+            MessagingEvent::SequenceGap(sender, expected, received) => {
+                Event::SequenceGap(sender, expected, received)
+            }
+            MessagingEvent::DuplicateDropped(sender, nonce) => {
+                Event::DuplicateDropped(sender, nonce)
+            }
+            MessagingEvent::MessageQueued(sender, kind) => Event::MessageQueued(sender, kind),
+            MessagingEvent::MessageDropped(sender, kind) => Event::MessageDropped(sender, kind),
         }
     }
 }
 
-impl<T: Config> XcmpMessageHandler for Pallet<T> {
-    fn handle_xcm_message(_sender: ParaId, _sent_at: relay_chain::BlockNumber, xcm: VersionedXcm) {
-        let hash = xcm.using_encoded(T::Hashing::hash);
-        log::info!("Processing HRMP XCM: {:?}", &hash);
-        match Xcm::try_from(xcm) {
-            Ok(_xcm) => {
-                // let location = (
-                //     Junction::Parent,
-                //     Junction::Parachain { id: sender.into() },
-                // );
-                // match T::XcmExecutor::execute_xcm(location.into(), xcm) {
-                //     Ok(..) => RawEvent::Success(hash),
-                //     Err(e) => RawEvent::Fail(hash, e),
-                // }
-            }
-            Err(..) => Self::deposit_event(Event::BadVersion(hash)),
-        };
+impl<T: Config> From<MessagingError> for Error<T> {
+    fn from(error: MessagingError) -> Self {
+        match error {
+            MessagingError::OutboxFull => Error::OutboxFull,
+            MessagingError::TooManyUnacked => Error::TooManyUnacked,
+        }
     }
-    fn handle_blob_message(sender: ParaId, _sent_at: relay_chain::BlockNumber, blob: Vec<u8>) {
-        log::warn!("Processing Blob XCM: {:?}", &blob);
-        match XCMPMessageOf::<T>::decode(&mut blob.as_slice()) {
-            Err(e) => {
-                log::error!("{:?}", e);
-                return;
+}
+
+impl<T: Config> Messaging for Pallet<T> {
+    type Runtime = T;
+    type ParaId = ParaId;
+    type Message = XCMPMessageOf<T>;
+    type SendError = MessageSendError;
+    type Error = Error<T>;
+    type AckTimeout = T::AckTimeout;
+    type MaxUnacked = T::MaxUnacked;
+    type MaxSendAttempts = T::MaxSendAttempts;
+    type MaxOutboxMessages = T::MaxOutboxMessages;
+    type RetryBackoff = T::RetryBackoff;
+    type MaxPausedMessages = T::MaxPausedMessages;
+    type ProtocolVersions = ProtocolVersions<T>;
+    type NextNonce = NextNonce<T>;
+    type Unacked = Unacked<T>;
+    type UnackedCount = UnackedCount<T>;
+    type UnackedDestinations = UnackedDestinations<T>;
+    type UnackedCursor = UnackedCursor<T>;
+    type InboundSequence = InboundSequence<T>;
+    type InboundRefused = InboundRefused<T>;
+    type Outbox = Outbox<T>;
+    type OutboxBounds = OutboxBounds<T>;
+    type OutboxDestinations = OutboxDestinations<T>;
+    type OutboxCursor = OutboxCursor<T>;
+    type Pauses = Pauses<T>;
+    type PausedMessages = PausedMessages<T>;
+    type PausedMessagesBounds = PausedMessagesBounds<T>;
+
+    fn deposit_messaging_event(event: MessagingEvent<ParaId>) {
+        Self::deposit_event(event.into());
+    }
+
+    fn send_blob_message(dest: ParaId, blob: Vec<u8>) -> Result<(), MessageSendError> {
+        T::XcmpMessageSender::send_blob_message(dest, blob, ServiceQuality::Ordered)
+    }
+
+    /// Worst case weight of the message handler
+    fn message_weight(msg: &XCMPMessageOf<T>) -> Weight {
+        match msg {
+            XCMPMessageOf::<T>::NewOrder(..) => T::WeightInfo::on_new_order(),
+            XCMPMessageOf::<T>::OrderCancel(..) => T::WeightInfo::on_cancel(),
+            XCMPMessageOf::<T>::OrderAmend(..) => T::WeightInfo::on_amend(),
+            XCMPMessageOf::<T>::Handshake(..) | XCMPMessageOf::<T>::HandshakeAck(..) => {
+                T::WeightInfo::on_handshake()
+            }
+            XCMPMessageOf::<T>::Ack(..) | XCMPMessageOf::<T>::Nack(..) => T::WeightInfo::on_ack(),
+            XCMPMessageOf::<T>::Reconcile(_, entries) => {
+                T::WeightInfo::on_reconcile(entries.len() as u32)
             }
-            Ok(XCMPMessageOf::<T>::NewOrder(client, order)) => {
+            XCMPMessageOf::<T>::SettlementChanged(..) => T::WeightInfo::on_settlement_changed(),
+            XCMPMessageOf::<T>::Subscribe => T::WeightInfo::on_subscribe(),
+            XCMPMessageOf::<T>::Unsubscribe => T::WeightInfo::on_unsubscribe(),
+            _ => T::WeightInfo::on_bad_message(),
+        }
+    }
+
+    fn process_message(sender: ParaId, msg: XCMPMessageOf<T>) -> DispatchResult {
+        let kind = msg.kind();
+        let result = match msg {
+            XCMPMessageOf::<T>::NewOrder(id, client, order) => {
                 let OrderBaseOf::<T> {
                     data,
                     until,
+                    until_relay,
                     fee,
                     device,
                 } = order;
                 let order = OrderOf::<T> {
+                    id,
                     fee,
                     data,
                     until,
+                    until_relay,
                     paraid: sender,
                     client,
                 };
                 log::info!("new order received for {:?}", &device);
+                Self::order_received(order, device)
+            }
+            XCMPMessageOf::<T>::OrderCancel(id, client, device) => {
+                Self::on_cancel(sender, id, client, device)
+            }
+            XCMPMessageOf::<T>::OrderAmend(id, client, device, amendment) => {
+                Self::on_amend(sender, id, client, device, amendment)
+            }
+            XCMPMessageOf::<T>::Handshake(version) => Self::on_handshake(sender, version),
+            XCMPMessageOf::<T>::HandshakeAck(version) => Self::on_handshake_ack(sender, version),
+            XCMPMessageOf::<T>::Ack(nonce) => Self::on_ack(sender, nonce),
+            XCMPMessageOf::<T>::Nack(nonce) => Self::on_nack(sender, nonce),
+            XCMPMessageOf::<T>::Reconcile(epoch, entries) => {
+                Self::on_reconcile(sender, epoch, entries)
+            }
+            XCMPMessageOf::<T>::SettlementChanged(mode) => {
+                Self::on_settlement_changed(sender, mode);
+                Ok(())
+            }
+            XCMPMessageOf::<T>::Subscribe => Self::subscribe(sender),
+            XCMPMessageOf::<T>::Unsubscribe => {
+                Self::unsubscribe(sender);
+                Ok(())
+            }
+            _ => Err(Error::<T>::UnexpectedMessage.into()),
+        };
+        if let Err(e) = result {
+            log::error!("{:?} processing return {:?}", kind, e);
+        }
+        result
+    }
 
-                match Self::order_received(order, device) {
-                    Err(e) => {
-                        log::error!("order_received return {:?}", e);
-                    }
-                    Ok(_) => {
-                        log::info!("order_received succeed");
+    fn resend_message_weight() -> Weight {
+        T::WeightInfo::resend_message()
+    }
+
+    fn retry_message_weight() -> Weight {
+        T::WeightInfo::retry_message()
+    }
+
+    fn queue_message_weight() -> Weight {
+        T::WeightInfo::on_queue_message()
+    }
+}
+
+impl<T: Config> OnKilledAccount<T::AccountId> for Pallet<T> {
+    /// The account with the given id was reaped.
+    fn on_killed_account(who: &T::AccountId) {
+        //Timewait
+        if let Some(dev) = Device::<T>::get(who) {
+            if dev.state == DeviceState::Off {
+                Device::<T>::remove(who);
+            }
+        }
+    }
+}
+
+impl<T: Config> OnValidationData for Pallet<T> {
+    fn on_validation_data(data: &PersistedValidationData) {
+        RelayParentNumber::<T>::put(data.relay_parent_number);
+    }
+}
+
+impl<T: Config> XcmpMessageHandler for Pallet<T> {
+    fn handle_xcm_message(sender: ParaId, _sent_at: relay_chain::BlockNumber, xcm: VersionedXcm) {
+        let hash = xcm.using_encoded(T::Hashing::hash);
+        log::info!("Processing HRMP XCM: {:?}", &hash);
+        let (event, weight) = match Xcm::try_from(xcm) {
+            Ok(xcm) => match T::XcmWeigher::weigh(&xcm) {
+                Some(weight) if weight <= T::MaxXcmWeight::get() => {
+                    let location = MultiLocation::X2(
+                        Junction::Parent,
+                        Junction::Parachain { id: sender.into() },
+                    );
+                    match T::XcmExecutor::execute_xcm(location, xcm) {
+                        Ok(()) => (Event::XcmSuccess(hash, weight), weight),
+                        Err(e) => (Event::XcmFail(hash, e), weight),
                     }
                 }
+                _ => (Event::XcmOverweight(hash), 0),
+            },
+            Err(..) => (Event::BadVersion(hash), 0),
+        };
+        Self::deposit_event(event);
+        // Inbound messages are processed outside of any extrinsic
+        frame_system::Pallet::<T>::register_extra_weight_unchecked(
+            weight,
+            DispatchClass::Mandatory,
+        );
+    }
+    fn handle_blob_message(sender: ParaId, _sent_at: relay_chain::BlockNumber, blob: Vec<u8>) {
+        log::warn!("Processing Blob XCM: {:?}", &blob);
+        let weight = match XCMPMessageOf::<T>::from_wire(&blob) {
+            Err(e) => {
+                log::error!("{:?}", e);
+                T::WeightInfo::on_bad_message()
             }
-            Ok(_) => {
-                log::warn!("unknown XCMP message received");
-                return;
+            Ok((_, Some(nonce), _)) if !Self::check_sequence(sender, nonce) => {
+                T::WeightInfo::on_duplicate()
+            }
+            Ok((_, nonce, msg)) if Self::is_paused(sender, msg.kind()) => {
+                Self::acknowledge(sender, nonce, Ok(()));
+                Self::on_paused(sender, msg)
+            }
+            Ok((_, nonce, msg)) => {
+                let weight = Self::message_weight(&msg);
+                let result = Self::process_message(sender, msg);
+                Self::acknowledge(sender, nonce, result);
+                weight
             }
         };
-        //debug_assert!(false, "Blob messages not handled.")
+        // Inbound messages are processed outside of any extrinsic
+        frame_system::Pallet::<T>::register_extra_weight_unchecked(
+            weight,
+            DispatchClass::Mandatory,
+        );
+    }
+}
+
+/// Currencies of the client parachains accepted for fees, each parachain is the reserve of its
+/// own currency and deposits of it are kept apart from the native balance
+pub struct ReserveAssets<T, AccountIdConverter>(PhantomData<(T, AccountIdConverter)>);
+
+impl<T: Config, AccountIdConverter> ReserveAssets<T, AccountIdConverter> {
+    /// Client parachain and amount of an accepted currency
+    fn reserve_of(asset: &MultiAsset) -> Option<(ParaId, BalanceOf<T>)> {
+        match asset {
+            MultiAsset::ConcreteFungible {
+                id: MultiLocation::X2(Junction::Parent, Junction::Parachain { id }),
+                amount,
+            } if FeeReserves::<T>::get(ParaId::from(*id)) => {
+                Some((ParaId::from(*id), BalanceOf::<T>::try_from(*amount).ok()?))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<T: Config, AccountIdConverter> FilterAssetLocation for ReserveAssets<T, AccountIdConverter> {
+    fn filter_asset_location(asset: &MultiAsset, origin: &MultiLocation) -> bool {
+        matches!(asset, MultiAsset::ConcreteFungible { id, .. } if id == origin)
+            && Self::reserve_of(asset).is_some()
+    }
+}
+
+impl<T: Config, AccountIdConverter: LocationConversion<T::AccountId>> TransactAsset
+    for ReserveAssets<T, AccountIdConverter>
+{
+    fn deposit_asset(what: &MultiAsset, who: &MultiLocation) -> Result<(), XcmError> {
+        let (paraid, amount) = Self::reserve_of(what).ok_or(XcmError::Undefined)?;
+        let who = AccountIdConverter::from_location(who).ok_or(XcmError::Undefined)?;
+        ReserveBalances::<T>::mutate(paraid, who, |balance| {
+            *balance = balance.saturating_add(amount)
+        });
+        Ok(())
+    }
+
+    fn withdraw_asset(what: &MultiAsset, who: &MultiLocation) -> Result<MultiAsset, XcmError> {
+        let (paraid, amount) = Self::reserve_of(what).ok_or(XcmError::Undefined)?;
+        let who = AccountIdConverter::from_location(who).ok_or(XcmError::Undefined)?;
+        ReserveBalances::<T>::try_mutate(paraid, who, |balance| {
+            *balance = balance.checked_sub(&amount).ok_or(XcmError::Undefined)?;
+            Ok(what.clone())
+        })
     }
 }
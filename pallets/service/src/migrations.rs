@@ -0,0 +1,144 @@
+//! Storage migrations of the service pallet
+use super::*;
+use frame_support::{traits::PalletInfo, weights::Weight};
+use pallet_common::migrations as common;
+
+/// Runs all pending migrations
+pub fn migrate<T: Config>() -> Weight {
+    let mut weight = 0;
+
+    if StorageVersion::<T>::get() == Releases::V0 {
+        weight += v1::migrate::<T>();
+    }
+    if StorageVersion::<T>::get() == Releases::V1 {
+        weight += v2::migrate::<T>();
+    }
+    weight
+}
+
+#[cfg(any(feature = "try-runtime", test))]
+pub fn pre_migrate<T: Config>() -> Result<(), &'static str> {
+    match StorageVersion::<T>::get() {
+        Releases::V0 => v1::pre_migrate::<T>(),
+        Releases::V1 => v2::pre_migrate::<T>(),
+        _ => (),
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "try-runtime", test))]
+pub fn post_migrate<T: Config>() -> Result<(), &'static str> {
+    if common::is_counted(pallet_name::<T>(), 1) {
+        v1::post_migrate::<T>()?;
+    }
+    if common::is_counted(pallet_name::<T>(), 2) {
+        v2::post_migrate::<T>()?;
+    }
+    Ok(())
+}
+
+fn pallet_name<T: Config>() -> &'static [u8] {
+    <T as frame_system::Config>::PalletInfo::name::<Pallet<T>>()
+        .expect("pallet is part of the runtime; qed")
+        .as_bytes()
+}
+
+/// Moves unversioned storage to `Releases::V1`
+pub mod v1 {
+    use super::*;
+
+    pub type OldOrderOf<T> = common::OrderV0<
+        <T as Config>::OrderPayload,
+        BalanceOf<T>,
+        MomentOf<T>,
+        <T as frame_system::Config>::AccountId,
+        ParaId,
+    >;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let translated = common::translate_orders::<T::AccountId, OldOrderOf<T>, _>(
+            pallet_name::<T>(),
+            |_, old| old.upgrade(),
+        );
+        // DeviceProfile layout is unchanged
+        StorageVersion::<T>::put(Releases::V1);
+        log::info!("service storage migrated to V1, {} orders", translated);
+
+        T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+    }
+
+    #[cfg(any(feature = "try-runtime", test))]
+    pub fn pre_migrate<T: Config>() {
+        common::count_orders::<T::AccountId, OldOrderOf<T>>(pallet_name::<T>(), 1);
+    }
+
+    #[cfg(any(feature = "try-runtime", test))]
+    pub fn post_migrate<T: Config>() -> Result<(), &'static str> {
+        if StorageVersion::<T>::get() < Releases::V1 {
+            return Err("storage version is not updated");
+        }
+        common::verify_orders::<T::AccountId, OrderOf<T>>(pallet_name::<T>(), 1)?;
+        if Orders::<T>::iter().any(|(device, _)| !Device::<T>::contains_key(&device)) {
+            return Err("order of unknown device");
+        }
+        Ok(())
+    }
+}
+
+/// Assigns order ids, local orders count from `NextOrderId` and orders of every client
+/// parachain from zero, moves to `Releases::V2`
+pub mod v2 {
+    use super::*;
+
+    pub type OldOrderOf<T> = common::OrderV1<
+        <T as Config>::OrderPayload,
+        BalanceOf<T>,
+        MomentOf<T>,
+        <T as frame_system::Config>::AccountId,
+        ParaId,
+    >;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let mut next_id = NextOrderId::<T>::get();
+        // Orders of client parachains take the ids their clients assign in the same migration
+        let mut foreign_ids = common::ParaOrderIds::default();
+
+        let translated = common::translate_orders::<T::AccountId, OldOrderOf<T>, _>(
+            pallet_name::<T>(),
+            |_, old| {
+                let order = old.upgrade(0);
+                let id = if Pallet::<T>::is_local(&order) {
+                    next_id += 1;
+                    next_id - 1
+                } else {
+                    foreign_ids.next(order.paraid)
+                };
+                OrderOf::<T> { id, ..order }
+            },
+        );
+        NextOrderId::<T>::put(next_id);
+        StorageVersion::<T>::put(Releases::V2);
+        log::info!("service storage migrated to V2, {} orders", translated);
+
+        T::DbWeight::get().reads_writes(translated + 2, translated + 2)
+    }
+
+    #[cfg(any(feature = "try-runtime", test))]
+    pub fn pre_migrate<T: Config>() {
+        common::count_orders::<T::AccountId, OldOrderOf<T>>(pallet_name::<T>(), 2);
+    }
+
+    #[cfg(any(feature = "try-runtime", test))]
+    pub fn post_migrate<T: Config>() -> Result<(), &'static str> {
+        if StorageVersion::<T>::get() != Releases::V2 {
+            return Err("storage version is not updated");
+        }
+        common::verify_orders::<T::AccountId, OrderOf<T>>(pallet_name::<T>(), 2)?;
+        let (local, foreign): (Vec<_>, Vec<_>) = Orders::<T>::iter()
+            .map(|(_, order)| order)
+            .partition(|order| Pallet::<T>::is_local(order));
+        let ids = local.iter().map(|order| order.id).collect();
+        common::verify_order_ids(ids, NextOrderId::<T>::get())?;
+        common::verify_para_order_ids(foreign.into_iter().map(|order| (order.paraid, order.id)))
+    }
+}
@@ -1,7 +1,7 @@
 #![allow(clippy::from_over_into)]
 
 use crate as pallet_service;
-use frame_support::parameter_types;
+use frame_support::{parameter_types, traits::Get, weights::Weight};
 use frame_system as system;
 use frame_system::EnsureRoot;
 use sp_core::H256;
@@ -16,13 +16,16 @@ use xcm_builder::{
     SovereignSignedViaLocation,
 };
 
-use xcm::v0::{Junction, MultiLocation, NetworkId};
-use xcm_executor::traits::{IsConcrete, NativeAsset};
+use xcm::v0::{Junction, MultiAsset, MultiLocation, NetworkId};
+use xcm_executor::traits::{FilterAssetLocation, MatchesFungible};
 use xcm_executor::{Config, XcmExecutor};
 
 use polkadot_parachain::primitives::Id as ParaId;
 use polkadot_parachain::primitives::Sibling;
 
+use cumulus_primitives_core::{MessageSendError, ServiceQuality, XcmpMessageSender};
+use std::cell::RefCell;
+
 pub const MILLISECS_PER_BLOCK: u64 = 12000;
 pub const SLOT_DURATION: u64 = MILLISECS_PER_BLOCK;
 
@@ -56,17 +59,45 @@ type LocalOriginConverter = (
     SignedAccountId32AsNative<RococoNetwork, Origin>,
 );
 
-type LocalAssetTransactor = CurrencyAdapter<
+/// Native balance is backed by the relay currency only, the relay chain is its reserve
+pub struct FeeAsset;
+impl MatchesFungible<Balance> for FeeAsset {
+    fn matches_fungible(a: &MultiAsset) -> Option<Balance> {
+        match a {
+            MultiAsset::ConcreteFungible { id, amount } if *id == RococoLocation::get() => {
+                Some(*amount)
+            }
+            _ => None,
+        }
+    }
+}
+impl FilterAssetLocation for FeeAsset {
+    fn filter_asset_location(asset: &MultiAsset, origin: &MultiLocation) -> bool {
+        matches!(asset, MultiAsset::ConcreteFungible { id, .. } if id == origin && *id == RococoLocation::get())
+    }
+}
+
+type NativeAssetTransactor = CurrencyAdapter<
     // Use this currency:
     Balances,
     // Use this currency when it is a fungible asset matching the given location or name:
-    IsConcrete<RococoLocation>,
+    FeeAsset,
     // Do a simple punn to convert an AccountId32 MultiLocation into a native chain account ID:
     LocationConverter,
     // Our chain's account ID type (we can't get away without mentioning it explicitly):
     AccountId,
 >;
 
+/// Currencies of the client parachains accepted by governance, kept apart from native balance
+type LocalAssetTransactor = (
+    NativeAssetTransactor,
+    pallet_service::ReserveAssets<Test, LocationConverter>,
+);
+type Reserves = (
+    FeeAsset,
+    pallet_service::ReserveAssets<Test, LocationConverter>,
+);
+
 pub struct XcmConfig;
 impl Config for XcmConfig {
     type Call = Call;
@@ -74,7 +105,7 @@ impl Config for XcmConfig {
     // How to withdraw and deposit an asset.
     type AssetTransactor = LocalAssetTransactor;
     type OriginConverter = LocalOriginConverter;
-    type IsReserve = NativeAsset;
+    type IsReserve = Reserves;
     type IsTeleporter = ();
     type LocationInverter = LocationInverter<Ancestry>;
 }
@@ -174,15 +205,105 @@ impl pallet_timestamp::Config for Test {
 
 parameter_types! {
     pub const OwnParamId: u32 = 0;
+    pub const RelayBlockDuration: u64 = 6000;
+    pub const ArchiveRetention: u64 = 10;
+    pub const MaxPolicyEntries: u32 = 2;
+    pub const MaxPausedMessages: u32 = 2;
+    pub const SnapshotChunk: u32 = 2;
+    pub const MaxSubscribers: u32 = 2;
+    pub const MaxRegistryChanges: u32 = 4;
+    pub const ResubscribeInterval: u64 = 5;
+    pub const MaxSendAttempts: u32 = 3;
+    pub const MaxOutboxMessages: u32 = 2;
+    pub const RetryBackoff: u64 = 2;
+    pub const AckTimeout: u64 = 5;
+    pub const MaxUnacked: u32 = 4;
+    pub const XcmInstructionWeight: Weight = 1_000;
+    pub const MaxXcmWeight: Weight = 1_000_000;
+}
+
+thread_local! {
+    /// Blobs handed over to the channel
+    pub static SENT: RefCell<Vec<(ParaId, Vec<u8>)>> = RefCell::new(vec![]);
+    /// Refuse all sends while set
+    pub static CHANNEL_CLOSED: RefCell<bool> = RefCell::new(false);
+}
+
+pub struct XcmpSender;
+impl XcmpMessageSender for XcmpSender {
+    fn send_blob_message<T: Into<ParaId>>(
+        dest: T,
+        msg: Vec<u8>,
+        _qos: ServiceQuality,
+    ) -> Result<u32, MessageSendError> {
+        if CHANNEL_CLOSED.with(|closed| *closed.borrow()) {
+            return Err(MessageSendError::NoChannel);
+        }
+        SENT.with(|sent| sent.borrow_mut().push((dest.into(), msg)));
+        Ok(0)
+    }
+}
+
+pub fn close_channel(closed: bool) {
+    CHANNEL_CLOSED.with(|c| *c.borrow_mut() = closed);
+}
+
+pub fn sent_messages() -> Vec<(ParaId, Vec<u8>)> {
+    SENT.with(|sent| sent.borrow().clone())
+}
+
+pub struct EnsureSiblingPara;
+impl frame_support::traits::EnsureOrigin<Origin> for EnsureSiblingPara {
+    type Success = ParaId;
+    fn try_origin(o: Origin) -> Result<ParaId, Origin> {
+        o.into().and_then(|o| match o {
+            cumulus_pallet_xcm_handler::Origin::SiblingParachain(id) => Ok(id),
+            r => Err(Origin::from(r)),
+        })
+    }
+    #[cfg(feature = "runtime-benchmarks")]
+    fn successful_origin() -> Origin {
+        Origin::from(cumulus_pallet_xcm_handler::Origin::SiblingParachain(
+            ParaId::from(0),
+        ))
+    }
+}
+
+pub struct SiblingAccount;
+impl sp_runtime::traits::Convert<ParaId, AccountId> for SiblingAccount {
+    fn convert(id: ParaId) -> AccountId {
+        use sp_runtime::traits::AccountIdConversion;
+        Sibling::from(id).into_account()
+    }
 }
 
 impl pallet_service::Config for Test {
     type Event = Event;
     type Currency = Balances;
     type OrderPayload = Vec<u8>;
-    type XcmSender = ();
+    type XcmpMessageSender = XcmpSender;
+    type XcmExecutor = XcmExecutor<XcmConfig>;
+    type XcmWeigher = pallet_common::FixedXcmWeight<XcmInstructionWeight, Call>;
+    type MaxXcmWeight = MaxXcmWeight;
     type SelfParaId = OwnParamId;
     type OnReceived = ();
+    type RelayBlockDuration = RelayBlockDuration;
+    type ArchiveRetention = ArchiveRetention;
+    type MaxPolicyEntries = MaxPolicyEntries;
+    type SiblingOrigin = EnsureSiblingPara;
+    type SiblingAccount = SiblingAccount;
+    type PauseOrigin = EnsureRoot<AccountId>;
+    type MaxPausedMessages = MaxPausedMessages;
+    type SnapshotChunk = SnapshotChunk;
+    type MaxSubscribers = MaxSubscribers;
+    type MaxRegistryChanges = MaxRegistryChanges;
+    type ResubscribeInterval = ResubscribeInterval;
+    type MaxSendAttempts = MaxSendAttempts;
+    type MaxOutboxMessages = MaxOutboxMessages;
+    type RetryBackoff = RetryBackoff;
+    type AckTimeout = AckTimeout;
+    type MaxUnacked = MaxUnacked;
+    type WeightInfo = ();
 }
 
 static INIT_DATA: [u64; 6] = [1, 2, 100, 101, 200, 201];
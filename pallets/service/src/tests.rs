@@ -1,9 +1,9 @@
 use frame_support::dispatch::{DispatchError, DispatchResult};
-use frame_support::traits::Currency;
+use frame_support::traits::{Currency, GenesisBuild, Get};
 use frame_support::{assert_err, assert_noop, assert_ok};
 use frame_system::{ensure_signed, RawOrigin};
 
-use crate::{mock::*, DeviceProfile, DeviceState, Error};
+use crate::{mock::*, DeviceProfile, DeviceState, Error, Telemetry};
 
 const DEV1: u64 = 100;
 const DEV2: u64 = 101;
@@ -13,6 +13,8 @@ const CL1: u64 = 200;
 const CL2: u64 = 201;
 const CL3: u64 = 203; // zero balance
 
+const LOCAL: u32 = 0; // the service parachain itself
+
 const PENALTY: Balance = 10_000;
 const DEFAULT_WCD: Moment = 1000;
 const DEFAULT_FEE: Balance = 100_000;
@@ -33,6 +35,7 @@ fn aux_order(dev_id: u64, client: u64, fee: Balance, wcd: Moment) -> DispatchRes
         Origin::signed(account(client)),
         OutOrder {
             until,
+            until_relay: None,
             data: Vec::new(),
             fee,
             device: account(dev_id),
@@ -58,6 +61,7 @@ fn aux_init_order(dev_id: u64, client: u64, wcd: Moment) -> Result<Order, Dispat
         Origin::signed(account(client)),
         OutOrder {
             until,
+            until_relay: None,
             data: Vec::new(),
             fee: DEFAULT_FEE,
             device: dev_id.clone(),
@@ -266,7 +270,11 @@ fn full_circle() {
         let order = ServiceModule::orders(&devid);
         assert!(order.is_some());
 
-        assert_ok!(ServiceModule::done(Origin::signed(devid.clone()), true));
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            true,
+            None
+        ));
         let dev1 = assert_some!(ServiceModule::devices(&devid));
         assert_eq!(dev1.state, DeviceState::Ready);
 
@@ -337,7 +345,11 @@ fn order_hasty_cancel() {
         let devid = account(DEV1);
         let _order = aux_init_order(DEV1, CL1, DEFAULT_WCD * 10);
         crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 1);
-        //assert_ok!(ServiceModule::accept(Origin::signed(account(DEV1)), false));
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
         let order = ServiceModule::orders(&devid);
         assert!(order.is_some());
 
@@ -356,6 +368,112 @@ fn order_hasty_cancel() {
     });
 }
 
+#[test]
+fn order_cancel_before_accept() {
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let c1 = aux_total_balance(CL1);
+        let d1 = aux_total_balance(DEV1);
+
+        let _order = aux_init_order(DEV1, CL1, DEFAULT_WCD * 10);
+        crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 1);
+        assert_err!(
+            ServiceModule::cancel(Origin::signed(account(CL2)), devid.clone()),
+            Error::<Test>::Prohibited
+        );
+        assert_ok!(ServiceModule::cancel(
+            Origin::signed(account(CL1)),
+            devid.clone()
+        ));
+
+        assert!(ServiceModule::orders(&devid).is_none());
+        let dev = assert_some!(ServiceModule::devices(&devid));
+        assert_eq!(dev.state, DeviceState::Ready);
+        assert_eq!(aux_free_balance(CL1), c1);
+        assert_eq!(aux_free_balance(DEV1), d1);
+    });
+}
+
+#[test]
+fn foreign_order_cancel() {
+    use codec::Encode;
+    use cumulus_primitives_core::XcmpMessageHandler;
+
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let client = account(CL1);
+        assert_some!(aux_register_device(DEV1, true));
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(7, client.clone(), order);
+        ServiceModule::handle_blob_message(1000.into(), 0, msg.encode());
+        let order = assert_some!(ServiceModule::orders(&devid));
+        // Foreign orders keep the id assigned by the client parachain
+        assert_eq!(order.id, 7);
+
+        // Only the parachain the order came from can withdraw it
+        let msg = crate::XCMPMessageOf::<Test>::OrderCancel(7, client.clone(), devid.clone());
+        ServiceModule::handle_blob_message(1001.into(), 0, msg.encode());
+        assert_some!(ServiceModule::orders(&devid));
+
+        // Cancel of another order of the client
+        let stale = crate::XCMPMessageOf::<Test>::OrderCancel(6, client.clone(), devid.clone());
+        ServiceModule::handle_blob_message(1000.into(), 0, stale.encode());
+        assert_some!(ServiceModule::orders(&devid));
+
+        ServiceModule::handle_blob_message(1000.into(), 0, msg.encode());
+        assert!(ServiceModule::orders(&devid).is_none());
+        let dev = assert_some!(ServiceModule::devices(&devid));
+        assert_eq!(dev.state, DeviceState::Ready);
+        let record = assert_some!(ServiceModule::archived_order((1000.into(), 7)));
+        assert_eq!(record.outcome, crate::OrderOutcome::Cancelled);
+        assert_eq!(record.settlement, None);
+    });
+}
+
+#[test]
+fn foreign_amend_rejected() {
+    use codec::Encode;
+    use cumulus_primitives_core::XcmpMessageHandler;
+
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let client = account(CL1);
+        assert_some!(aux_register_device(DEV1, true));
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(3, client.clone(), order);
+        ServiceModule::handle_blob_message(1000.into(), 0, msg.encode());
+        assert_some!(ServiceModule::orders(&devid));
+
+        // Shortening the deadline is refused, the client parachain releases the extra fee
+        let amendment = crate::AmendmentOf::<Test> {
+            until: DEFAULT_WCD * 5,
+            until_relay: None,
+            fee: DEFAULT_FEE * 2,
+        };
+        let msg =
+            crate::XCMPMessageOf::<Test>::OrderAmend(3, client.clone(), devid.clone(), amendment);
+        ServiceModule::handle_blob_message(1000.into(), 0, msg.encode());
+        assert!(ServiceModule::amendments(&devid).is_none());
+
+        let reject = crate::XCMPMessageOf::<Test>::OrderAmendReject(3, client, devid).encode();
+        assert!(sent_messages().contains(&(1000.into(), reject)));
+    });
+}
+
 #[test]
 fn delay_accept() {
     new_test_ext().execute_with(|| {
@@ -406,7 +524,11 @@ fn delay_confirm() {
         assert_eq!(d1, d2);
         // delay
         crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 11);
-        assert_ok!(ServiceModule::done(Origin::signed(account(DEV1)), true));
+        assert_ok!(ServiceModule::done(
+            Origin::signed(account(DEV1)),
+            true,
+            None
+        ));
 
         let d3 = aux_total_balance(DEV1);
         assert_eq!(d3 - d1, DEFAULT_FEE - PENALTY);
@@ -436,7 +558,11 @@ fn device_off_after_confirm() {
         assert!(order.is_some());
 
         // confirm
-        assert_ok!(ServiceModule::done(Origin::signed(devid.clone()), false));
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            false,
+            None
+        ));
 
         let dev = assert_some!(ServiceModule::devices(&devid));
         assert_eq!(dev.state, DeviceState::Off);
@@ -467,7 +593,11 @@ fn device_reregister() {
             Error::<Test>::DeviceExists
         );
         // amend
-        assert_ok!(ServiceModule::done(Origin::signed(devid.clone()), false));
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            false,
+            None
+        ));
         let dev = assert_some!(ServiceModule::devices(&devid));
         assert_eq!(dev.state, DeviceState::Off);
 
@@ -508,3 +638,1830 @@ fn order_abandoned() {
         );
     });
 }
+
+#[test]
+fn report_progress() {
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let _order = aux_init_order(DEV1, CL1, DEFAULT_WCD * 10);
+        let telemetry = Telemetry {
+            progress: 50,
+            position: Some((55_751_244, 37_618_423, 150_000)),
+            digest: [7; 32],
+        };
+
+        // not accepted yet
+        assert_err!(
+            ServiceModule::report(Origin::signed(devid.clone()), telemetry.clone(), false),
+            Error::<Test>::IllegalState
+        );
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+        assert_err!(
+            ServiceModule::report(
+                Origin::signed(devid.clone()),
+                Telemetry {
+                    progress: 101,
+                    ..telemetry.clone()
+                },
+                false
+            ),
+            Error::<Test>::BadTelemetry
+        );
+
+        crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 2);
+        assert_ok!(ServiceModule::report(
+            Origin::signed(devid.clone()),
+            telemetry.clone(),
+            true
+        ));
+        let (at, snapshot) = assert_some!(ServiceModule::progress(&devid));
+        assert_eq!(at, DEFAULT_WCD * 2);
+        assert_eq!(snapshot, telemetry);
+
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            true,
+            None
+        ));
+        assert!(ServiceModule::progress(&devid).is_none());
+    });
+}
+
+#[test]
+fn relay_deadline() {
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let d1 = aux_total_balance(DEV1);
+        let _dev1 = assert_some!(aux_register_device(DEV1, true));
+        crate::RelayParentNumber::<Test>::put(10);
+
+        let order = |until_relay| {
+            ServiceModule::order(
+                Origin::signed(account(CL1)),
+                OutOrder {
+                    until: 0,
+                    until_relay: Some(until_relay),
+                    data: Vec::new(),
+                    fee: DEFAULT_FEE,
+                    device: devid.clone(),
+                },
+            )
+        };
+        assert_err!(order(10), Error::<Test>::Overdue);
+        assert_ok!(order(12));
+
+        // timestamp deadline is ignored
+        crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 100);
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+        assert_err!(
+            ServiceModule::cancel(Origin::signed(account(CL1)), devid.clone()),
+            Error::<Test>::Prohibited
+        );
+
+        crate::RelayParentNumber::<Test>::put(12);
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            true,
+            None
+        ));
+        let d2 = aux_total_balance(DEV1);
+        assert_eq!(d2 - d1, DEFAULT_FEE - PENALTY);
+    });
+}
+
+#[test]
+fn genesis_devices() {
+    let mut storage = frame_system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+    crate::GenesisConfig::<Test> {
+        devices: vec![
+            (account(DEV1), PENALTY, DEFAULT_WCD, true),
+            (account(DEV2), PENALTY * 2, DEFAULT_WCD * 2, false),
+        ],
+    }
+    .assimilate_storage(&mut storage)
+    .unwrap();
+
+    sp_io::TestExternalities::from(storage).execute_with(|| {
+        let dev1 = assert_some!(ServiceModule::devices(account(DEV1)));
+        assert_eq!(dev1.state, DeviceState::Ready);
+        assert_eq!(dev1.penalty, PENALTY);
+        assert_eq!(dev1.wcd, DEFAULT_WCD);
+
+        let dev2 = assert_some!(ServiceModule::devices(account(DEV2)));
+        assert_eq!(dev2.state, DeviceState::Off);
+        assert_eq!(dev2.wcd, DEFAULT_WCD * 2);
+    });
+}
+
+#[test]
+fn migrate_to_v1() {
+    use crate::migrations::{self, v1::OldOrderOf};
+    use codec::Encode;
+    use frame_support::{storage::migration::put_storage_value, Hashable};
+
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let _dev1 = assert_some!(aux_register_device(DEV1, true));
+        put_storage_value(
+            b"ServiceModule",
+            b"Orders",
+            &devid.twox_64_concat(),
+            OldOrderOf::<Test> {
+                until: DEFAULT_WCD,
+                data: vec![1, 2, 3],
+                fee: DEFAULT_FEE,
+                client: account(CL1),
+                paraid: 0.into(),
+            },
+        );
+        assert!(ServiceModule::orders(&devid).is_none());
+
+        assert_ok!(migrations::pre_migrate::<Test>());
+        migrations::migrate::<Test>();
+        assert_ok!(migrations::post_migrate::<Test>());
+
+        let order = assert_some!(ServiceModule::orders(&devid));
+        assert_eq!(order.until, DEFAULT_WCD);
+        assert_eq!(order.until_relay, None);
+        assert_eq!(order.data, vec![1, 2, 3]);
+        assert_eq!(order.client, account(CL1));
+        assert_eq!(order.id, 0);
+        assert_eq!(crate::StorageVersion::<Test>::get(), crate::Releases::V2);
+    });
+}
+
+#[test]
+fn migrate_to_v2() {
+    use crate::migrations::{self, v2::OldOrderOf};
+    use frame_support::{storage::migration::put_storage_value, Hashable};
+
+    new_test_ext().execute_with(|| {
+        crate::StorageVersion::<Test>::put(crate::Releases::V1);
+        crate::NextOrderId::<Test>::put(5);
+        let orders = [(DEV1, CL1, LOCAL), (DEV2, CL2, 1000), (DEV3, CL3, 1000)];
+        for (dev, client, paraid) in orders.iter() {
+            assert_some!(aux_register_device(*dev, true));
+            put_storage_value(
+                b"ServiceModule",
+                b"Orders",
+                &account(*dev).twox_64_concat(),
+                OldOrderOf::<Test> {
+                    until: DEFAULT_WCD,
+                    until_relay: Some(7),
+                    data: vec![1, 2, 3],
+                    fee: DEFAULT_FEE,
+                    client: account(*client),
+                    paraid: (*paraid).into(),
+                },
+            );
+        }
+
+        assert_ok!(migrations::pre_migrate::<Test>());
+        migrations::migrate::<Test>();
+        assert_ok!(migrations::post_migrate::<Test>());
+
+        let order1 = assert_some!(ServiceModule::orders(account(DEV1)));
+        let order2 = assert_some!(ServiceModule::orders(account(DEV2)));
+        let order3 = assert_some!(ServiceModule::orders(account(DEV3)));
+        assert_eq!(order1.until_relay, Some(7));
+        assert_eq!(order2.client, account(CL2));
+        assert_eq!(order1.id, 5);
+        assert_eq!(crate::NextOrderId::<Test>::get(), 6);
+        // Orders of the client parachain are numbered in the key order, as its client does
+        let ids = if account(DEV2).twox_64_concat() < account(DEV3).twox_64_concat() {
+            (0, 1)
+        } else {
+            (1, 0)
+        };
+        assert_eq!((order2.id, order3.id), ids);
+        assert_eq!(crate::StorageVersion::<Test>::get(), crate::Releases::V2);
+    });
+}
+
+#[test]
+fn order_events() {
+    use crate::{Event as ServiceEvent, Settlement};
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let devid = account(DEV1);
+        assert_some!(aux_register_device(DEV1, true));
+
+        assert_ok!(aux_order(DEV1, CL1, DEFAULT_FEE, DEFAULT_WCD * 3));
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+        crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 4);
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            true,
+            None
+        ));
+
+        let events: Vec<_> = System::events()
+            .into_iter()
+            .filter_map(|record| match record.event {
+                Event::pallet_service(event) => Some(event),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            &events[1..],
+            &[
+                ServiceEvent::NewOrder(0, account(CL1), devid.clone(), 0.into(), DEFAULT_FEE),
+                ServiceEvent::Accept(0, account(CL1), devid.clone(), 0.into(), DEFAULT_FEE),
+                ServiceEvent::Done(
+                    0,
+                    account(CL1),
+                    devid.clone(),
+                    0.into(),
+                    DEFAULT_FEE,
+                    Some(Settlement {
+                        paid: DEFAULT_FEE,
+                        refunded: 0,
+                        penalty: PENALTY,
+                        slashed: true,
+                    })
+                ),
+            ]
+        );
+    });
+}
+
+#[test]
+fn quote_order() {
+    new_test_ext().execute_with(|| {
+        assert_some!(aux_register_device(DEV1, true));
+        assert_some!(aux_register_device(DEV2, false));
+        assert_eq!(ServiceModule::ready_devices(), vec![account(DEV1)]);
+
+        let now = crate::Timestamp::<Test>::now();
+        let mut order = OutOrder {
+            until: now + DEFAULT_WCD,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: account(DEV1),
+        };
+        let quote = assert_some!(ServiceModule::quote_order(&order, 7));
+        assert_eq!(quote.fee, DEFAULT_FEE);
+        assert_eq!(quote.penalty, PENALTY);
+        assert_eq!(quote.dispatch_fee, 7);
+        assert_eq!(quote.min_until, now + DEFAULT_WCD);
+        assert!(quote.available);
+
+        order.until = now + DEFAULT_WCD - 1;
+        assert!(!assert_some!(ServiceModule::quote_order(&order, 7)).available);
+
+        order.device = account(DEV3);
+        assert!(ServiceModule::quote_order(&order, 7).is_none());
+
+        assert_ok!(aux_order(DEV1, CL1, DEFAULT_FEE, DEFAULT_WCD));
+        assert!(ServiceModule::ready_devices().is_empty());
+
+        let info = assert_some!(ServiceModule::device_info(&account(DEV1)));
+        assert_eq!(info.state, DeviceState::Busy);
+        assert_eq!(info.paraid, None);
+        let order = assert_some!(ServiceModule::order_info(&account(DEV1)));
+        assert_eq!(order.client, account(CL1));
+        assert_eq!(order.paraid, 0);
+    });
+}
+
+#[test]
+fn order_archive() {
+    use crate::{OrderOutcome, Settlement};
+    use sp_core::H256;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let devid = account(DEV1);
+
+        let _order = aux_init_order(DEV1, CL1, DEFAULT_WCD * 10);
+        crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 1);
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+        crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 2);
+        let result = H256::repeat_byte(7);
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            true,
+            Some(result)
+        ));
+
+        let record = assert_some!(ServiceModule::archived_order((LOCAL.into(), 0)));
+        assert_eq!(record.client, account(CL1));
+        assert_eq!(record.device, devid);
+        assert_eq!(record.received, 0);
+        assert_eq!(record.accepted, Some(DEFAULT_WCD));
+        assert_eq!(record.closed, DEFAULT_WCD * 2);
+        assert_eq!(record.outcome, OrderOutcome::Done);
+        assert_eq!(record.result, Some(result));
+        assert_eq!(
+            record.settlement,
+            Some(Settlement {
+                paid: DEFAULT_FEE,
+                refunded: 0,
+                penalty: PENALTY,
+                slashed: false,
+            })
+        );
+        assert_eq!(record.archived_at, 1);
+
+        assert_ok!(aux_order(DEV1, CL2, DEFAULT_FEE, DEFAULT_WCD * 2));
+        crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 5);
+        assert_ok!(ServiceModule::cancel(
+            Origin::signed(account(CL2)),
+            devid.clone()
+        ));
+
+        let record = assert_some!(ServiceModule::archived_order((LOCAL.into(), 1)));
+        assert_eq!(record.outcome, OrderOutcome::Cancelled);
+        assert_eq!(record.accepted, None);
+        assert_eq!(record.result, None);
+
+        assert_eq!(
+            ServiceModule::device_history(&devid),
+            vec![(LOCAL.into(), 0), (LOCAL.into(), 1)]
+        );
+        assert_eq!(
+            ServiceModule::client_history(&account(CL1)),
+            vec![(LOCAL.into(), 0)]
+        );
+        assert_eq!(
+            ServiceModule::client_history(&account(CL2)),
+            vec![(LOCAL.into(), 1)]
+        );
+    });
+}
+
+#[test]
+fn archive_pruning() {
+    use frame_support::{traits::Hooks, weights::Weight};
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let devid = account(DEV1);
+
+        let _order = aux_init_order(DEV1, CL1, DEFAULT_WCD * 10);
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            true,
+            None
+        ));
+        assert_some!(ServiceModule::archived_order((LOCAL.into(), 0)));
+
+        // Retention is not over yet
+        ServiceModule::on_idle(10, Weight::max_value());
+        assert_some!(ServiceModule::archived_order((LOCAL.into(), 0)));
+
+        // Not enough weight to prune anything
+        ServiceModule::on_idle(11, 0);
+        assert_some!(ServiceModule::archived_order((LOCAL.into(), 0)));
+
+        ServiceModule::on_idle(11, Weight::max_value());
+        assert!(ServiceModule::archived_order((LOCAL.into(), 0)).is_none());
+        assert!(ServiceModule::device_history(&devid).is_empty());
+        assert!(ServiceModule::client_history(&account(CL1)).is_empty());
+    });
+}
+
+#[test]
+fn order_amend() {
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let b1 = aux_free_balance(CL1);
+
+        let order = aux_init_order(DEV1, CL1, DEFAULT_WCD * 10).unwrap();
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+
+        let amendment = crate::AmendmentOf::<Test> {
+            until: order.until + DEFAULT_WCD,
+            until_relay: None,
+            fee: DEFAULT_FEE * 2,
+        };
+        assert_err!(
+            ServiceModule::amend_order(
+                Origin::signed(account(CL2)),
+                devid.clone(),
+                amendment.clone()
+            ),
+            Error::<Test>::Prohibited
+        );
+        assert_err!(
+            ServiceModule::amend_order(
+                Origin::signed(account(CL1)),
+                devid.clone(),
+                crate::AmendmentOf::<Test> {
+                    until: order.until - 1,
+                    ..amendment.clone()
+                }
+            ),
+            Error::<Test>::BadOrderDetails
+        );
+        assert_ok!(ServiceModule::amend_order(
+            Origin::signed(account(CL1)),
+            devid.clone(),
+            amendment.clone()
+        ));
+        assert_eq!(b1 - aux_free_balance(CL1), DEFAULT_FEE * 2);
+        // Not binding until the device accepts it
+        assert_eq!(
+            assert_some!(ServiceModule::orders(&devid)).until,
+            order.until
+        );
+
+        assert_ok!(ServiceModule::accept_amendment(Origin::signed(
+            devid.clone()
+        )));
+        let amended = assert_some!(ServiceModule::orders(&devid));
+        assert_eq!(amended.until, amendment.until);
+        assert_eq!(amended.fee, DEFAULT_FEE * 2);
+        assert_err!(
+            ServiceModule::accept_amendment(Origin::signed(devid.clone())),
+            Error::<Test>::NoAmendment
+        );
+
+        crate::Timestamp::<Test>::set_timestamp(order.until);
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            true,
+            None
+        ));
+        assert_eq!(b1 - aux_total_balance(CL1), DEFAULT_FEE * 2);
+    });
+}
+
+#[test]
+fn pending_amendment_released_on_close() {
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let b1 = aux_free_balance(CL1);
+
+        let order = aux_init_order(DEV1, CL1, DEFAULT_WCD * 10).unwrap();
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+        assert_ok!(ServiceModule::amend_order(
+            Origin::signed(account(CL1)),
+            devid.clone(),
+            crate::AmendmentOf::<Test> {
+                until: order.until,
+                until_relay: None,
+                fee: DEFAULT_FEE * 3,
+            }
+        ));
+
+        assert_ok!(ServiceModule::done(
+            Origin::signed(devid.clone()),
+            true,
+            None
+        ));
+        assert!(ServiceModule::amendments(&devid).is_none());
+        assert_eq!(b1 - aux_free_balance(CL1), DEFAULT_FEE);
+        assert_eq!(b1 - aux_total_balance(CL1), DEFAULT_FEE);
+    });
+}
+
+#[test]
+fn access_policy() {
+    use crate::AccessPolicyOf;
+
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        assert_err!(
+            ServiceModule::set_policy(Origin::signed(devid.clone()), AccessPolicyOf::<Test>::Open),
+            Error::<Test>::NoDevice
+        );
+        assert_some!(aux_register_device(DEV1, true));
+
+        let entries = vec![
+            (0.into(), account(CL1)),
+            (1.into(), account(CL1)),
+            (0.into(), account(CL3)),
+        ];
+        assert_err!(
+            ServiceModule::set_policy(
+                Origin::signed(devid.clone()),
+                AccessPolicyOf::<Test>::Allow(entries)
+            ),
+            Error::<Test>::TooManyEntries
+        );
+
+        assert_ok!(ServiceModule::set_policy(
+            Origin::signed(devid.clone()),
+            AccessPolicyOf::<Test>::Allow(vec![(0.into(), account(CL1))])
+        ));
+        assert_err!(
+            aux_order(DEV1, CL2, DEFAULT_FEE, DEFAULT_WCD * 10),
+            Error::<Test>::NotAllowed
+        );
+        assert_eq!(aux_free_balance(CL2), aux_total_balance(CL2));
+        assert_ok!(aux_order(DEV1, CL1, DEFAULT_FEE, DEFAULT_WCD * 10));
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            true,
+            true
+        ));
+
+        assert_ok!(ServiceModule::set_policy(
+            Origin::signed(devid.clone()),
+            AccessPolicyOf::<Test>::Deny(vec![(0.into(), account(CL1))])
+        ));
+        assert_err!(
+            aux_order(DEV1, CL1, DEFAULT_FEE, DEFAULT_WCD * 10),
+            Error::<Test>::NotAllowed
+        );
+        assert_ok!(aux_order(DEV1, CL2, DEFAULT_FEE, DEFAULT_WCD * 10));
+
+        assert_ok!(ServiceModule::set_policy(
+            Origin::signed(devid.clone()),
+            AccessPolicyOf::<Test>::Open
+        ));
+        assert_eq!(
+            ServiceModule::policies(&devid),
+            AccessPolicyOf::<Test>::Open
+        );
+    });
+}
+
+#[test]
+fn paused_para_refuses_orders() {
+    use codec::Encode;
+    use cumulus_primitives_core::XcmpMessageHandler;
+    use pallet_common::PauseTarget;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let devid = account(DEV1);
+        assert_some!(aux_register_device(DEV1, true));
+
+        assert_noop!(
+            ServiceModule::set_pause(
+                Origin::signed(account(CL1)),
+                PauseTarget::Para(1000.into()),
+                true
+            ),
+            DispatchError::BadOrigin
+        );
+        assert_ok!(ServiceModule::set_pause(
+            Origin::root(),
+            PauseTarget::Para(1000.into()),
+            true
+        ));
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(0, account(CL1), order);
+        ServiceModule::handle_blob_message(1000.into(), 0, msg.encode());
+        assert!(ServiceModule::orders(&devid).is_none());
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_service(crate::Event::OrderFailed(
+                0,
+                account(CL1),
+                devid.clone(),
+                1000.into()
+            ))));
+
+        // Other parachains are not affected
+        ServiceModule::handle_blob_message(1001.into(), 0, msg.encode());
+        assert_some!(ServiceModule::orders(&devid));
+    });
+}
+
+#[test]
+fn paused_messages_replayed() {
+    use codec::Encode;
+    use cumulus_primitives_core::XcmpMessageHandler;
+    use frame_support::{traits::Hooks, weights::Weight};
+    use pallet_common::{MessageKind, PauseTarget};
+
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        let client = account(CL1);
+        assert_some!(aux_register_device(DEV1, true));
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(0, client.clone(), order);
+        ServiceModule::handle_blob_message(1000.into(), 0, msg.encode());
+        assert_some!(ServiceModule::orders(&devid));
+
+        let target = PauseTarget::Kind(MessageKind::OrderCancel);
+        assert_ok!(ServiceModule::set_pause(Origin::root(), target, true));
+        let cancel = crate::XCMPMessageOf::<Test>::OrderCancel(0, client.clone(), devid.clone());
+        for _ in 0..3 {
+            ServiceModule::handle_blob_message(1000.into(), 0, cancel.encode());
+        }
+        assert_some!(ServiceModule::orders(&devid));
+        // The third message overflows the queue
+        assert_eq!(crate::PausedMessagesBounds::<Test>::get(), (0, 2));
+
+        ServiceModule::on_idle(1, Weight::max_value());
+        assert_some!(ServiceModule::orders(&devid));
+
+        assert_ok!(ServiceModule::set_pause(Origin::root(), target, false));
+        ServiceModule::on_idle(1, Weight::max_value());
+        assert!(ServiceModule::orders(&devid).is_none());
+        assert_eq!(crate::PausedMessagesBounds::<Test>::get(), (2, 2));
+    });
+}
+
+#[test]
+fn emergency_pause_blocks_orders() {
+    use pallet_common::PauseTarget;
+
+    new_test_ext().execute_with(|| {
+        assert_some!(aux_register_device(DEV1, true));
+        assert_ok!(ServiceModule::set_pause(
+            Origin::root(),
+            PauseTarget::All,
+            true
+        ));
+        assert_noop!(
+            aux_order(DEV1, CL1, DEFAULT_FEE, DEFAULT_WCD * 10),
+            Error::<Test>::Paused
+        );
+
+        assert_ok!(ServiceModule::set_pause(
+            Origin::root(),
+            PauseTarget::All,
+            false
+        ));
+        assert_ok!(aux_order(DEV1, CL1, DEFAULT_FEE, DEFAULT_WCD * 10));
+    });
+}
+
+#[test]
+fn enveloped_messages() {
+    use codec::Encode;
+    use cumulus_primitives_core::XcmpMessageHandler;
+    use pallet_common::{
+        Envelope, MessageKind, MESSAGE_MAGIC, PROTOCOL_V0, PROTOCOL_V1, PROTOCOL_V2,
+        PROTOCOL_VERSION,
+    };
+
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        assert_some!(aux_register_device(DEV1, true));
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(0, account(CL1), order);
+        assert_eq!(msg.to_wire(PROTOCOL_V0, None), msg.encode());
+
+        // Unknown versions and mislabeled payloads are refused
+        let future = Envelope {
+            magic: MESSAGE_MAGIC,
+            version: PROTOCOL_VERSION + 1,
+            kind: MessageKind::NewOrder,
+            payload: msg.encode(),
+        };
+        ServiceModule::handle_blob_message(1000.into(), 0, future.encode());
+        let mislabeled = Envelope {
+            version: PROTOCOL_VERSION,
+            kind: MessageKind::OrderCancel,
+            payload: (None::<u64>, &msg).encode(),
+            ..future
+        };
+        ServiceModule::handle_blob_message(1000.into(), 0, mislabeled.encode());
+        let unversioned = Envelope {
+            version: PROTOCOL_V0,
+            ..mislabeled
+        };
+        assert!(crate::XCMPMessageOf::<Test>::from_wire(&unversioned.encode()).is_err());
+        assert!(ServiceModule::orders(&devid).is_none());
+
+        // Versions without acknowledgements carry no nonce
+        let ack = crate::XCMPMessageOf::<Test>::HandshakeAck(PROTOCOL_V1);
+        assert_eq!(
+            crate::XCMPMessageOf::<Test>::from_wire(&ack.to_wire(PROTOCOL_V1, Some(3)))
+                .map(|(version, nonce, _)| (version, nonce)),
+            Ok((PROTOCOL_V1, None))
+        );
+        // Legacy order messages carry no order id and are refused
+        let legacy = msg.to_wire(PROTOCOL_V2, Some(3));
+        assert_ne!(legacy, msg.to_wire(PROTOCOL_VERSION, Some(3)));
+        assert!(crate::XCMPMessageOf::<Test>::from_wire(&legacy).is_err());
+        ServiceModule::handle_blob_message(1000.into(), 0, legacy);
+        assert!(ServiceModule::orders(&devid).is_none());
+
+        let blob = msg.to_wire(PROTOCOL_VERSION, Some(3));
+        assert_eq!(
+            crate::XCMPMessageOf::<Test>::from_wire(&blob)
+                .map(|(version, nonce, _)| (version, nonce)),
+            Ok((PROTOCOL_VERSION, Some(3)))
+        );
+        ServiceModule::handle_blob_message(1000.into(), 0, blob);
+        assert_some!(ServiceModule::orders(&devid));
+    });
+}
+
+#[test]
+fn handshake() {
+    use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+    use pallet_common::PROTOCOL_VERSION;
+
+    new_test_ext().execute_with(|| {
+        assert_eq!(ServiceModule::protocol_version(ParaId::from(1000)), None);
+
+        let blob = crate::XCMPMessageOf::<Test>::Handshake(PROTOCOL_VERSION + 5)
+            .to_wire(PROTOCOL_VERSION, None);
+        ServiceModule::handle_blob_message(1000.into(), 0, blob);
+        assert_eq!(
+            ServiceModule::protocol_version(ParaId::from(1000)),
+            Some(PROTOCOL_VERSION)
+        );
+
+        let blob = crate::XCMPMessageOf::<Test>::HandshakeAck(PROTOCOL_VERSION + 1)
+            .to_wire(PROTOCOL_VERSION, None);
+        ServiceModule::handle_blob_message(1001.into(), 0, blob);
+        assert_eq!(ServiceModule::protocol_version(ParaId::from(1001)), None);
+    });
+}
+
+#[test]
+fn undelivered_messages_retried() {
+    use cumulus_primitives_core::ParaId;
+    use frame_support::{traits::Hooks, weights::Weight};
+    use pallet_common::MessageKind;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        close_channel(true);
+        assert_ok!(ServiceModule::handshake(Origin::root(), dest));
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_service(crate::Event::MessageDeferred(dest, MessageKind::Handshake))));
+
+        // Later messages wait behind the deferred one
+        close_channel(false);
+        assert_ok!(ServiceModule::handshake(Origin::root(), dest));
+        assert!(sent_messages().is_empty());
+        assert_eq!(crate::OutboxBounds::<Test>::get(dest), (0, 2));
+
+        // Not due until the backoff expires
+        ServiceModule::on_idle(2, Weight::max_value());
+        assert!(sent_messages().is_empty());
+
+        ServiceModule::on_idle(3, Weight::max_value());
+        assert_eq!(sent_messages().len(), 2);
+        assert!(!crate::OutboxBounds::<Test>::contains_key(dest));
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_service(crate::Event::MessageDelivered(
+                dest,
+                MessageKind::Handshake
+            ))));
+
+        // Sent directly once the outbox is drained
+        assert_ok!(ServiceModule::handshake(Origin::root(), dest));
+        assert_eq!(sent_messages().len(), 3);
+    });
+}
+
+#[test]
+fn undelivered_messages_abandoned() {
+    use cumulus_primitives_core::ParaId;
+    use frame_support::{traits::Hooks, weights::Weight};
+    use pallet_common::MessageKind;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        close_channel(true);
+        assert_ok!(ServiceModule::handshake(Origin::root(), dest));
+
+        // Second attempt at block 3, third one after a doubled backoff
+        ServiceModule::on_idle(3, Weight::max_value());
+        assert_eq!(crate::Outbox::<Test>::get(dest, 0).unwrap().attempts, 2);
+        ServiceModule::on_idle(6, Weight::max_value());
+        assert_some!(crate::Outbox::<Test>::get(dest, 0));
+
+        ServiceModule::on_idle(7, Weight::max_value());
+        assert!(crate::Outbox::<Test>::get(dest, 0).is_none());
+        assert!(!crate::OutboxBounds::<Test>::contains_key(dest));
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_service(crate::Event::MessageAbandoned(
+                dest,
+                MessageKind::Handshake,
+                3
+            ))));
+        assert!(sent_messages().is_empty());
+    });
+}
+
+#[test]
+fn handshake_needs_root() {
+    use cumulus_primitives_core::ParaId;
+
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            ServiceModule::handshake(Origin::signed(account(CL1)), ParaId::from(1000)),
+            DispatchError::BadOrigin
+        );
+    });
+}
+
+#[test]
+fn full_outbox_refuses_messages() {
+    use cumulus_primitives_core::ParaId;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        close_channel(true);
+        assert_ok!(ServiceModule::handshake(Origin::root(), dest));
+        assert_ok!(ServiceModule::handshake(Origin::root(), dest));
+        assert_noop!(
+            ServiceModule::handshake(Origin::root(), dest),
+            Error::<Test>::OutboxFull
+        );
+        // Other destinations are not affected
+        assert_ok!(ServiceModule::handshake(Origin::root(), ParaId::from(1001)));
+    });
+}
+
+#[test]
+fn outbox_destinations_take_turns() {
+    use crate::WeightInfo;
+    use cumulus_primitives_core::ParaId;
+    use frame_support::traits::Hooks;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        close_channel(true);
+        assert_ok!(ServiceModule::handshake(Origin::root(), ParaId::from(1000)));
+        assert_ok!(ServiceModule::handshake(Origin::root(), ParaId::from(1000)));
+        assert_ok!(ServiceModule::handshake(Origin::root(), ParaId::from(1001)));
+        close_channel(false);
+
+        // One message per round, the cursor moves on to the next destination
+        let limit = <() as WeightInfo>::retry_message();
+        for _ in 0..3 {
+            ServiceModule::on_idle(3, limit);
+        }
+        let dests: Vec<_> = sent_messages().into_iter().map(|(dest, _)| dest).collect();
+        assert_eq!(
+            dests,
+            vec![ParaId::from(1000), ParaId::from(1001), ParaId::from(1000)]
+        );
+        assert!(crate::OutboxDestinations::<Test>::get().is_empty());
+    });
+}
+
+#[test]
+fn acknowledged_messages() {
+    use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+    use frame_support::{traits::Hooks, weights::Weight};
+    use pallet_common::{MessageKind, PROTOCOL_VERSION};
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        let devid = account(DEV1);
+        assert_some!(aux_register_device(DEV1, true));
+        let blob = crate::XCMPMessageOf::<Test>::HandshakeAck(PROTOCOL_VERSION)
+            .to_wire(PROTOCOL_VERSION, None);
+        ServiceModule::handle_blob_message(dest, 0, blob);
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(0, account(CL1), order);
+        let ack = |msg: crate::XCMPMessageOf<Test>| (dest, msg.to_wire(PROTOCOL_VERSION, None));
+
+        // Processed messages are acknowledged, refused ones are not
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(0)));
+        assert_some!(ServiceModule::orders(&devid));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Ack(0)))
+        );
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(1)));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Nack(1)))
+        );
+
+        // Order messages to the parachain wait for its acknowledgement
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+        let pending = assert_some!(ServiceModule::unacked(dest, 0));
+        assert_eq!(pending.kind, MessageKind::OrderAccept);
+
+        ServiceModule::on_idle(5, Weight::max_value());
+        assert_eq!(ServiceModule::unacked(dest, 0).unwrap().resends, 0);
+        ServiceModule::on_idle(6, Weight::max_value());
+        assert_eq!(sent_messages().last(), Some(&(dest, pending.blob)));
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_service(crate::Event::MessageUnacknowledged(
+                dest,
+                0,
+                MessageKind::OrderAccept,
+                1
+            ))));
+
+        let blob = crate::XCMPMessageOf::<Test>::Ack(0).to_wire(PROTOCOL_VERSION, None);
+        ServiceModule::handle_blob_message(dest, 0, blob);
+        assert!(ServiceModule::unacked(dest, 0).is_none());
+        assert!(System::events().iter().any(
+            |record| record.event == Event::pallet_service(crate::Event::MessageAcked(dest, 0))
+        ));
+    });
+}
+
+#[test]
+fn unacked_messages_given_up() {
+    use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+    use frame_support::{traits::Hooks, weights::Weight};
+    use pallet_common::{MessageKind, PROTOCOL_VERSION};
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        let devid = account(DEV1);
+        assert_some!(aux_register_device(DEV1, true));
+        let blob = crate::XCMPMessageOf::<Test>::HandshakeAck(PROTOCOL_VERSION)
+            .to_wire(PROTOCOL_VERSION, None);
+        ServiceModule::handle_blob_message(dest, 0, blob);
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(0, account(CL1), order);
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(0)));
+
+        // Deferred messages are delivered from the outbox, not appended again
+        close_channel(true);
+        assert_ok!(ServiceModule::accept(Origin::signed(devid), false, true));
+        ServiceModule::on_idle(6, Weight::max_value());
+        assert_eq!(crate::OutboxBounds::<Test>::get(dest), (0, 1));
+        assert_eq!(ServiceModule::unacked(dest, 0).unwrap().resends, 0);
+
+        close_channel(false);
+        ServiceModule::on_idle(11, Weight::max_value());
+        assert!(!crate::OutboxBounds::<Test>::contains_key(dest));
+        ServiceModule::on_idle(16, Weight::max_value());
+        ServiceModule::on_idle(21, Weight::max_value());
+        assert_eq!(ServiceModule::unacked(dest, 0).unwrap().resends, 3);
+
+        ServiceModule::on_idle(26, Weight::max_value());
+        assert!(ServiceModule::unacked(dest, 0).is_none());
+        assert!(crate::UnackedDestinations::<Test>::get().is_empty());
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_service(crate::Event::UnackedAbandoned(
+                dest,
+                0,
+                MessageKind::OrderAccept
+            ))));
+    });
+}
+
+#[test]
+fn unacked_messages_bounded() {
+    use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+    use frame_support::weights::Weight;
+    use pallet_common::{MessageKind, PROTOCOL_VERSION};
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        assert_some!(aux_register_device(DEV1, true));
+        let blob = crate::XCMPMessageOf::<Test>::HandshakeAck(PROTOCOL_VERSION)
+            .to_wire(PROTOCOL_VERSION, None);
+        ServiceModule::handle_blob_message(dest, 0, blob);
+        assert_ok!(ServiceModule::set_subscriber(Origin::root(), dest, true));
+        ServiceModule::send_snapshots(Weight::max_value());
+
+        for id in [DEV2, DEV3, 300].iter() {
+            assert_some!(aux_register_device(*id, true));
+        }
+        ServiceModule::push_changes(Weight::max_value());
+        assert_eq!(crate::UnackedCount::<Test>::get(dest), 4);
+
+        // Registry changes beyond the bound are dropped, the subscriber resyncs on the gap
+        assert_some!(aux_register_device(301, true));
+        ServiceModule::push_changes(Weight::max_value());
+        assert_eq!(crate::UnackedCount::<Test>::get(dest), 4);
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_service(crate::Event::MessageRefused(
+                dest,
+                MessageKind::DeviceAnnounce
+            ))));
+    });
+}
+
+#[test]
+fn duplicate_messages_dropped() {
+    use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+    use pallet_common::{SeqWindow, PROTOCOL_VERSION};
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        let devid = account(DEV1);
+        assert_some!(aux_register_device(DEV1, true));
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(0, account(CL1), order);
+        let ack = |msg: crate::XCMPMessageOf<Test>| (dest, msg.to_wire(PROTOCOL_VERSION, None));
+        let events = || System::events().into_iter().map(|record| record.event);
+
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(0)));
+        assert_some!(ServiceModule::orders(&devid));
+
+        // Replayed message is acknowledged again without processing
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(0)));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Ack(0)))
+        );
+        assert!(events()
+            .any(|event| event == Event::pallet_service(crate::Event::DuplicateDropped(dest, 0))));
+
+        let cancel = crate::XCMPMessageOf::<Test>::OrderCancel(0, account(CL1), devid.clone());
+        ServiceModule::handle_blob_message(dest, 0, cancel.to_wire(PROTOCOL_VERSION, Some(2)));
+        assert!(ServiceModule::orders(&devid).is_none());
+        assert!(events()
+            .any(|event| event == Event::pallet_service(crate::Event::SequenceGap(dest, 1, 2))));
+        assert_eq!(
+            ServiceModule::inbound_sequence(dest),
+            SeqWindow {
+                watermark: 1,
+                seen: 1
+            }
+        );
+
+        // Filling the gap moves the watermark past the processed nonces
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(1)));
+        assert_some!(ServiceModule::orders(&devid));
+        assert_eq!(ServiceModule::inbound_sequence(dest).watermark, 3);
+
+        // Duplicates of refused messages are refused again
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(3)));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Nack(3)))
+        );
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(3)));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Nack(3)))
+        );
+
+        // Nonces beyond the window are dropped and refused
+        let far = 4 + SeqWindow::SIZE + 1;
+        ServiceModule::handle_blob_message(dest, 0, cancel.to_wire(PROTOCOL_VERSION, Some(far)));
+        assert_some!(ServiceModule::orders(&devid));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Nack(far)))
+        );
+        assert!(events()
+            .any(|event| event == Event::pallet_service(crate::Event::SequenceGap(dest, 4, far))));
+    });
+}
+
+#[test]
+fn sibling_order() {
+    use cumulus_primitives_core::ParaId;
+    use frame_support::traits::ReservableCurrency;
+    use pallet_common::PauseTarget;
+    use sp_runtime::traits::Convert;
+
+    new_test_ext().execute_with(|| {
+        let devid = account(DEV1);
+        assert_some!(aux_register_device(DEV1, true));
+        let sovereign = SiblingAccount::convert(ParaId::from(1000));
+        Balances::make_free_balance_be(&sovereign, 100_000_000);
+        let sibling = Origin::from(cumulus_pallet_xcm_handler::Origin::SiblingParachain(
+            ParaId::from(1000),
+        ));
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        assert_noop!(
+            ServiceModule::sibling_order(Origin::signed(account(CL1)), order.clone()),
+            DispatchError::BadOrigin
+        );
+
+        // Paused parachain can't place orders through XCM either
+        assert_ok!(ServiceModule::set_pause(
+            Origin::root(),
+            PauseTarget::Para(1000.into()),
+            true
+        ));
+        assert_noop!(
+            ServiceModule::sibling_order(sibling.clone(), order.clone()),
+            Error::<Test>::Paused
+        );
+        assert_ok!(ServiceModule::set_pause(
+            Origin::root(),
+            PauseTarget::Para(1000.into()),
+            false
+        ));
+        assert_ok!(ServiceModule::sibling_order(sibling.clone(), order));
+
+        // Settled locally by the sovereign account of the parachain
+        let placed = assert_some!(ServiceModule::orders(&devid));
+        assert_eq!(placed.client, sovereign);
+        assert_eq!(placed.paraid, ParaId::from(1000));
+        assert_eq!(Balances::reserved_balance(&sovereign), DEFAULT_FEE);
+
+        assert_noop!(
+            ServiceModule::cancel(Origin::signed(account(CL1)), devid.clone()),
+            Error::<Test>::Prohibited
+        );
+        assert_ok!(ServiceModule::sibling_cancel(sibling, devid.clone()));
+        assert!(ServiceModule::orders(&devid).is_none());
+        assert_eq!(Balances::reserved_balance(&sovereign), 0);
+    });
+}
+
+#[test]
+fn epoch_reconciliation() {
+    use crate::{LedgerEntryOf, SettlementMode};
+    use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+    use pallet_common::PROTOCOL_VERSION;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        let devid = account(DEV1);
+        assert_some!(aux_register_device(DEV1, true));
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let complete = |nonce| {
+            let msg = crate::XCMPMessageOf::<Test>::NewOrder(nonce, account(CL1), order.clone());
+            ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(nonce)));
+            assert_ok!(ServiceModule::accept(
+                Origin::signed(devid.clone()),
+                false,
+                true
+            ));
+            assert_ok!(ServiceModule::done(
+                Origin::signed(devid.clone()),
+                true,
+                None
+            ));
+        };
+
+        // Fees paid on completion are not recorded
+        complete(0);
+        assert_eq!(ServiceModule::ledger(dest, &devid), Default::default());
+
+        let netting = crate::XCMPMessageOf::<Test>::SettlementChanged(SettlementMode::Netting);
+        ServiceModule::handle_blob_message(dest, 0, netting.to_wire(PROTOCOL_VERSION, Some(1)));
+        assert_eq!(
+            ServiceModule::settlement_mode(dest),
+            SettlementMode::Netting
+        );
+        complete(2);
+        let recorded = LedgerEntryOf::<Test> {
+            fees: DEFAULT_FEE,
+            penalties: PENALTY,
+        };
+        assert_eq!(ServiceModule::ledger(dest, &devid), recorded);
+
+        let ack = |msg: crate::XCMPMessageOf<Test>| (dest, msg.to_wire(PROTOCOL_VERSION, None));
+        let events = || System::events().into_iter().map(|record| record.event);
+
+        // Settling more than the completed orders is refused
+        let reconcile =
+            crate::XCMPMessageOf::<Test>::Reconcile(0, vec![(devid.clone(), DEFAULT_FEE + 1, 0)]);
+        ServiceModule::handle_blob_message(dest, 0, reconcile.to_wire(PROTOCOL_VERSION, Some(3)));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Nack(3)))
+        );
+        assert!(events().any(|event| event
+            == Event::pallet_service(crate::Event::ReconciliationMismatch(
+                dest,
+                0,
+                devid.clone(),
+                DEFAULT_FEE + 1,
+                DEFAULT_FEE
+            ))));
+        assert_eq!(ServiceModule::ledger(dest, &devid), recorded);
+
+        // So is netting more than the collateral of the closed orders
+        let reconcile = crate::XCMPMessageOf::<Test>::Reconcile(
+            0,
+            vec![(devid.clone(), DEFAULT_FEE, PENALTY + 1)],
+        );
+        ServiceModule::handle_blob_message(dest, 0, reconcile.to_wire(PROTOCOL_VERSION, Some(4)));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Nack(4)))
+        );
+        assert!(events().any(|event| event
+            == Event::pallet_service(crate::Event::PenaltyMismatch(
+                dest,
+                0,
+                devid.clone(),
+                PENALTY + 1,
+                PENALTY
+            ))));
+        assert_eq!(ServiceModule::ledger(dest, &devid), recorded);
+
+        // Entries of the same device are added up
+        let reconcile = crate::XCMPMessageOf::<Test>::Reconcile(
+            0,
+            vec![
+                (devid.clone(), DEFAULT_FEE, 0),
+                (devid.clone(), DEFAULT_FEE, 0),
+            ],
+        );
+        ServiceModule::handle_blob_message(dest, 0, reconcile.to_wire(PROTOCOL_VERSION, Some(5)));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Nack(5)))
+        );
+        assert!(events().any(|event| event
+            == Event::pallet_service(crate::Event::ReconciliationMismatch(
+                dest,
+                0,
+                devid.clone(),
+                DEFAULT_FEE * 2,
+                DEFAULT_FEE
+            ))));
+        assert_eq!(ServiceModule::ledger(dest, &devid), recorded);
+
+        let reconcile = crate::XCMPMessageOf::<Test>::Reconcile(
+            1,
+            vec![
+                (devid.clone(), DEFAULT_FEE / 2, 0),
+                (devid.clone(), DEFAULT_FEE / 2, PENALTY),
+            ],
+        );
+        ServiceModule::handle_blob_message(dest, 0, reconcile.to_wire(PROTOCOL_VERSION, Some(6)));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&ack(crate::XCMPMessageOf::<Test>::Ack(6)))
+        );
+        assert!(
+            events().any(|event| event == Event::pallet_service(crate::Event::Reconciled(dest, 1)))
+        );
+        assert_eq!(ServiceModule::ledger(dest, &devid), Default::default());
+    });
+}
+
+#[test]
+fn foreign_order_cancel_after_deadline() {
+    use codec::Encode;
+    use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+    use pallet_common::PROTOCOL_VERSION;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        let devid = account(DEV1);
+        let client = account(CL1);
+        assert_some!(aux_register_device(DEV1, true));
+        let blob = crate::XCMPMessageOf::<Test>::HandshakeAck(PROTOCOL_VERSION)
+            .to_wire(PROTOCOL_VERSION, None);
+        ServiceModule::handle_blob_message(dest, 0, blob);
+
+        let order = OutOrder {
+            until: DEFAULT_WCD * 10,
+            until_relay: None,
+            data: Vec::new(),
+            fee: DEFAULT_FEE,
+            device: devid.clone(),
+        };
+        let msg = crate::XCMPMessageOf::<Test>::NewOrder(0, client.clone(), order);
+        ServiceModule::handle_blob_message(dest, 0, msg.to_wire(PROTOCOL_VERSION, Some(0)));
+        assert_ok!(ServiceModule::accept(
+            Origin::signed(devid.clone()),
+            false,
+            true
+        ));
+        let cancel = crate::XCMPMessageOf::<Test>::OrderCancel(0, client.clone(), devid.clone());
+        let rejected =
+            crate::XCMPMessageOf::<Test>::OrderCancelRejected(0, client.clone(), devid.clone())
+                .encode();
+        let replies = || {
+            sent_messages()
+                .into_iter()
+                .filter_map(|(_, blob)| crate::XCMPMessageOf::<Test>::from_wire(&blob).ok())
+                .map(|(_, _, msg)| msg.encode())
+                .collect::<Vec<_>>()
+        };
+
+        // Accepted orders can't be withdrawn before the deadline
+        ServiceModule::handle_blob_message(dest, 0, cancel.to_wire(PROTOCOL_VERSION, Some(1)));
+        assert_some!(ServiceModule::orders(&devid));
+        assert!(replies().contains(&rejected));
+
+        // Overdue order is closed, the client parachain settles it on the confirmation
+        crate::Timestamp::<Test>::set_timestamp(DEFAULT_WCD * 11);
+        ServiceModule::handle_blob_message(dest, 0, cancel.to_wire(PROTOCOL_VERSION, Some(2)));
+        assert!(ServiceModule::orders(&devid).is_none());
+        let record = assert_some!(ServiceModule::archived_order((dest, 0)));
+        assert_eq!(record.outcome, crate::OrderOutcome::Cancelled);
+        assert_eq!(record.settlement, None);
+        let cancelled =
+            crate::XCMPMessageOf::<Test>::OrderCancelled(0, client.clone(), devid.clone()).encode();
+        assert!(replies().contains(&cancelled));
+        assert_eq!(
+            sent_messages().last(),
+            Some(&(
+                dest,
+                crate::XCMPMessageOf::<Test>::Ack(2).to_wire(PROTOCOL_VERSION, None)
+            ))
+        );
+
+        // Cancels of closed orders are rejected
+        ServiceModule::handle_blob_message(dest, 0, cancel.to_wire(PROTOCOL_VERSION, Some(3)));
+        assert_eq!(replies().iter().filter(|msg| **msg == rejected).count(), 2);
+        assert!(System::events().iter().any(|record| record.event
+            == Event::pallet_service(crate::Event::CancelRejected(
+                0,
+                client.clone(),
+                devid.clone(),
+                dest
+            ))));
+    });
+}
+
+#[test]
+fn device_sync_pushed_to_subscribers() {
+    use codec::Encode;
+    use cumulus_primitives_core::ParaId;
+    use frame_support::weights::Weight;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        let devid = account(DEV1);
+        let pushed = || {
+            sent_messages()
+                .into_iter()
+                .filter(|(para, _)| *para == dest)
+                .filter_map(|(_, blob)| crate::XCMPMessageOf::<Test>::from_wire(&blob).ok())
+                .map(|(_, _, msg)| msg.encode())
+                .collect::<Vec<_>>()
+        };
+
+        assert_noop!(
+            ServiceModule::set_subscriber(Origin::signed(account(CL1)), dest, true),
+            DispatchError::BadOrigin
+        );
+        assert_ok!(ServiceModule::set_subscriber(
+            RawOrigin::Root.into(),
+            dest,
+            true
+        ));
+        assert!(ServiceModule::subscribed(dest));
+        assert!(System::events().iter().any(
+            |r| r.event == Event::pallet_service(crate::Event::SubscriberApproved(dest, true))
+        ));
+        // Empty registry snapshot completes the subscription
+        ServiceModule::send_snapshots(Weight::max_value());
+        let snapshot = crate::XCMPMessageOf::<Test>::DeviceSnapshot(0, vec![]);
+        assert_eq!(pushed(), vec![snapshot.encode()]);
+
+        // Changes wait for the weight to push them to every subscriber
+        assert_some!(aux_register_device(DEV1, true));
+        assert_eq!(pushed(), vec![snapshot.encode()]);
+        ServiceModule::push_changes(Weight::max_value());
+        let announce = crate::XCMPMessageOf::<Test>::DeviceAnnounce(
+            0,
+            devid.clone(),
+            PENALTY,
+            DEFAULT_WCD,
+            DeviceState::Ready,
+        );
+        assert_eq!(pushed(), vec![snapshot.encode(), announce.encode()]);
+
+        // Profile changes of a registered device are pushed as updates
+        assert_ok!(ServiceModule::register(
+            Origin::signed(devid.clone()),
+            PENALTY * 2,
+            DEFAULT_WCD,
+            false
+        ));
+        assert_ok!(ServiceModule::set_state(
+            Origin::signed(devid.clone()),
+            true
+        ));
+        // Pushing a change weighs by the number of subscribers
+        let one = <() as crate::weights::WeightInfo>::push_change(1);
+        ServiceModule::push_changes(one);
+        assert_eq!(pushed().len(), 3);
+        ServiceModule::push_changes(Weight::max_value());
+        let update =
+            crate::XCMPMessageOf::<Test>::DeviceUpdate(1, devid.clone(), PENALTY * 2, DEFAULT_WCD);
+        let off =
+            crate::XCMPMessageOf::<Test>::DeviceStateChanged(2, devid.clone(), DeviceState::Off);
+        let on =
+            crate::XCMPMessageOf::<Test>::DeviceStateChanged(3, devid.clone(), DeviceState::Ready);
+        assert_eq!(
+            pushed(),
+            vec![
+                snapshot.encode(),
+                announce.encode(),
+                update.encode(),
+                off.encode(),
+                on.encode()
+            ]
+        );
+
+        // Access policies are pushed along with the profiles
+        assert_ok!(ServiceModule::set_policy(
+            Origin::signed(devid.clone()),
+            crate::AccessPolicyOf::<Test>::Allow(vec![(dest, account(CL1))])
+        ));
+        ServiceModule::push_changes(Weight::max_value());
+        let policy = crate::XCMPMessageOf::<Test>::DevicePolicy(
+            4,
+            devid.clone(),
+            pallet_common::AccessPolicy::Allow(vec![(1000, account(CL1))]),
+        );
+        assert_eq!(pushed().pop(), Some(policy.encode()));
+
+        assert_ok!(ServiceModule::set_subscriber(
+            RawOrigin::Root.into(),
+            dest,
+            false
+        ));
+        assert_ok!(ServiceModule::set_state(Origin::signed(devid), false));
+        ServiceModule::push_changes(Weight::max_value());
+        assert_eq!(pushed().len(), 6);
+        assert_eq!(ServiceModule::subscriber_count(), 0);
+    });
+}
+
+#[test]
+fn subscribe_sends_registry_snapshot() {
+    use crate::Event as ServiceEvent;
+    use codec::Encode;
+    use cumulus_primitives_core::ParaId;
+    use frame_support::weights::Weight;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        let pushed = || {
+            sent_messages()
+                .into_iter()
+                .filter(|(para, _)| *para == dest)
+                .filter_map(|(_, blob)| crate::XCMPMessageOf::<Test>::from_wire(&blob).ok())
+                .map(|(_, _, msg)| msg)
+                .collect::<Vec<_>>()
+        };
+        assert_some!(aux_register_device(DEV1, true));
+        assert_some!(aux_register_device(DEV2, true));
+        assert_some!(aux_register_device(DEV3, true));
+
+        let chunks = || {
+            pushed()
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    crate::XCMPMessageOf::<Test>::DeviceSnapshot(next, devices) => {
+                        Some((next, devices.len()))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Only parachains approved by governance subscribe
+        let subscribe = crate::XCMPMessageOf::<Test>::Subscribe;
+        ServiceModule::handle_blob_message(1000.into(), 0, subscribe.encode());
+        assert!(!ServiceModule::subscribed(dest));
+        assert_noop!(
+            ServiceModule::approve_subscriber(Origin::signed(account(CL1)), dest, true),
+            DispatchError::BadOrigin
+        );
+        assert_ok!(ServiceModule::approve_subscriber(
+            RawOrigin::Root.into(),
+            dest,
+            true
+        ));
+        ServiceModule::handle_blob_message(1000.into(), 0, subscribe.encode());
+        assert!(ServiceModule::subscribed(dest));
+        assert!(pushed().is_empty());
+
+        // Changes of devices the snapshot has yet to send reach the subscriber with it
+        assert_ok!(ServiceModule::set_state(
+            Origin::signed(account(DEV1)),
+            false
+        ));
+        ServiceModule::push_changes(Weight::max_value());
+        assert!(pushed().is_empty());
+
+        // Three devices are sent in chunks of two, as many chunks a block as the weight allows
+        let chunk = <() as crate::weights::WeightInfo>::send_snapshot(2);
+        ServiceModule::send_snapshots(chunk);
+        assert_eq!(chunks(), vec![(0, 2)]);
+
+        // Mid-snapshot, changes of devices already sent are pushed
+        let (first, state) = match pushed().pop() {
+            Some(crate::XCMPMessageOf::<Test>::DeviceSnapshot(_, devices)) => {
+                (devices[0].0.clone(), devices[0].3)
+            }
+            _ => panic!("snapshot chunk sent"),
+        };
+        let toggled = if state == DeviceState::Off {
+            DeviceState::Ready
+        } else {
+            DeviceState::Off
+        };
+        assert_ok!(ServiceModule::set_state(
+            Origin::signed(first.clone()),
+            toggled == DeviceState::Ready
+        ));
+        ServiceModule::push_changes(Weight::max_value());
+        let changed = crate::XCMPMessageOf::<Test>::DeviceStateChanged(0, first, toggled);
+        assert_eq!(pushed().pop().unwrap().encode(), changed.encode());
+
+        // Every chunk tells the sequence the changes follow from
+        ServiceModule::send_snapshots(chunk);
+        assert_eq!(chunks(), vec![(0, 2), (1, 1)]);
+        assert!(System::events()
+            .iter()
+            .any(|r| r.event == Event::pallet_service(ServiceEvent::SnapshotSent(dest, 3))));
+
+        // Changes follow the snapshot
+        assert_ok!(ServiceModule::set_state(
+            Origin::signed(account(DEV2)),
+            false
+        ));
+        ServiceModule::push_changes(Weight::max_value());
+        let off =
+            crate::XCMPMessageOf::<Test>::DeviceStateChanged(1, account(DEV2), DeviceState::Off);
+        assert_eq!(pushed().pop().unwrap().encode(), off.encode());
+
+        // Subscribing again starts a fresh snapshot at the current sequence once the interval
+        // since the last one passed, asking again meanwhile changes nothing
+        ServiceModule::handle_blob_message(1000.into(), 0, subscribe.encode());
+        ServiceModule::handle_blob_message(1000.into(), 0, subscribe.encode());
+        ServiceModule::send_snapshots(Weight::max_value());
+        assert_eq!(chunks().len(), 2);
+        System::set_block_number(6);
+        ServiceModule::send_snapshots(Weight::max_value());
+        assert_eq!(chunks(), vec![(0, 2), (1, 1), (2, 2), (2, 1)]);
+
+        // The number of subscribers is bounded
+        for para in [1001, 1002].iter() {
+            assert_ok!(ServiceModule::approve_subscriber(
+                RawOrigin::Root.into(),
+                (*para).into(),
+                true
+            ));
+            ServiceModule::handle_blob_message((*para).into(), 0, subscribe.encode());
+        }
+        assert!(ServiceModule::subscribed(ParaId::from(1001)));
+        assert!(!ServiceModule::subscribed(ParaId::from(1002)));
+        assert_eq!(ServiceModule::subscriber_count(), 2);
+
+        let unsubscribe = crate::XCMPMessageOf::<Test>::Unsubscribe;
+        ServiceModule::handle_blob_message(1000.into(), 0, unsubscribe.encode());
+        assert!(!ServiceModule::subscribed(dest));
+        // Revoked approval unsubscribes too
+        assert_ok!(ServiceModule::approve_subscriber(
+            RawOrigin::Root.into(),
+            1001.into(),
+            false
+        ));
+        assert_eq!(ServiceModule::subscriber_count(), 0);
+        let sent = pushed().len();
+        assert_ok!(ServiceModule::set_state(
+            Origin::signed(account(DEV2)),
+            true
+        ));
+        ServiceModule::push_changes(Weight::max_value());
+        assert_eq!(pushed().len(), sent);
+    });
+}
+
+#[test]
+fn fee_transfer_credits_device() {
+    use cumulus_primitives_core::{ParaId, XcmpMessageHandler};
+    use xcm::v0::{Junction, MultiAsset, MultiLocation, NetworkId, Order as XcmOrder, Xcm};
+    use xcm::VersionedXcm;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let id: [u8; 32] = account(DEV1).into();
+        let transfer = |currency: u32| {
+            VersionedXcm::V0(Xcm::ReserveAssetDeposit {
+                assets: vec![MultiAsset::ConcreteFungible {
+                    id: MultiLocation::X2(Junction::Parent, Junction::Parachain { id: currency }),
+                    amount: DEFAULT_FEE,
+                }],
+                effects: vec![XcmOrder::DepositAsset {
+                    assets: vec![MultiAsset::All],
+                    dest: MultiLocation::X1(Junction::AccountId32 {
+                        network: NetworkId::Polkadot,
+                        id,
+                    }),
+                }],
+            })
+        };
+        let before = Balances::free_balance(account(DEV1));
+        let failed = || {
+            System::events()
+                .iter()
+                .filter(|r| matches!(r.event, Event::pallet_service(crate::Event::XcmFail(..))))
+                .count()
+        };
+
+        // Currencies of client parachains are refused until governance accepts them
+        ServiceModule::handle_xcm_message(1000.into(), 0, transfer(1000));
+        assert_eq!(failed(), 1);
+        assert_noop!(
+            ServiceModule::set_fee_reserve(Origin::signed(account(DEV1)), 1000.into(), true),
+            DispatchError::BadOrigin
+        );
+        assert_ok!(ServiceModule::set_fee_reserve(
+            Origin::root(),
+            1000.into(),
+            true
+        ));
+        assert!(System::events().iter().any(|r| matches!(
+            r.event,
+            Event::pallet_service(crate::Event::FeeReserveChanged(_, true))
+        )));
+
+        // A client parachain is only the reserve of its own currency
+        ServiceModule::handle_xcm_message(1000.into(), 0, transfer(1001));
+        assert_eq!(failed(), 2);
+
+        // Deposits are kept apart from the native balance
+        ServiceModule::handle_xcm_message(1000.into(), 0, transfer(1000));
+        assert_eq!(Balances::free_balance(account(DEV1)), before);
+        assert_eq!(
+            ServiceModule::reserve_balance(ParaId::from(1000), account(DEV1)),
+            DEFAULT_FEE
+        );
+        assert_eq!(
+            ServiceModule::reserve_balance(ParaId::from(1001), account(DEV1)),
+            0
+        );
+        assert!(System::events()
+            .iter()
+            .any(|r| matches!(r.event, Event::pallet_service(crate::Event::XcmSuccess(..)))));
+    });
+}
+
+#[test]
+fn overweight_xcm_dropped() {
+    use cumulus_primitives_core::XcmpMessageHandler;
+    use xcm::v0::{Junction, MultiAsset, MultiLocation, NetworkId, Order as XcmOrder, Xcm};
+    use xcm::VersionedXcm;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let id: [u8; 32] = account(DEV1).into();
+        let before = Balances::free_balance(account(DEV1));
+        let deposit = XcmOrder::DepositAsset {
+            assets: vec![MultiAsset::All],
+            dest: MultiLocation::X1(Junction::AccountId32 {
+                network: NetworkId::Polkadot,
+                id,
+            }),
+        };
+        // Every instruction is weighed, the padding takes it over the limit
+        let mut effects = vec![deposit];
+        effects.resize(
+            (MaxXcmWeight::get() / XcmInstructionWeight::get()) as usize,
+            XcmOrder::Null,
+        );
+        let xcm = VersionedXcm::V0(Xcm::ReserveAssetDeposit {
+            assets: vec![MultiAsset::ConcreteFungible {
+                id: MultiLocation::X2(Junction::Parent, Junction::Parachain { id: 1000 }),
+                amount: DEFAULT_FEE,
+            }],
+            effects,
+        });
+
+        ServiceModule::handle_xcm_message(1000.into(), 0, xcm);
+        assert_eq!(Balances::free_balance(account(DEV1)), before);
+        assert!(System::events().iter().any(|r| matches!(
+            r.event,
+            Event::pallet_service(crate::Event::XcmOverweight(..))
+        )));
+    });
+}
+
+#[test]
+fn registry_changes_bounded() {
+    use crate::Event as ServiceEvent;
+    use cumulus_primitives_core::ParaId;
+    use frame_support::weights::Weight;
+
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let dest = ParaId::from(1000);
+        let chunks = || {
+            sent_messages()
+                .into_iter()
+                .filter(|(para, _)| *para == dest)
+                .filter_map(|(_, blob)| crate::XCMPMessageOf::<Test>::from_wire(&blob).ok())
+                .filter_map(|(_, _, msg)| match msg {
+                    crate::XCMPMessageOf::<Test>::DeviceSnapshot(next, devices) => {
+                        Some((next, devices.len()))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_ok!(ServiceModule::set_subscriber(
+            RawOrigin::Root.into(),
+            dest,
+            true
+        ));
+        ServiceModule::send_snapshots(Weight::max_value());
+        assert_eq!(chunks(), vec![(0, 0)]);
+
+        // Changes beyond the bound drop the queue, the subscriber gets a fresh snapshot instead
+        assert_some!(aux_register_device(DEV1, true));
+        for _ in 0..2 {
+            assert_ok!(ServiceModule::set_state(
+                Origin::signed(account(DEV1)),
+                false
+            ));
+            assert_ok!(ServiceModule::set_state(
+                Origin::signed(account(DEV1)),
+                true
+            ));
+        }
+        assert!(System::events()
+            .iter()
+            .any(|r| r.event == Event::pallet_service(ServiceEvent::RegistryChangesDropped(4))));
+        ServiceModule::push_changes(Weight::max_value());
+        ServiceModule::send_snapshots(Weight::max_value());
+        assert_eq!(chunks(), vec![(0, 0), (0, 1)]);
+    });
+}
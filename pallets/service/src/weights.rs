@@ -0,0 +1,393 @@
+//! Placeholder weights for pallet_robonomics_service
+//!
+//! The values are estimated by hand and were not produced by the benchmarking CLI,
+//! the file must be regenerated on reference hardware before production use with:
+//! ./target/release/parachain-collator-service benchmark --chain dev --execution wasm --wasm-execution compiled
+//!     --pallet pallet_service --extrinsic '*' --steps 50 --repeat 20
+//!     --output pallets/service/src/weights.rs
+
+#![allow(unused_parens)]
+#![allow(unused_imports)]
+
+use frame_support::{
+    traits::Get,
+    weights::{constants::RocksDbWeight, Weight},
+};
+use sp_std::marker::PhantomData;
+
+/// Weight functions needed for pallet_service.
+pub trait WeightInfo {
+    fn order() -> Weight;
+    fn cancel() -> Weight;
+    fn accept() -> Weight;
+    fn reject() -> Weight;
+    fn done() -> Weight;
+    fn amend_order() -> Weight;
+    fn accept_amendment() -> Weight;
+    fn report() -> Weight;
+    fn register() -> Weight;
+    fn set_policy(n: u32) -> Weight;
+    fn set_state() -> Weight;
+    fn on_new_order() -> Weight;
+    fn on_cancel() -> Weight;
+    fn on_amend() -> Weight;
+    fn on_bad_message() -> Weight;
+    fn set_pause() -> Weight;
+    fn on_paused_order() -> Weight;
+    fn on_queue_message() -> Weight;
+    fn handshake() -> Weight;
+    fn on_handshake() -> Weight;
+    fn prune_archived() -> Weight;
+    fn retry_message() -> Weight;
+    fn on_ack() -> Weight;
+    fn resend_message() -> Weight;
+    fn on_duplicate() -> Weight;
+    fn on_reconcile(n: u32) -> Weight;
+    fn set_subscriber() -> Weight;
+    fn on_subscribe() -> Weight;
+    fn on_unsubscribe() -> Weight;
+    fn on_settlement_changed() -> Weight;
+    fn push_change(s: u32) -> Weight;
+    fn approve_subscriber() -> Weight;
+    fn send_snapshot(n: u32) -> Weight;
+    fn set_fee_reserve() -> Weight;
+}
+
+/// Placeholder weights for pallet_service, not benchmarked.
+pub struct SubstrateWeight<T>(PhantomData<T>);
+impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
+    fn order() -> Weight {
+        (78_412_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(6 as Weight))
+            .saturating_add(T::DbWeight::get().writes(5 as Weight))
+    }
+    fn cancel() -> Weight {
+        (71_903_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(6 as Weight))
+            .saturating_add(T::DbWeight::get().writes(6 as Weight))
+    }
+    fn accept() -> Weight {
+        (34_157_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn reject() -> Weight {
+        (63_280_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(5 as Weight))
+            .saturating_add(T::DbWeight::get().writes(6 as Weight))
+    }
+    fn done() -> Weight {
+        (96_035_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(6 as Weight))
+            .saturating_add(T::DbWeight::get().writes(6 as Weight))
+    }
+    fn amend_order() -> Weight {
+        (38_472_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn accept_amendment() -> Weight {
+        (29_905_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn report() -> Weight {
+        (42_761_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(5 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn register() -> Weight {
+        (21_348_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn set_policy(n: u32) -> Weight {
+        (22_137_000 as Weight)
+            .saturating_add((121_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn set_state() -> Weight {
+        (19_826_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_new_order() -> Weight {
+        (52_644_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(6 as Weight))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+    }
+    fn on_cancel() -> Weight {
+        (41_586_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().writes(8 as Weight))
+    }
+    fn on_amend() -> Weight {
+        (27_318_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_bad_message() -> Weight {
+        (4_917_000 as Weight)
+    }
+    fn set_pause() -> Weight {
+        (14_260_000 as Weight).saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_paused_order() -> Weight {
+        (31_772_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_queue_message() -> Weight {
+        (12_945_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+    }
+    fn handshake() -> Weight {
+        (19_402_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_handshake() -> Weight {
+        (23_118_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+    }
+    fn prune_archived() -> Weight {
+        (18_204_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(4 as Weight))
+    }
+    fn retry_message() -> Weight {
+        (31_806_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(2 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_ack() -> Weight {
+        (19_442_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn resend_message() -> Weight {
+        (33_057_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_duplicate() -> Weight {
+        (21_730_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(3 as Weight))
+            .saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_reconcile(n: u32) -> Weight {
+        (19_245_000 as Weight)
+            .saturating_add((4_318_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(T::DbWeight::get().reads((1 as Weight).saturating_mul(n as Weight)))
+            .saturating_add(T::DbWeight::get().writes((1 as Weight).saturating_mul(n as Weight)))
+    }
+    fn set_subscriber() -> Weight {
+        (14_872_000 as Weight).saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn on_subscribe() -> Weight {
+        (24_806_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().writes(3 as Weight))
+    }
+    fn on_unsubscribe() -> Weight {
+        (16_208_000 as Weight).saturating_add(T::DbWeight::get().writes(2 as Weight))
+    }
+    fn on_settlement_changed() -> Weight {
+        (15_934_000 as Weight).saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+    fn push_change(s: u32) -> Weight {
+        (9_516_000 as Weight)
+            .saturating_add((31_842_000 as Weight).saturating_mul(s as Weight))
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().reads((5 as Weight).saturating_mul(s as Weight)))
+            .saturating_add(T::DbWeight::get().writes(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes((3 as Weight).saturating_mul(s as Weight)))
+    }
+    fn approve_subscriber() -> Weight {
+        (13_517_000 as Weight)
+            .saturating_add(T::DbWeight::get().reads(1 as Weight))
+            .saturating_add(T::DbWeight::get().writes(4 as Weight))
+    }
+    fn send_snapshot(n: u32) -> Weight {
+        (21_094_000 as Weight)
+            .saturating_add((3_216_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(T::DbWeight::get().reads(4 as Weight))
+            .saturating_add(T::DbWeight::get().reads((3 as Weight).saturating_mul(n as Weight)))
+            .saturating_add(T::DbWeight::get().writes(4 as Weight))
+    }
+    fn set_fee_reserve() -> Weight {
+        (11_204_000 as Weight).saturating_add(T::DbWeight::get().writes(1 as Weight))
+    }
+}
+
+// For backwards compatibility and tests
+impl WeightInfo for () {
+    fn order() -> Weight {
+        (78_412_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(6 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(5 as Weight))
+    }
+    fn cancel() -> Weight {
+        (71_903_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(6 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(6 as Weight))
+    }
+    fn accept() -> Weight {
+        (34_157_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn reject() -> Weight {
+        (63_280_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(5 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(6 as Weight))
+    }
+    fn done() -> Weight {
+        (96_035_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(6 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(6 as Weight))
+    }
+    fn amend_order() -> Weight {
+        (38_472_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn accept_amendment() -> Weight {
+        (29_905_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn report() -> Weight {
+        (42_761_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(5 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn register() -> Weight {
+        (21_348_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn set_policy(n: u32) -> Weight {
+        (22_137_000 as Weight)
+            .saturating_add((121_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn set_state() -> Weight {
+        (19_826_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_new_order() -> Weight {
+        (52_644_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(6 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+    }
+    fn on_cancel() -> Weight {
+        (41_586_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(8 as Weight))
+    }
+    fn on_amend() -> Weight {
+        (27_318_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_bad_message() -> Weight {
+        (4_917_000 as Weight)
+    }
+    fn set_pause() -> Weight {
+        (14_260_000 as Weight).saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_paused_order() -> Weight {
+        (31_772_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_queue_message() -> Weight {
+        (12_945_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+    }
+    fn handshake() -> Weight {
+        (19_402_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_handshake() -> Weight {
+        (23_118_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+    }
+    fn prune_archived() -> Weight {
+        (18_204_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(4 as Weight))
+    }
+    fn retry_message() -> Weight {
+        (31_806_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(2 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_ack() -> Weight {
+        (19_442_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn resend_message() -> Weight {
+        (33_057_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_duplicate() -> Weight {
+        (21_730_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(3 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_reconcile(n: u32) -> Weight {
+        (19_245_000 as Weight)
+            .saturating_add((4_318_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(RocksDbWeight::get().reads((1 as Weight).saturating_mul(n as Weight)))
+            .saturating_add(RocksDbWeight::get().writes((1 as Weight).saturating_mul(n as Weight)))
+    }
+    fn set_subscriber() -> Weight {
+        (14_872_000 as Weight).saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn on_subscribe() -> Weight {
+        (24_806_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(3 as Weight))
+    }
+    fn on_unsubscribe() -> Weight {
+        (16_208_000 as Weight).saturating_add(RocksDbWeight::get().writes(2 as Weight))
+    }
+    fn on_settlement_changed() -> Weight {
+        (15_934_000 as Weight).saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+    fn push_change(s: u32) -> Weight {
+        (9_516_000 as Weight)
+            .saturating_add((31_842_000 as Weight).saturating_mul(s as Weight))
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().reads((5 as Weight).saturating_mul(s as Weight)))
+            .saturating_add(RocksDbWeight::get().writes(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes((3 as Weight).saturating_mul(s as Weight)))
+    }
+    fn approve_subscriber() -> Weight {
+        (13_517_000 as Weight)
+            .saturating_add(RocksDbWeight::get().reads(1 as Weight))
+            .saturating_add(RocksDbWeight::get().writes(4 as Weight))
+    }
+    fn send_snapshot(n: u32) -> Weight {
+        (21_094_000 as Weight)
+            .saturating_add((3_216_000 as Weight).saturating_mul(n as Weight))
+            .saturating_add(RocksDbWeight::get().reads(4 as Weight))
+            .saturating_add(RocksDbWeight::get().reads((3 as Weight).saturating_mul(n as Weight)))
+            .saturating_add(RocksDbWeight::get().writes(4 as Weight))
+    }
+    fn set_fee_reserve() -> Weight {
+        (11_204_000 as Weight).saturating_add(RocksDbWeight::get().writes(1 as Weight))
+    }
+}
@@ -13,7 +13,7 @@ use sp_runtime::traits::{AccountIdLookup, BlakeTwo256, Block as BlockT, Identify
 use sp_runtime::{
     create_runtime_str, generic, impl_opaque_keys,
     transaction_validity::{TransactionSource, TransactionValidity},
-    ApplyExtrinsicResult, MultiSignature,
+    ApplyExtrinsicResult, ModuleId, MultiSignature,
 };
 
 use sp_std::prelude::*;
@@ -22,19 +22,22 @@ use sp_version::NativeVersion;
 use sp_version::RuntimeVersion;
 
 // XCM imports
+use frame_support::traits::Get;
 use frame_system::{
     limits::{BlockLength, BlockWeights},
     EnsureRoot,
 };
+#[cfg(feature = "service")]
+use polkadot_parachain::primitives::Id as ParaId;
 use polkadot_parachain::primitives::Sibling;
-use xcm::v0::{Junction, MultiLocation, NetworkId};
+use xcm::v0::{Junction, MultiAsset, MultiLocation, NetworkId};
 use xcm_builder::{
     AccountId32Aliases, CurrencyAdapter, LocationInverter, ParentIsDefault, RelayChainAsNative,
     SiblingParachainAsNative, SiblingParachainConvertsVia, SignedAccountId32AsNative,
     SovereignSignedViaLocation,
 };
 use xcm_executor::{
-    traits::{IsConcrete, NativeAsset},
+    traits::{FilterAssetLocation, MatchesFungible},
     Config, XcmExecutor,
 };
 
@@ -82,6 +85,12 @@ pub type AccountIndex = u32;
 /// Balance of an account.
 pub type Balance = u128;
 
+/// A timestamp: milliseconds since the unix epoch.
+pub type Moment = u64;
+
+/// Order payload of the robonomics pallets.
+pub type OrderPayload = u64;
+
 /// Index of a transaction in the chain.
 pub type Index = u32;
 
@@ -240,7 +249,7 @@ parameter_types! {
 
 impl pallet_timestamp::Config for Runtime {
     /// A timestamp: milliseconds since the unix epoch.
-    type Moment = u64;
+    type Moment = Moment;
     type OnTimestampSet = ();
     type MinimumPeriod = MinimumPeriod;
     type WeightInfo = ();
@@ -282,7 +291,7 @@ impl pallet_sudo::Config for Runtime {
 #[cfg(feature = "client")]
 impl cumulus_pallet_parachain_system::Config for Runtime {
     type Event = Event;
-    type OnValidationData = ();
+    type OnValidationData = ClientModule;
     type SelfParaId = parachain_info::Module<Runtime>;
     type DownwardMessageHandlers = ();
     type XcmpMessageHandlers = ClientModule;
@@ -291,7 +300,7 @@ impl cumulus_pallet_parachain_system::Config for Runtime {
 #[cfg(feature = "service")]
 impl cumulus_pallet_parachain_system::Config for Runtime {
     type Event = Event;
-    type OnValidationData = ();
+    type OnValidationData = ServiceModule;
     type SelfParaId = parachain_info::Module<Runtime>;
     type DownwardMessageHandlers = ();
     type XcmpMessageHandlers = ServiceModule;
@@ -306,6 +315,10 @@ parameter_types! {
     pub Ancestry: MultiLocation = Junction::Parachain {
         id: ParachainInfo::parachain_id().into()
     }.into();
+    pub FeeAssetLocation: MultiLocation = MultiLocation::X2(
+        Junction::Parent,
+        Junction::Parachain { id: ParachainInfo::parachain_id().into() },
+    );
 }
 
 type LocationConverter = (
@@ -314,17 +327,51 @@ type LocationConverter = (
     AccountId32Aliases<RococoNetwork, AccountId>,
 );
 
-type LocalAssetTransactor = CurrencyAdapter<
+/// Native balance is backed by the relay currency only, the relay chain is its reserve
+pub struct FeeAsset;
+impl MatchesFungible<Balance> for FeeAsset {
+    fn matches_fungible(a: &MultiAsset) -> Option<Balance> {
+        match a {
+            MultiAsset::ConcreteFungible { id, amount } if *id == RococoLocation::get() => {
+                Some(*amount)
+            }
+            _ => None,
+        }
+    }
+}
+impl FilterAssetLocation for FeeAsset {
+    fn filter_asset_location(asset: &MultiAsset, origin: &MultiLocation) -> bool {
+        matches!(asset, MultiAsset::ConcreteFungible { id, .. } if id == origin && *id == RococoLocation::get())
+    }
+}
+
+type NativeAssetTransactor = CurrencyAdapter<
     // Use this currency:
     Balances,
     // Use this currency when it is a fungible asset matching the given location or name:
-    IsConcrete<RococoLocation>,
+    FeeAsset,
     // Do a simple punn to convert an AccountId32 MultiLocation into a native chain account ID:
     LocationConverter,
     // Our chain's account ID type (we can't get away without mentioning it explicitly):
     AccountId,
 >;
 
+/// Currencies of the client parachains accepted by governance, kept apart from native balance
+#[cfg(feature = "service")]
+type LocalAssetTransactor = (
+    NativeAssetTransactor,
+    pallet_service::ReserveAssets<Runtime, LocationConverter>,
+);
+#[cfg(feature = "service")]
+type Reserves = (
+    FeeAsset,
+    pallet_service::ReserveAssets<Runtime, LocationConverter>,
+);
+#[cfg(feature = "client")]
+type LocalAssetTransactor = NativeAssetTransactor;
+#[cfg(feature = "client")]
+type Reserves = FeeAsset;
+
 type LocalOriginConverter = (
     SovereignSignedViaLocation<LocationConverter, Origin>,
     RelayChainAsNative<RelayChainOrigin, Origin>,
@@ -339,7 +386,7 @@ impl Config for XcmConfig {
     // How to withdraw and deposit an asset.
     type AssetTransactor = LocalAssetTransactor;
     type OriginConverter = LocalOriginConverter;
-    type IsReserve = NativeAsset;
+    type IsReserve = Reserves;
     type IsTeleporter = ();
     type LocationInverter = LocationInverter<Ancestry>;
 }
@@ -353,6 +400,34 @@ impl cumulus_pallet_xcm_handler::Config for Runtime {
     type AccountIdConverter = LocationConverter;
 }
 
+parameter_types! {
+    pub const RelayBlockDuration: u64 = 6000;
+    /// Closed orders are kept for a week
+    pub const ArchiveRetention: BlockNumber = 7 * DAYS;
+    pub const MaxPolicyEntries: u32 = 64;
+    pub const MaxPausedMessages: u32 = 256;
+    pub const SnapshotChunk: u32 = 64;
+    pub const MaxSubscribers: u32 = 16;
+    pub const MaxRegistryChanges: u32 = 256;
+    /// Subscribers get a fresh registry snapshot at most every ten minutes
+    pub const ResubscribeInterval: BlockNumber = 10 * MINUTES;
+    pub const MaxSendAttempts: u32 = 8;
+    pub const MaxOutboxMessages: u32 = 64;
+    pub const RetryBackoff: BlockNumber = 10;
+    pub const AckTimeout: BlockNumber = 20;
+    pub const MaxUnacked: u32 = 64;
+    /// Weight of every instruction of an inbound XCM
+    pub const XcmInstructionWeight: Weight = 1_000_000;
+    /// Inbound XCM may use a tenth of the block
+    pub const MaxXcmWeight: Weight = MAXIMUM_BLOCK_WEIGHT / 10;
+    /// Netted fees are settled daily
+    pub const SettlementEpoch: BlockNumber = DAYS;
+    pub const SettlementBatch: u32 = 64;
+    pub const NettingPotId: ModuleId = ModuleId(*b"rbx/nett");
+    /// Unanswered withdrawals may be settled locally after an hour
+    pub const CancelTimeout: Moment = 60 * 60 * 1000;
+}
+
 // Create the runtime by composing the FRAME pallets that were previously configured.
 #[cfg(feature = "service")]
 pub struct AcceptOnReceive;
@@ -367,25 +442,108 @@ impl<T: pallet_service::Config> pallet_service::OnReceived<T> for AcceptOnReceiv
     }
 }
 
+/// Origin of calls dispatched by sibling parachains through XCM `Transact`
+#[cfg(feature = "service")]
+pub struct EnsureSiblingPara;
+#[cfg(feature = "service")]
+impl frame_support::traits::EnsureOrigin<Origin> for EnsureSiblingPara {
+    type Success = ParaId;
+    fn try_origin(o: Origin) -> Result<ParaId, Origin> {
+        o.into().and_then(|o| match o {
+            cumulus_pallet_xcm_handler::Origin::SiblingParachain(id) => Ok(id),
+            r => Err(Origin::from(r)),
+        })
+    }
+    #[cfg(feature = "runtime-benchmarks")]
+    fn successful_origin() -> Origin {
+        Origin::from(cumulus_pallet_xcm_handler::Origin::SiblingParachain(
+            ParaId::from(0),
+        ))
+    }
+}
+
+/// Sovereign account of a sibling parachain
+#[cfg(feature = "service")]
+pub struct SiblingAccount;
+#[cfg(feature = "service")]
+impl sp_runtime::traits::Convert<ParaId, AccountId> for SiblingAccount {
+    fn convert(id: ParaId) -> AccountId {
+        use sp_runtime::traits::AccountIdConversion;
+        Sibling::from(id).into_account()
+    }
+}
+
 /// Configure the pallet service
 #[cfg(feature = "service")]
 impl pallet_service::Config for Runtime {
     type Event = Event;
     //type XcmSender = XcmHandler;
     type XcmpMessageSender = ParachainSystem;
-    type OrderPayload = u64;
+    type XcmExecutor = XcmExecutor<XcmConfig>;
+    type XcmWeigher = pallet_service::FixedXcmWeight<XcmInstructionWeight, Call>;
+    type MaxXcmWeight = MaxXcmWeight;
+    type OrderPayload = OrderPayload;
     type Currency = Balances;
     type SelfParaId = parachain_info::Module<Runtime>;
     type OnReceived = AcceptOnReceive;
+    type RelayBlockDuration = RelayBlockDuration;
+    type ArchiveRetention = ArchiveRetention;
+    type MaxPolicyEntries = MaxPolicyEntries;
+    type SiblingOrigin = EnsureSiblingPara;
+    type SiblingAccount = SiblingAccount;
+    type PauseOrigin = EnsureRoot<AccountId>;
+    type MaxPausedMessages = MaxPausedMessages;
+    type SnapshotChunk = SnapshotChunk;
+    type MaxSubscribers = MaxSubscribers;
+    type MaxRegistryChanges = MaxRegistryChanges;
+    type ResubscribeInterval = ResubscribeInterval;
+    type MaxSendAttempts = MaxSendAttempts;
+    type MaxOutboxMessages = MaxOutboxMessages;
+    type RetryBackoff = RetryBackoff;
+    type AckTimeout = AckTimeout;
+    type MaxUnacked = MaxUnacked;
+    type WeightInfo = pallet_service::weights::SubstrateWeight<Runtime>;
+}
+
+/// Order payloads are integers of a fixed size
+#[cfg(all(feature = "client", feature = "runtime-benchmarks"))]
+pub struct PayloadOfSize;
+#[cfg(all(feature = "client", feature = "runtime-benchmarks"))]
+impl sp_runtime::traits::Convert<u32, OrderPayload> for PayloadOfSize {
+    fn convert(_: u32) -> OrderPayload {
+        OrderPayload::max_value()
+    }
 }
 
 #[cfg(feature = "client")]
 impl pallet_client::Config for Runtime {
     type Event = Event;
-    //type XcmSender = XcmHandler;
+    type XcmSender = XcmHandler;
     type XcmpMessageSender = ParachainSystem;
-    type OrderPayload = u64;
+    type XcmExecutor = XcmExecutor<XcmConfig>;
+    type XcmWeigher = pallet_service::FixedXcmWeight<XcmInstructionWeight, Call>;
+    type MaxXcmWeight = MaxXcmWeight;
+    type AccountIdConverter = LocationConverter;
+    type FeeAssetLocation = FeeAssetLocation;
+    type SettlementEpoch = SettlementEpoch;
+    type SettlementBatch = SettlementBatch;
+    type PotId = NettingPotId;
+    type OrderPayload = OrderPayload;
+    #[cfg(feature = "runtime-benchmarks")]
+    type PayloadOfSize = PayloadOfSize;
     type Currency = Balances;
+    type SelfParaId = parachain_info::Module<Runtime>;
+    type RelayBlockDuration = RelayBlockDuration;
+    type MaxPolicyEntries = MaxPolicyEntries;
+    type PauseOrigin = EnsureRoot<AccountId>;
+    type MaxPausedMessages = MaxPausedMessages;
+    type MaxSendAttempts = MaxSendAttempts;
+    type MaxOutboxMessages = MaxOutboxMessages;
+    type RetryBackoff = RetryBackoff;
+    type AckTimeout = AckTimeout;
+    type MaxUnacked = MaxUnacked;
+    type CancelTimeout = CancelTimeout;
+    type WeightInfo = pallet_client::weights::SubstrateWeight<Runtime>;
 }
 
 #[cfg(feature = "service")]
@@ -404,7 +562,7 @@ construct_runtime!(
         TransactionPayment: pallet_transaction_payment::{Pallet, Storage},
         ParachainInfo: parachain_info::{Pallet, Storage, Config},
         XcmHandler: cumulus_pallet_xcm_handler::{Pallet, Event<T>, Origin},
-        ServiceModule: pallet_service::{Pallet, Call, Storage, Event<T>},
+        ServiceModule: pallet_service::{Pallet, Call, Storage, Config<T>, Event<T>},
     }
 );
 
@@ -424,7 +582,7 @@ construct_runtime!(
         TransactionPayment: pallet_transaction_payment::{Pallet, Storage},
         ParachainInfo: parachain_info::{Pallet, Storage, Config},
         XcmHandler: cumulus_pallet_xcm_handler::{Pallet, Event<T>, Origin},
-        ClientModule: pallet_client::{Pallet, Call, Storage, Event<T>},
+        ClientModule: pallet_client::{Pallet, Call, Storage, Config<T>, Event<T>},
     }
 );
 
@@ -461,6 +619,12 @@ pub type Executive = frame_executive::Executive<
     AllPallets,
 >;
 
+/// Robonomics pallet of the current build, backs `RobobankApi`
+#[cfg(feature = "service")]
+type Robobank = ServiceModule;
+#[cfg(feature = "client")]
+type Robobank = ClientModule;
+
 impl_runtime_apis! {
     impl sp_api::Core<Block> for Runtime {
         fn version() -> RuntimeVersion {
@@ -555,6 +719,36 @@ impl_runtime_apis! {
         }
     }
 
+    impl robobank_runtime_api::RobobankApi<Block, AccountId, Balance, Moment, OrderPayload> for Runtime {
+        fn device(device: AccountId) -> Option<robobank_runtime_api::DeviceInfo<Balance, Moment>> {
+            Robobank::device_info(&device)
+        }
+
+        fn order(
+            device: AccountId,
+        ) -> Option<robobank_runtime_api::OrderInfo<OrderPayload, Balance, Moment, AccountId>> {
+            Robobank::order_info(&device)
+        }
+
+        fn ready_devices() -> Vec<AccountId> {
+            Robobank::ready_devices()
+        }
+
+        fn quote_order(
+            order: robobank_runtime_api::OrderRequest<OrderPayload, Balance, Moment, AccountId>,
+        ) -> Option<robobank_runtime_api::OrderQuote<Balance, Moment>> {
+            #[cfg(feature = "service")]
+            let weight =
+                <<Runtime as pallet_service::Config>::WeightInfo as pallet_service::WeightInfo>::order();
+            #[cfg(feature = "client")]
+            let weight =
+                <<Runtime as pallet_client::Config>::WeightInfo as pallet_client::WeightInfo>::order(
+                    codec::Encode::encoded_size(&order.data) as u32,
+                );
+            Robobank::quote_order(&order, TransactionPayment::weight_to_fee(weight))
+        }
+    }
+
     #[cfg(feature = "runtime-benchmarks")]
     impl frame_benchmarking::Benchmark<Block> for Runtime {
         fn dispatch_benchmark(
@@ -584,6 +778,10 @@ impl_runtime_apis! {
             add_benchmark!(params, batches, frame_system, SystemBench::<Runtime>);
             add_benchmark!(params, batches, pallet_balances, Balances);
             add_benchmark!(params, batches, pallet_timestamp, Timestamp);
+            #[cfg(feature = "service")]
+            add_benchmark!(params, batches, pallet_service, ServiceModule);
+            #[cfg(feature = "client")]
+            add_benchmark!(params, batches, pallet_client, ClientModule);
 
             if batches.is_empty() { return Err("Benchmark not found for this pallet.".into()) }
             Ok(batches)
@@ -10,7 +10,7 @@ root = Keypair.create_from_uri('//Alice')
 bob = Keypair.create_from_uri('//Bob')
 
 charlie = Keypair.create_from_uri('//Charlie')
-dev = Keypair.create_from_uri('//Device//1')
+dev = Keypair.create_from_uri('//Dave')
 
 # Parachain addresses
 
@@ -46,7 +46,9 @@ CUSTOM_TYPES = {
     "OrderOf": {
         "type": "struct",
         "type_mapping": [
+            ["id", "u64"],
             ["until", "Moment"],
+            ["until_relay", "Option<u32>"],
             ["args", "u64"],
             ["fee", "Balance"],
             ["client", "AccountId"],
@@ -57,10 +59,20 @@ CUSTOM_TYPES = {
         "type": "struct",
         "type_mapping": [
             ["until", "Moment"],
+            ["until_relay", "Option<u32>"],
             ["args", "u64"],
             ["fee", "Balance"],
             ["device", "AccountId"],
         ]
+    },
+    "Settlement": {
+        "type": "struct",
+        "type_mapping": [
+            ["paid", "Balance"],
+            ["refunded", "Balance"],
+            ["penalty", "Balance"],
+            ["slashed", "bool"],
+        ]
     }
 }
 
@@ -227,7 +239,8 @@ def done(app):
         call_module='ServiceModule',
         call_function='done',
         call_params={
-            'onoff': True
+            'onoff': True,
+            'result': None
         }
     )
     extrinsic = app.create_signed_extrinsic(call=call, keypair=dev )
@@ -245,6 +258,27 @@ def reject(app):
     extrinsic = app.create_signed_extrinsic(call=call, keypair=dev )
     receipt = app.submit_extrinsic(extrinsic, wait_for_inclusion=True)
 
+def handshake(app, account, paraid):
+    '''
+    Negotiate message protocol version with the parachain, account holds the sudo key
+    '''
+    payload = app.compose_call(
+        call_module='ClientModule',
+        call_function='handshake',
+        call_params={
+            'paraid': paraid
+        }
+    )
+    call = app.compose_call(
+        call_module='Sudo',
+        call_function='sudo',
+        call_params={
+            'call': payload.value,
+        }
+    )
+    extrinsic = app.create_signed_extrinsic(call=call, keypair=account )
+    receipt = app.submit_extrinsic(extrinsic, wait_for_inclusion=True)
+
 def order(app, account, amount):
     '''
     Order
@@ -262,6 +296,7 @@ def order(app, account, amount):
         call_params={
             'order': {
                 'until': (now.value + 10000000) ,
+                'until_relay': None,
                 'args': 0,
                 'fee': 200_000_000_000,
                 'device': dev.ss58_address,
@@ -305,11 +340,32 @@ def config_device_srv(app, amount):
     extrinsic = app.create_signed_extrinsic(call=call, keypair=dev )
     receipt = app.submit_extrinsic(extrinsic, wait_for_inclusion=True)
 
+    '''
+    Allow parachain 100 to subscribe to the device registry
+    '''
+    payload = app.compose_call(
+        call_module='ServiceModule',
+        call_function='approve_subscriber',
+        call_params={
+            'paraid': 100,
+            'approved': True
+        }
+    )
+    call = app.compose_call(
+        call_module='Sudo',
+        call_function='sudo',
+        call_params={
+            'call': payload.value,
+        }
+    )
+    extrinsic = app.create_signed_extrinsic(call=call, keypair=root )
+    receipt = app.submit_extrinsic(extrinsic, wait_for_inclusion=True)
+
 
 def config_device(app, amount):
     '''
     Endow device account,
-    Register devices in client and service side
+    Mirror the device on the client side
     '''
     call = app.compose_call(
         call_module='Balances',
@@ -323,18 +379,35 @@ def config_device(app, amount):
     receipt = app.submit_extrinsic(extrinsic, wait_for_inclusion=True)
     print("transfer")
 
-    call = app.compose_call(
+    '''
+    The device profile is mirrored from the service side, bind the device
+    to parachain 200 and subscribe to its device registry
+    '''
+    bind = app.compose_call(
         call_module='ClientModule',
-        call_function='register',
+        call_function='bind_device',
         call_params={
-            'paraid': 200,
-            'penalty': 1000_000_000,
-            'wcd': 3600000,
-            'onoff': True
+            'device': dev.ss58_address,
+            'paraid': 200
         }
     )
-    extrinsic = app.create_signed_extrinsic(call=call, keypair=dev )
-    receipt = app.submit_extrinsic(extrinsic, wait_for_inclusion=True)
+    subscribe = app.compose_call(
+        call_module='ClientModule',
+        call_function='subscribe',
+        call_params={
+            'paraid': 200
+        }
+    )
+    for payload in [bind, subscribe]:
+        call = app.compose_call(
+            call_module='Sudo',
+            call_function='sudo',
+            call_params={
+                'call': payload.value,
+            }
+        )
+        extrinsic = app.create_signed_extrinsic(call=call, keypair=root )
+        receipt = app.submit_extrinsic(extrinsic, wait_for_inclusion=True)
 
     print( f"configured device '{dev}' with address {dev.ss58_address} " )
 
@@ -383,7 +456,7 @@ if __name__=="__main__":
     parser.add_argument('--amount', help='tokens amount', type=int, default=10_000_000_000_000)
     parser.add_argument('--paraid', help='parachain id', nargs='*', type=int, default=[100] )
     parser.add_argument('--account', help='account uri (i.e  //Bob)', type=str, default='//Bob' )
-    parser.add_argument('--dev', help='device name', type=str, default='//Device//1' )
+    parser.add_argument('--dev', help='device name', type=str, default='//Dave' )
     parser.add_argument('--wasm', help='wasm file')
     parser.add_argument('--genesis', help='genesis file')
 
@@ -446,16 +519,19 @@ if __name__=="__main__":
         show_call(substrate, args.amount )
 
     elif cmd=="configure":
-        config_device(substrate, args.amount )
+        # the service side approves the subscription before the client side subscribes
         if len(args.ws_url)>1:
-            url = args.ws_url[1]
-            substrate = SubstrateInterface(
-               url=url,
+            service = SubstrateInterface(
+               url=args.ws_url[1],
                ss58_format=42,
                type_registry_preset='rococo',
                type_registry={'types': CUSTOM_TYPES }
             )
-            config_device_srv(substrate, args.amount )
+            config_device_srv(service, args.amount )
+        config_device(substrate, args.amount )
+
+    elif cmd=="handshake":
+        handshake(substrate, Keypair.create_from_uri(args.account), args.paraid[0] )
 
     elif cmd=="order":
         order(substrate, Keypair.create_from_uri(args.account),  args.amount )